@@ -1,6 +1,7 @@
 use async_std::{prelude::*, stream::Stream, sync::Arc};
 use std::pin::Pin;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[cfg(target_os = "macos")]
@@ -16,6 +17,8 @@ pub enum MidiIoError {
     SourceNotFound,
     #[error("Couldn't find MIDI destination with this name")]
     DestinationNotFound,
+    #[error("Couldn't send to MIDI destination")]
+    SendFailed,
 }
 
 #[cfg(target_os = "macos")]
@@ -40,14 +43,20 @@ pub struct MidiInput {
     input_receiver: async_std::sync::Receiver<MidiEvent>,
 }
 impl MidiInput {
-    #[cfg(target_os = "macos")]
     pub fn new(name: &str) -> Result<MidiInput, MidiIoError> {
+        MidiInput::nth(name, 0)
+    }
+    /// Opens the `index`th source called `name`, counting from 0 in the order the system lists
+    /// them, for when several of the same device are plugged in
+    #[cfg(target_os = "macos")]
+    pub fn nth(name: &str, index: usize) -> Result<MidiInput, MidiIoError> {
         let client = coremidi::Client::new(&format!("roller-input-{}", name))
             .map_err(|_| MidiIoError::InitFailed)?;
 
         let source = coremidi::Sources
             .into_iter()
-            .find(|source| source.display_name().as_deref() == Some(name))
+            .filter(|source| source.display_name().as_deref() == Some(name))
+            .nth(index)
             .ok_or(MidiIoError::SourceNotFound)?;
 
         let (input_sender, input_receiver) = async_std::sync::channel::<MidiEvent>(1024);
@@ -86,7 +95,7 @@ impl MidiInput {
         })
     }
     #[cfg(not(target_os = "macos"))]
-    pub fn new(name: &str) -> Result<MidiInput, MidiIoError> {
+    pub fn nth(_name: &str, _index: usize) -> Result<MidiInput, MidiIoError> {
         Err(MidiIoError::InitFailed)
     }
 }
@@ -112,14 +121,36 @@ struct MidiOutputState {}
 
 unsafe impl Send for MidiOutput {}
 unsafe impl Sync for MidiOutput {}
+// Timed sends sleep until shortly before they're due, then spin, as sleeps can overshoot by a
+// millisecond or more
+#[cfg(target_os = "macos")]
+const TIMED_SEND_SPIN: Duration = Duration::from_millis(2);
+
+#[cfg(target_os = "macos")]
+fn wait_until(at: Instant) {
+    let now = Instant::now();
+    if at > now + TIMED_SEND_SPIN {
+        std::thread::sleep(at - now - TIMED_SEND_SPIN);
+    }
+    while Instant::now() < at {
+        std::hint::spin_loop();
+    }
+}
+
 #[derive(Debug)]
 pub struct MidiOutput {
     state: MidiOutputState,
     output_sender: async_std::sync::Sender<Vec<u8>>,
+    timed_output_sender: async_std::sync::Sender<(Instant, Vec<u8>)>,
+    timed_send_failed: Arc<AtomicBool>,
 }
 impl MidiOutput {
-    #[cfg(target_os = "macos")]
     pub fn new(name: &str) -> Result<MidiOutput, MidiIoError> {
+        MidiOutput::nth(name, 0)
+    }
+    /// Opens the `index`th destination called `name`, like `MidiInput::nth`
+    #[cfg(target_os = "macos")]
+    pub fn nth(name: &str, index: usize) -> Result<MidiOutput, MidiIoError> {
         let client = coremidi::Client::new(&format!("roller-output-{}", name))
             .map_err(|_| MidiIoError::InitFailed)?;
 
@@ -127,20 +158,51 @@ impl MidiOutput {
 
         let destination = coremidi::Destinations
             .into_iter()
-            .find(|dest| dest.display_name().as_deref() == Some(name))
+            .filter(|dest| dest.display_name().as_deref() == Some(name))
+            .nth(index)
             .ok_or(MidiIoError::DestinationNotFound)?;
 
         let midi_output_port = client
             .output_port(&format!("roller-output-{}", name))
             .map_err(|_| MidiIoError::InitFailed)?;
 
+        // Timed packets get a port and thread of their own, so they aren't held up behind other
+        // messages or by the scheduling of async tasks
+        let (timed_output_sender, timed_output_receiver) =
+            async_std::sync::channel::<(Instant, Vec<u8>)>(64);
+        let timed_destination = coremidi::Destinations
+            .into_iter()
+            .filter(|dest| dest.display_name().as_deref() == Some(name))
+            .nth(index)
+            .ok_or(MidiIoError::DestinationNotFound)?;
+        let timed_output_port = client
+            .output_port(&format!("roller-timed-output-{}", name))
+            .map_err(|_| MidiIoError::InitFailed)?;
+
+        // Failures are flagged for the next timed send to report, as the destination has likely
+        // gone away. The thread keeps draining packets so senders don't block
+        let timed_send_failed = Arc::new(AtomicBool::new(false));
+        let thread_send_failed = timed_send_failed.clone();
+        std::thread::spawn(move || {
+            while let Ok((at, packet)) = async_std::task::block_on(timed_output_receiver.recv()) {
+                wait_until(at);
+                let packets = coremidi::PacketBuffer::new(0, &packet);
+                if timed_output_port
+                    .send(&timed_destination, &packets)
+                    .is_err()
+                {
+                    thread_send_failed.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+
         async_std::task::spawn(async move {
             while let Some(packet) = output_receiver.next().await {
                 let packets = coremidi::PacketBuffer::new(0, &packet);
-                midi_output_port
-                    .send(&destination, &packets)
-                    .map_err(|_| "failed to send packets")
-                    .unwrap();
+                // The destination may have been unplugged, keep draining so senders don't block
+                if midi_output_port.send(&destination, &packets).is_err() {
+                    eprintln!("failed to send midi packets");
+                }
                 async_std::task::sleep(Duration::from_millis(1)).await;
             }
         });
@@ -148,13 +210,30 @@ impl MidiOutput {
         Ok(MidiOutput {
             state: MidiOutputState { client },
             output_sender,
+            timed_output_sender,
+            timed_send_failed,
         })
     }
     #[cfg(not(target_os = "macos"))]
-    pub fn new(name: &str) -> Result<MidiOutput, MidiIoError> {
+    pub fn nth(_name: &str, _index: usize) -> Result<MidiOutput, MidiIoError> {
         Err(MidiIoError::InitFailed)
     }
     pub async fn send_packet(&self, packet: impl Into<Vec<u8>>) {
         self.output_sender.send(packet.into()).await
     }
+    /// Sends a packet as close to `at` as possible, for realtime messages like clock pulses.
+    /// Timed packets are sent in the order they're given, and straight away if `at` has passed.
+    /// Once a timed packet fails to send, every later call fails too
+    pub async fn send_packet_at(
+        &self,
+        packet: impl Into<Vec<u8>>,
+        at: Instant,
+    ) -> Result<(), MidiIoError> {
+        if self.timed_send_failed.load(Ordering::Relaxed) {
+            return Err(MidiIoError::SendFailed);
+        }
+
+        self.timed_output_sender.send((at, packet.into())).await;
+        Ok(())
+    }
 }
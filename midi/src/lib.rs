@@ -3,7 +3,9 @@ use num_traits::FromPrimitive;
 use serde::{Deserialize, Serialize};
 
 mod io;
+mod timecode;
 pub use io::{MidiInput, MidiIoError, MidiOutput};
+pub use timecode::{FrameRate, MtcDecoder, Timecode};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Note(u8);
@@ -97,6 +99,17 @@ pub enum MidiEvent {
     NoteOff { note: Note, velocity: u8 },
     ControlChange { control: ControlChannel, value: u8 },
     TimingClock,
+    Start,
+    Continue,
+    Stop,
+    // In MIDI beats, sixteenth notes since the start of the song
+    SongPositionPointer { position: u16 },
+    // One of the eight pieces of a timecode, assembled by an `MtcDecoder`
+    MtcQuarterFrame { piece: u8, value: u8 },
+    // Sent when a timecode source locates, rather than running through
+    MtcFullFrame(Timecode),
+    // Other system exclusive messages, without their start and end bytes
+    SysEx(Vec<u8>),
 }
 impl MidiEvent {
     pub fn from_bytes(bytes: &[u8]) -> Result<Option<MidiEvent>, MidiMessageError> {
@@ -127,7 +140,77 @@ impl MidiEvent {
                 value: stream.read_u8()?,
             })),
             Status::TimingClock => Ok(Some(MidiEvent::TimingClock)),
+            Status::Start => Ok(Some(MidiEvent::Start)),
+            Status::Continue => Ok(Some(MidiEvent::Continue)),
+            Status::Stop => Ok(Some(MidiEvent::Stop)),
+            Status::SongPositionPointer => {
+                // 14 bits, least significant 7 first
+                let lsb = stream.read_u8()?;
+                let msb = stream.read_u8()?;
+
+                Ok(Some(MidiEvent::SongPositionPointer {
+                    position: (u16::from(msb & 0x7F) << 7) | u16::from(lsb & 0x7F),
+                }))
+            }
+            Status::MIDITimeCodeQtrFrame => {
+                let data = stream.read_u8()?;
+
+                Ok(Some(MidiEvent::MtcQuarterFrame {
+                    piece: (data >> 4) & 0x07,
+                    value: data & 0x0F,
+                }))
+            }
+            Status::SysExStart => {
+                let mut data = Vec::new();
+                loop {
+                    match stream.read_u8()? {
+                        byte if byte == Status::SysExEnd as u8 => break,
+                        byte => data.push(byte),
+                    }
+                }
+
+                match timecode::full_frame_from_sysex(&data) {
+                    Some(timecode) => Ok(Some(MidiEvent::MtcFullFrame(timecode))),
+                    None => Ok(Some(MidiEvent::SysEx(data))),
+                }
+            }
             _ => Err(MidiMessageError::MalformedPacket),
         }
     }
+    /// Encodes the event on channel 1
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            MidiEvent::NoteOn { note, velocity } => {
+                vec![Status::NoteOn as u8, u8::from(*note), *velocity]
+            }
+            MidiEvent::NoteOff { note, velocity } => {
+                vec![Status::NoteOff as u8, u8::from(*note), *velocity]
+            }
+            MidiEvent::ControlChange { control, value } => {
+                vec![Status::ControlChange as u8, u8::from(*control), *value]
+            }
+            MidiEvent::TimingClock => vec![Status::TimingClock as u8],
+            MidiEvent::Start => vec![Status::Start as u8],
+            MidiEvent::Continue => vec![Status::Continue as u8],
+            MidiEvent::Stop => vec![Status::Stop as u8],
+            MidiEvent::SongPositionPointer { position } => vec![
+                Status::SongPositionPointer as u8,
+                (position & 0x7F) as u8,
+                ((position >> 7) & 0x7F) as u8,
+            ],
+            MidiEvent::MtcQuarterFrame { piece, value } => vec![
+                Status::MIDITimeCodeQtrFrame as u8,
+                ((piece & 0x07) << 4) | (value & 0x0F),
+            ],
+            MidiEvent::MtcFullFrame(timecode) => sysex_bytes(&timecode::full_frame_sysex(timecode)),
+            MidiEvent::SysEx(data) => sysex_bytes(data),
+        }
+    }
+}
+
+fn sysex_bytes(data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![Status::SysExStart as u8];
+    bytes.extend_from_slice(data);
+    bytes.push(Status::SysExEnd as u8);
+    bytes
 }
@@ -0,0 +1,306 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameRate {
+    Fps24,
+    Fps25,
+    // 29.97 frames per second, skipping frame numbers 0 and 1 each minute but every tenth
+    Fps2997Drop,
+    Fps30,
+}
+impl FrameRate {
+    // As sent in the hours byte of full frames and the last quarter frame
+    fn from_code(code: u8) -> FrameRate {
+        match code & 0x03 {
+            0 => FrameRate::Fps24,
+            1 => FrameRate::Fps25,
+            2 => FrameRate::Fps2997Drop,
+            _ => FrameRate::Fps30,
+        }
+    }
+    fn code(self) -> u8 {
+        match self {
+            FrameRate::Fps24 => 0,
+            FrameRate::Fps25 => 1,
+            FrameRate::Fps2997Drop => 2,
+            FrameRate::Fps30 => 3,
+        }
+    }
+    /// Frames numbered in each second
+    pub fn frames_per_second(self) -> u32 {
+        match self {
+            FrameRate::Fps24 => 24,
+            FrameRate::Fps25 => 25,
+            FrameRate::Fps2997Drop | FrameRate::Fps30 => 30,
+        }
+    }
+    /// Frames that actually pass each second
+    pub fn fps(self) -> f64 {
+        match self {
+            FrameRate::Fps2997Drop => 30_000.0 / 1001.0,
+            rate => rate.frames_per_second() as f64,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub rate: FrameRate,
+}
+impl Timecode {
+    pub fn new(hours: u8, minutes: u8, seconds: u8, frames: u8, rate: FrameRate) -> Timecode {
+        Timecode {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            rate,
+        }
+    }
+    /// Parses `hh:mm:ss:ff`, or `hh:mm:ss;ff` as drop frame timecode is often written
+    pub fn parse(timecode: &str, rate: FrameRate) -> Option<Timecode> {
+        let parts: Vec<u8> = timecode
+            .split(&[':', ';'][..])
+            .map(|part| part.parse().ok())
+            .collect::<Option<_>>()?;
+
+        match parts.as_slice() {
+            [hours, minutes, seconds, frames]
+                if *minutes < 60
+                    && *seconds < 60
+                    && u32::from(*frames) < rate.frames_per_second() =>
+            {
+                Some(Timecode::new(*hours, *minutes, *seconds, *frames, rate))
+            }
+            _ => None,
+        }
+    }
+    /// Frames since midnight, leaving out the frame numbers drop frame timecode skips
+    pub fn frame_count(&self) -> u64 {
+        let frames_per_second = u64::from(self.rate.frames_per_second());
+        let total_minutes = u64::from(self.hours) * 60 + u64::from(self.minutes);
+        let count = ((total_minutes * 60) + u64::from(self.seconds)) * frames_per_second
+            + u64::from(self.frames);
+
+        match self.rate {
+            FrameRate::Fps2997Drop => count - 2 * (total_minutes - total_minutes / 10),
+            _ => count,
+        }
+    }
+    pub fn as_secs(&self) -> f64 {
+        self.frame_count() as f64 / self.rate.fps()
+    }
+    pub fn from_frame_count(frame_count: u64, rate: FrameRate) -> Timecode {
+        let frames_per_second = u64::from(rate.frames_per_second());
+        let frame_count = match rate {
+            // Adds back the skipped frame numbers, 17982 frames pass every ten minutes
+            FrameRate::Fps2997Drop => {
+                let (tens, rest) = (frame_count / 17982, frame_count % 17982);
+                let skipped_in_rest = if rest < 2 { 0 } else { 2 * ((rest - 2) / 1798) };
+                frame_count + 18 * tens + skipped_in_rest
+            }
+            _ => frame_count,
+        };
+
+        let frames = frame_count % frames_per_second;
+        let total_seconds = frame_count / frames_per_second;
+        Timecode::new(
+            ((total_seconds / 3600) % 24) as u8,
+            ((total_seconds / 60) % 60) as u8,
+            (total_seconds % 60) as u8,
+            frames as u8,
+            rate,
+        )
+    }
+    pub fn add_frames(&self, frames: u64) -> Timecode {
+        Timecode::from_frame_count(self.frame_count() + frames, self.rate)
+    }
+    // The hours byte of a full frame, with the frame rate in bits 5 and 6
+    fn hours_byte(&self) -> u8 {
+        (self.rate.code() << 5) | (self.hours & 0x1F)
+    }
+}
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.rate == FrameRate::Fps2997Drop {
+            ';'
+        } else {
+            ':'
+        };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours, self.minutes, self.seconds, separator, self.frames
+        )
+    }
+}
+
+pub(crate) fn full_frame_from_sysex(data: &[u8]) -> Option<Timecode> {
+    // Universal real time, any device, MTC full frame
+    match data {
+        [0x7F, _, 0x01, 0x01, hours, minutes, seconds, frames] => Some(Timecode::new(
+            hours & 0x1F,
+            *minutes,
+            *seconds,
+            *frames,
+            FrameRate::from_code(hours >> 5),
+        )),
+        _ => None,
+    }
+}
+
+pub(crate) fn full_frame_sysex(timecode: &Timecode) -> Vec<u8> {
+    vec![
+        0x7F,
+        0x7F,
+        0x01,
+        0x01,
+        timecode.hours_byte(),
+        timecode.minutes,
+        timecode.seconds,
+        timecode.frames,
+    ]
+}
+
+/// Assembles MTC quarter frames into timecode. The eight quarter frames making up a timecode
+/// are sent over two frames, so each is completed two frames after the time it holds
+#[derive(Debug, Default)]
+pub struct MtcDecoder {
+    pieces: [u8; 8],
+    // Bit n is set once piece n has been received, in order from piece 0
+    received: u8,
+}
+impl MtcDecoder {
+    pub fn new() -> MtcDecoder {
+        MtcDecoder::default()
+    }
+    pub fn push_quarter_frame(&mut self, piece: u8, value: u8) -> Option<Timecode> {
+        let piece = piece & 0x07;
+        // Pieces out of order, as when the sender runs backwards, start over
+        if piece == 0 {
+            self.received = 0;
+        } else if self.received != (1 << piece) - 1 {
+            self.received = 0;
+            return None;
+        }
+        self.pieces[piece as usize] = value & 0x0F;
+        self.received |= 1 << piece;
+
+        if piece != 7 {
+            return None;
+        }
+
+        let byte = |idx: usize| self.pieces[idx] | (self.pieces[idx + 1] << 4);
+        let hours = byte(6);
+        let timecode = Timecode::new(
+            hours & 0x1F,
+            byte(4),
+            byte(2),
+            byte(0),
+            FrameRate::from_code(hours >> 5),
+        );
+
+        Some(timecode.add_frames(2))
+    }
+    /// Full frames are sent when the sender locates, quarter frames start over after them
+    pub fn push_full_frame(&mut self, timecode: Timecode) -> Timecode {
+        self.received = 0;
+        timecode
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiEvent;
+
+    fn quarter_frames(timecode: &Timecode) -> Vec<(u8, u8)> {
+        let bytes = [
+            timecode.frames,
+            timecode.seconds,
+            timecode.minutes,
+            timecode.hours_byte(),
+        ];
+        (0..8)
+            .map(|piece| {
+                let byte = bytes[piece / 2];
+                let value = if piece % 2 == 0 {
+                    byte & 0x0F
+                } else {
+                    byte >> 4
+                };
+                (piece as u8, value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quarter_frames_assemble_two_frames_on() {
+        let timecode = Timecode::new(1, 2, 3, 23, FrameRate::Fps25);
+        let mut decoder = MtcDecoder::new();
+
+        let decoded: Vec<_> = quarter_frames(&timecode)
+            .into_iter()
+            .filter_map(|(piece, value)| decoder.push_quarter_frame(piece, value))
+            .collect();
+
+        assert_eq!(decoded, vec![Timecode::new(1, 2, 4, 0, FrameRate::Fps25)]);
+    }
+
+    #[test]
+    fn quarter_frames_out_of_order_are_dropped() {
+        let timecode = Timecode::new(0, 0, 10, 0, FrameRate::Fps30);
+        let mut decoder = MtcDecoder::new();
+
+        let decoded: Vec<_> = quarter_frames(&timecode)
+            .into_iter()
+            .rev()
+            .filter_map(|(piece, value)| decoder.push_quarter_frame(piece, value))
+            .collect();
+
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn full_frames_round_trip_as_sysex() {
+        let timecode = Timecode::new(10, 59, 30, 12, FrameRate::Fps2997Drop);
+        let bytes = MidiEvent::MtcFullFrame(timecode).to_bytes();
+
+        assert_eq!(
+            MidiEvent::from_bytes(&bytes).unwrap(),
+            Some(MidiEvent::MtcFullFrame(timecode))
+        );
+    }
+
+    #[test]
+    fn drop_frame_counts_skip_frame_numbers() {
+        let rate = FrameRate::Fps2997Drop;
+        let after_first_minute = Timecode::new(0, 1, 0, 2, rate);
+        let tenth_minute = Timecode::new(0, 10, 0, 0, rate);
+
+        assert_eq!(after_first_minute.frame_count(), 1800);
+        assert_eq!(tenth_minute.frame_count(), 17982);
+        assert_eq!(Timecode::from_frame_count(1800, rate), after_first_minute);
+        assert_eq!(Timecode::from_frame_count(17982, rate), tenth_minute);
+        assert_eq!(
+            Timecode::new(0, 0, 59, 29, rate).add_frames(1),
+            after_first_minute
+        );
+    }
+
+    #[test]
+    fn timecode_parses_and_displays() {
+        let timecode = Timecode::parse("01:02:03:04", FrameRate::Fps25).unwrap();
+
+        assert_eq!(timecode, Timecode::new(1, 2, 3, 4, FrameRate::Fps25));
+        assert_eq!(timecode.to_string(), "01:02:03:04");
+        assert_eq!(Timecode::parse("00:00:00:25", FrameRate::Fps25), None);
+        assert_eq!(Timecode::parse("00:61:00:00", FrameRate::Fps25), None);
+    }
+}
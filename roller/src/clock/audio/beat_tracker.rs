@@ -0,0 +1,475 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// Onset strength is measured this many times a second
+const FRAME_RATE: f64 = 100.0;
+// Tempo and phase are estimated from the last few bars of onsets
+const ANALYSIS_SECS: f64 = 8.0;
+const MIN_ANALYSIS_SECS: f64 = 4.0;
+const ANALYSIS_INTERVAL_SECS: f64 = 0.25;
+const MIN_BPM: f64 = 70.0;
+const MAX_BPM: f64 = 180.0;
+// When a track could be heard at either of two tempos, the one nearer this wins
+const PREFERRED_BPM: f64 = 120.0;
+// Tempo changes smaller than this are smoothed over, larger changes are taken as a new track
+const TEMPO_TOLERANCE: f64 = 0.04;
+const BEATS_PER_BAR: usize = 4;
+
+// Centre frequencies of the bands onsets are measured in, the first is a low pass that picks up
+// kick drums
+const BAND_FREQUENCIES: [f64; 4] = [150.0, 500.0, 2000.0, 6000.0];
+
+/// A biquad filter, see https://www.w3.org/TR/audio-eq-cookbook/
+#[derive(Debug, Clone)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+    fn low_pass(frequency: f64, sample_rate: f64) -> Biquad {
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2.0 * std::f64::consts::FRAC_1_SQRT_2);
+        let cos_w0 = w0.cos();
+
+        Biquad::new(
+            (1.0 - cos_w0) / 2.0,
+            1.0 - cos_w0,
+            (1.0 - cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+    fn band_pass(frequency: f64, sample_rate: f64) -> Biquad {
+        let w0 = 2.0 * PI * frequency.min(sample_rate * 0.4) / sample_rate;
+        let alpha = w0.sin() / 2.0;
+        let cos_w0 = w0.cos();
+
+        Biquad::new(alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+    }
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// The tempo and bar phase heard in the most recent audio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeatEstimate {
+    pub bpm: f64,
+    // Onset frame of the most recent downbeat, counted from the start of the audio
+    pub downbeat_frame: f64,
+    // How strongly the onsets repeat at the estimated tempo, from 0 for noise to 1 for a
+    // metronome
+    pub confidence: f64,
+}
+
+/// Estimates tempo and downbeats from mono audio. Onsets are measured as rises in energy across
+/// a few frequency bands, the tempo is the strongest period in their autocorrelation, and beats
+/// are placed where onsets line up best at that period. Downbeats are the beats with the most
+/// kick drum
+pub struct BeatTracker {
+    hop: usize,
+    frame_rate: f64,
+    bands: Vec<Biquad>,
+    band_energy: Vec<f64>,
+    prev_band_levels: Vec<f64>,
+    samples_in_frame: usize,
+    // Onset strength across all bands and in the kick band, for the analysis window
+    onsets: VecDeque<f64>,
+    kick_onsets: VecDeque<f64>,
+    frames: u64,
+    frames_since_analysis: usize,
+    // In frames per beat
+    period: Option<f64>,
+    last_beat_frame: Option<f64>,
+    beat_count: i64,
+    // How much kick there has been on each beat of the bar, by beat count
+    bar_scores: [f64; BEATS_PER_BAR],
+}
+impl BeatTracker {
+    pub fn new(sample_rate: u32) -> BeatTracker {
+        let sample_rate = sample_rate as f64;
+        let hop = (sample_rate / FRAME_RATE).round().max(1.0) as usize;
+
+        let bands: Vec<_> = BAND_FREQUENCIES
+            .iter()
+            .enumerate()
+            .map(|(idx, frequency)| {
+                if idx == 0 {
+                    Biquad::low_pass(*frequency, sample_rate)
+                } else {
+                    Biquad::band_pass(*frequency, sample_rate)
+                }
+            })
+            .collect();
+
+        BeatTracker {
+            hop,
+            frame_rate: sample_rate / hop as f64,
+            band_energy: vec![0.0; bands.len()],
+            prev_band_levels: vec![0.0; bands.len()],
+            bands,
+            samples_in_frame: 0,
+            onsets: VecDeque::new(),
+            kick_onsets: VecDeque::new(),
+            frames: 0,
+            frames_since_analysis: 0,
+            period: None,
+            last_beat_frame: None,
+            beat_count: 0,
+            bar_scores: [0.0; BEATS_PER_BAR],
+        }
+    }
+    /// Onset frames per second
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+    /// Onset frames measured so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+    /// Feeds in mono samples, returning an estimate for each analysis that ran
+    pub fn process(&mut self, samples: &[f32]) -> Vec<BeatEstimate> {
+        let mut estimates = vec![];
+
+        for sample in samples {
+            for (band, energy) in self.bands.iter_mut().zip(self.band_energy.iter_mut()) {
+                *energy += band.process(*sample as f64).powi(2);
+            }
+
+            self.samples_in_frame += 1;
+            if self.samples_in_frame == self.hop {
+                self.end_frame();
+
+                if self.frames_since_analysis as f64 >= ANALYSIS_INTERVAL_SECS * self.frame_rate {
+                    self.frames_since_analysis = 0;
+                    estimates.extend(self.analyse());
+                }
+            }
+        }
+
+        estimates
+    }
+    fn end_frame(&mut self) {
+        let mut onset = 0.0;
+        let mut kick_onset = 0.0;
+
+        for (idx, energy) in self.band_energy.iter_mut().enumerate() {
+            // Log compressed, so quiet passages still have onsets
+            let level = (1.0 + 1000.0 * *energy / self.hop as f64).ln();
+            let rise = (level - self.prev_band_levels[idx]).max(0.0);
+
+            onset += rise;
+            if idx == 0 {
+                kick_onset = rise;
+            }
+
+            self.prev_band_levels[idx] = level;
+            *energy = 0.0;
+        }
+
+        let window_frames = (ANALYSIS_SECS * self.frame_rate) as usize;
+        if self.onsets.len() == window_frames {
+            self.onsets.pop_front();
+            self.kick_onsets.pop_front();
+        }
+        self.onsets.push_back(onset);
+        self.kick_onsets.push_back(kick_onset);
+
+        self.samples_in_frame = 0;
+        self.frames += 1;
+        self.frames_since_analysis += 1;
+    }
+    fn analyse(&mut self) -> Option<BeatEstimate> {
+        let len = self.onsets.len();
+        if (len as f64) < MIN_ANALYSIS_SECS * self.frame_rate {
+            return None;
+        }
+
+        let onsets: Vec<f64> = self.onsets.iter().copied().collect();
+        let mean = onsets.iter().sum::<f64>() / len as f64;
+        let centred: Vec<f64> = onsets.iter().map(|onset| onset - mean).collect();
+        let energy = centred.iter().map(|x| x * x).sum::<f64>() / len as f64;
+        if energy <= 0.0 {
+            return None;
+        }
+
+        let min_lag = (60.0 * self.frame_rate / MAX_BPM).floor() as usize;
+        let max_lag = (60.0 * self.frame_rate / MIN_BPM).ceil() as usize;
+        let autocorrelation: Vec<f64> = (0..(max_lag * BEATS_PER_BAR).min(len / 2))
+            .map(|lag| {
+                let sum: f64 = centred
+                    .iter()
+                    .zip(&centred[lag..])
+                    .map(|(a, b)| a * b)
+                    .sum();
+                sum / (len - lag) as f64 / energy
+            })
+            .collect();
+        if autocorrelation.len() <= max_lag + 1 {
+            return None;
+        }
+
+        // The true period is reinforced by its multiples
+        let score = |lag: usize| {
+            let bpm = 60.0 * self.frame_rate / lag as f64;
+            let preference = (-0.5 * (bpm / PREFERRED_BPM).log2().powi(2)).exp();
+            let double = autocorrelation.get(lag * 2).copied().unwrap_or(0.0);
+
+            (autocorrelation[lag] + 0.5 * double) * preference
+        };
+        let lag = (min_lag..=max_lag).max_by(|a, b| score(*a).partial_cmp(&score(*b)).unwrap())?;
+        let confidence = autocorrelation[lag].clamp(0.0, 1.0);
+
+        // Each multiple of the period pins it down more precisely
+        let harmonic_periods: Vec<f64> = (1..=BEATS_PER_BAR)
+            .filter_map(|multiple| {
+                let search_from = (lag * multiple).saturating_sub(multiple).max(1);
+                let search_to = (lag * multiple + multiple).min(autocorrelation.len() - 2);
+                let peak = (search_from..=search_to).max_by(|a, b| {
+                    autocorrelation[*a]
+                        .partial_cmp(&autocorrelation[*b])
+                        .unwrap()
+                })?;
+
+                let (a, b, c) = (
+                    autocorrelation[peak - 1],
+                    autocorrelation[peak],
+                    autocorrelation[peak + 1],
+                );
+                let curvature = a - 2.0 * b + c;
+                let offset = if curvature < 0.0 {
+                    0.5 * (a - c) / curvature
+                } else {
+                    0.0
+                };
+                Some((peak as f64 + offset) / multiple as f64)
+            })
+            .collect();
+        let mut period = harmonic_periods.iter().sum::<f64>() / harmonic_periods.len() as f64;
+
+        if let Some(prev_period) = self.period {
+            if (period / prev_period - 1.0).abs() < TEMPO_TOLERANCE {
+                period = prev_period + 0.3 * (period - prev_period);
+            }
+        }
+
+        // Beats fall where onsets line up best at the period
+        let window_start = self.frames as f64 - len as f64;
+        let last_frame = len as f64 - 1.0;
+        let comb_score = |offset: f64| {
+            (0..)
+                .map(|beat| last_frame - offset - beat as f64 * period)
+                .take_while(|idx| *idx >= 0.0)
+                .map(|idx| onsets[idx.round() as usize])
+                .sum::<f64>()
+        };
+        let offset = (0..period.ceil() as usize)
+            .map(|offset| offset as f64)
+            .max_by(|a, b| comb_score(*a).partial_cmp(&comb_score(*b)).unwrap())?;
+        let mut last_beat_frame = window_start + last_frame - offset;
+
+        match (self.last_beat_frame, self.period) {
+            (Some(prev_beat_frame), Some(prev_period))
+                if (period / prev_period - 1.0).abs() < TEMPO_TOLERANCE =>
+            {
+                // Nudge towards the new beat rather than jumping, so phase stays continuous
+                let beats_since = ((last_beat_frame - prev_beat_frame) / period).round();
+                let predicted = prev_beat_frame + beats_since * period;
+                let error = last_beat_frame - predicted;
+                if error.abs() < period * 0.15 {
+                    last_beat_frame = predicted + 0.25 * error;
+                }
+                self.beat_count += beats_since as i64;
+            }
+            _ => {
+                self.bar_scores = [0.0; BEATS_PER_BAR];
+            }
+        }
+        self.period = Some(period);
+        self.last_beat_frame = Some(last_beat_frame);
+
+        // Downbeats are the beats of the bar with the most kick, over the last few bars
+        for beats_ago in 0..BEATS_PER_BAR {
+            let kick: f64 = (0..)
+                .map(|bar| {
+                    last_beat_frame
+                        - window_start
+                        - (beats_ago + bar * BEATS_PER_BAR) as f64 * period
+                })
+                // Nudging the phase can put the latest beat just past the end of the window
+                .skip_while(|idx| idx.round() > last_frame)
+                .take_while(|idx| *idx >= 1.0)
+                .map(|idx| {
+                    let idx = idx.round() as usize;
+                    self.kick_onsets
+                        .range(idx - 1..(idx + 2).min(len))
+                        .copied()
+                        .fold(0.0, f64::max)
+                })
+                .sum();
+
+            let slot = (self.beat_count - beats_ago as i64).rem_euclid(BEATS_PER_BAR as i64);
+            self.bar_scores[slot as usize] = 0.8 * self.bar_scores[slot as usize] + kick;
+        }
+        let downbeat_slot = (0..BEATS_PER_BAR as i64).max_by(|a, b| {
+            self.bar_scores[*a as usize]
+                .partial_cmp(&self.bar_scores[*b as usize])
+                .unwrap()
+        })?;
+        let beats_since_downbeat =
+            (self.beat_count - downbeat_slot).rem_euclid(BEATS_PER_BAR as i64);
+
+        Some(BeatEstimate {
+            bpm: 60.0 * self.frame_rate / period,
+            downbeat_frame: last_beat_frame - beats_since_downbeat as f64 * period,
+            confidence,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    // A drum loop, with a kick on the first beat of each bar and a snare on the others
+    fn drum_loop(bpm: f64, secs: f64) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(41);
+        let samples_per_beat = SAMPLE_RATE as f64 * 60.0 / bpm;
+
+        (0..(secs * SAMPLE_RATE as f64) as usize)
+            .map(|idx| {
+                let beat = (idx as f64 / samples_per_beat).floor();
+                let since_beat = (idx as f64 - beat * samples_per_beat) / SAMPLE_RATE as f64;
+                let envelope = (-since_beat * 30.0).exp();
+                let noise = rng.gen_range(-1.0, 1.0);
+
+                let drum = if beat as usize % BEATS_PER_BAR == 0 {
+                    (2.0 * PI * 55.0 * since_beat).sin()
+                } else {
+                    0.5 * noise
+                };
+                (0.8 * drum * envelope + 0.02 * noise) as f32
+            })
+            .collect()
+    }
+
+    // Like `drum_loop`, with each beat played up to 60ms early or late
+    fn jittered_drum_loop(seed: u64, bpm: f64, secs: f64) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let secs_per_beat = 60.0 / bpm;
+        let beats: Vec<f64> = (0..(secs / secs_per_beat).ceil() as usize + 1)
+            .map(|beat| (beat as f64 * secs_per_beat + rng.gen_range(-0.06, 0.06)).max(0.0))
+            .collect();
+
+        let mut beat = 0;
+        (0..(secs * SAMPLE_RATE as f64) as usize)
+            .map(|idx| {
+                let secs = idx as f64 / SAMPLE_RATE as f64;
+                while beat + 1 < beats.len() && beats[beat + 1] <= secs {
+                    beat += 1;
+                }
+                let since_beat = secs - beats[beat];
+                let envelope = (-since_beat * 30.0).exp();
+                let noise = rng.gen_range(-1.0, 1.0);
+
+                let drum = if beat % BEATS_PER_BAR == 0 {
+                    (2.0 * PI * 55.0 * since_beat).sin()
+                } else {
+                    0.5 * noise
+                };
+                (0.8 * drum * envelope + 0.02 * noise) as f32
+            })
+            .collect()
+    }
+
+    fn last_estimate(tracker: &mut BeatTracker, samples: &[f32]) -> BeatEstimate {
+        samples
+            .chunks(1024)
+            .flat_map(|chunk| tracker.process(chunk))
+            .last()
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_tempo() {
+        for bpm in [96.0, 124.0, 140.0].iter() {
+            let mut tracker = BeatTracker::new(SAMPLE_RATE);
+            let estimate = last_estimate(&mut tracker, &drum_loop(*bpm, 12.0));
+
+            assert!(
+                (estimate.bpm - bpm).abs() < bpm * 0.01,
+                "{} {}",
+                bpm,
+                estimate.bpm
+            );
+            assert!(estimate.confidence > 0.5, "{}", estimate.confidence);
+        }
+    }
+
+    #[test]
+    fn follows_loose_playing() {
+        let mut rng = StdRng::seed_from_u64(41);
+
+        for seed in 0..40 {
+            let bpm = rng.gen_range(90.0, 150.0);
+            let mut tracker = BeatTracker::new(SAMPLE_RATE);
+
+            // Beats played off the grid nudge the phase around, which mustn't take the
+            // tracker outside the audio it has heard
+            for chunk in jittered_drum_loop(seed, bpm, 6.0).chunks(1024) {
+                tracker.process(chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_downbeats() {
+        let bpm = 128.0;
+        let mut tracker = BeatTracker::new(SAMPLE_RATE);
+        let estimate = last_estimate(&mut tracker, &drum_loop(bpm, 16.0));
+
+        let downbeat_secs = estimate.downbeat_frame / tracker.frame_rate();
+        let secs_per_bar = 60.0 / bpm * BEATS_PER_BAR as f64;
+        let bar_phase = downbeat_secs / secs_per_bar - (downbeat_secs / secs_per_bar).round();
+        assert!(
+            (bar_phase * secs_per_bar).abs() < 0.03,
+            "{}",
+            bar_phase * secs_per_bar
+        );
+    }
+
+    #[test]
+    fn has_no_confidence_in_noise() {
+        let mut rng = StdRng::seed_from_u64(41);
+        let noise: Vec<f32> = (0..SAMPLE_RATE * 12)
+            .map(|_| rng.gen_range(-0.5, 0.5))
+            .collect();
+
+        let mut tracker = BeatTracker::new(SAMPLE_RATE);
+        let estimate = last_estimate(&mut tracker, &noise);
+        assert!(estimate.confidence < 0.2, "{}", estimate.confidence);
+    }
+}
@@ -0,0 +1,361 @@
+use chrono::Utc;
+use futures::stream::Stream;
+use serde::Deserialize;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use roller_protocol::clock::ClockEvent;
+
+mod beat_tracker;
+mod wav;
+
+pub use beat_tracker::BeatTracker;
+pub use wav::WavReader;
+
+// Audio is analysed in chunks this long
+const CHUNK_SECS: f64 = 0.01;
+const RESTART_INTERVAL: Duration = Duration::from_secs(5);
+const BEATS_PER_BAR: f64 = 4.0;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioInput {
+    // A command that records WAV audio to its standard output, eg.
+    // `["arecord", "-q", "-f", "S16_LE", "-r", "44100", "-c", "1", "-t", "wav"]` on Linux or
+    // `["sox", "-q", "-d", "-t", "wav", "-"]` on macOS. It's restarted if it exits
+    Command(Vec<String>),
+    // A WAV file, played through in real time
+    File(PathBuf),
+}
+impl AudioInput {
+    /// Resolves a file input relative to `root`
+    pub fn with_root(self, root: &Path) -> AudioInput {
+        match self {
+            AudioInput::File(path) => AudioInput::File(root.join(path)),
+            input => input,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AudioClockConfig {
+    pub name: String,
+    pub input: AudioInput,
+    // From 0 to 1, higher values follow quieter or less rhythmic music, at the risk of following
+    // noise. Below the matching confidence the clock is handed back to tapped tempo
+    #[serde(default = "AudioClockConfig::default_sensitivity")]
+    pub sensitivity: f64,
+}
+impl AudioClockConfig {
+    fn default_sensitivity() -> f64 {
+        0.5
+    }
+    pub fn min_confidence(&self) -> f64 {
+        0.05 + 0.3 * (1.0 - self.sensitivity.clamp(0.0, 1.0))
+    }
+    /// Resolves a file input relative to `root`
+    pub fn with_root(mut self, root: &Path) -> AudioClockConfig {
+        self.input = self.input.with_root(root);
+        self
+    }
+}
+
+enum RunEnd {
+    Finished,
+    Closed,
+}
+
+// Passes the audio to `process` in chunks as it arrives, or paced to real time when it's from a
+// file, until it ends or `process` returns false
+fn run_audio_reader(
+    reader: impl Read,
+    paced: bool,
+    process: &mut dyn FnMut(u32, &[f32]) -> bool,
+) -> io::Result<RunEnd> {
+    let mut wav = WavReader::new(BufReader::new(reader))?;
+    let sample_rate = wav.sample_rate();
+
+    let chunk_frames = (sample_rate as f64 * CHUNK_SECS).ceil() as usize;
+    let mut samples = Vec::with_capacity(chunk_frames);
+    let started_at = Instant::now();
+    let mut frames_read = 0;
+
+    while wav.read_mono(&mut samples, chunk_frames)? > 0 {
+        frames_read += samples.len();
+        if paced {
+            let audio_elapsed = Duration::from_secs_f64(frames_read as f64 / sample_rate as f64);
+            if let Some(until) = (started_at + audio_elapsed).checked_duration_since(Instant::now())
+            {
+                std::thread::sleep(until);
+            }
+        }
+
+        if !process(sample_rate, &samples) {
+            return Ok(RunEnd::Closed);
+        }
+    }
+
+    Ok(RunEnd::Finished)
+}
+
+fn run_command(
+    command: &[String],
+    process: &mut dyn FnMut(u32, &[f32]) -> bool,
+) -> io::Result<RunEnd> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "audio command is empty"))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().unwrap();
+    let result = run_audio_reader(stdout, false, process);
+
+    let _ = child.kill();
+    let _ = child.wait();
+    result
+}
+
+/// Reads `input` on a thread of its own, restarting recording commands if they exit. Each run
+/// gets a processor from `new_processor`, given the audio's sample rate, which is passed chunks
+/// of mono samples until it returns false. `description` names the input in messages
+pub fn spawn_audio_reader<F, P>(description: String, input: AudioInput, new_processor: F)
+where
+    F: Fn(u32) -> P + Send + 'static,
+    P: FnMut(&[f32]) -> bool,
+{
+    std::thread::spawn(move || loop {
+        // The processor is made once the sample rate is known, from the first chunk
+        let mut processor: Option<P> = None;
+        let mut process = |sample_rate: u32, samples: &[f32]| {
+            processor.get_or_insert_with(|| new_processor(sample_rate))(samples)
+        };
+
+        let result = match &input {
+            AudioInput::Command(command) => run_command(command, &mut process),
+            AudioInput::File(path) => std::fs::File::open(path)
+                .and_then(|file| run_audio_reader(file, true, &mut process)),
+        };
+
+        match (result, &input) {
+            (Ok(RunEnd::Closed), _) => return,
+            (Ok(RunEnd::Finished), AudioInput::File(_)) => {
+                println!("{} reached the end of its file", description);
+                return;
+            }
+            (Err(err), AudioInput::File(_)) => {
+                eprintln!("unable to read {}: {}", description, err);
+                return;
+            }
+            (Ok(RunEnd::Finished), AudioInput::Command(_)) => {
+                eprintln!("{} stopped recording", description);
+            }
+            (Err(err), AudioInput::Command(_)) => {
+                eprintln!("unable to record {}: {}", description, err);
+            }
+        }
+        std::thread::sleep(RESTART_INTERVAL);
+    });
+}
+
+/// Tempo and downbeats heard on an audio input. Estimates are only passed on when the beat is
+/// clear enough for the configured sensitivity
+pub fn audio_clock_events(config: &AudioClockConfig) -> impl Stream<Item = ClockEvent> {
+    let min_confidence = config.min_confidence();
+    let (clock_event_send, clock_event_recv) = async_std::sync::channel(16);
+
+    spawn_audio_reader(
+        format!("audio clock \"{}\"", config.name),
+        config.input.clone(),
+        move |sample_rate| {
+            let clock_event_send = clock_event_send.clone();
+            let mut tracker = BeatTracker::new(sample_rate);
+
+            move |samples: &[f32]| {
+                for estimate in tracker.process(samples) {
+                    if estimate.confidence < min_confidence {
+                        continue;
+                    }
+
+                    let secs_since_downbeat =
+                        (tracker.frames() as f64 - estimate.downbeat_frame) / tracker.frame_rate();
+                    let event = ClockEvent::Sync {
+                        bpm: estimate.bpm,
+                        downbeat_at: Utc::now()
+                            - chrono::Duration::microseconds(
+                                (secs_since_downbeat * 1_000_000.0) as i64,
+                            ),
+                        beats_per_bar: BEATS_PER_BAR,
+                    };
+
+                    if let Err(async_std::sync::TrySendError::Disconnected(_)) =
+                        clock_event_send.try_send(event)
+                    {
+                        return false;
+                    }
+                }
+                true
+            }
+        },
+    );
+
+    clock_event_recv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::beat_tracker::BeatEstimate;
+    use super::*;
+    use std::f64::consts::PI;
+    use std::io::Cursor;
+
+    const SAMPLE_RATE: u32 = 22050;
+    const BPM: f64 = 120.0;
+
+    // A kick drum on every beat
+    fn kicks(secs: f64) -> Vec<f32> {
+        let samples_per_beat = SAMPLE_RATE as f64 * 60.0 / BPM;
+
+        (0..(secs * SAMPLE_RATE as f64) as usize)
+            .map(|idx| {
+                let since_beat = (idx as f64 % samples_per_beat) / SAMPLE_RATE as f64;
+                (0.8 * (2.0 * PI * 55.0 * since_beat).sin() * (-since_beat * 30.0).exp()) as f32
+            })
+            .collect()
+    }
+
+    #[derive(Clone, Copy)]
+    enum Encoding {
+        Int16,
+        Float32,
+    }
+    impl Encoding {
+        fn format_tag(self) -> u16 {
+            match self {
+                Encoding::Int16 => 1,
+                Encoding::Float32 => 3,
+            }
+        }
+        fn bits(self) -> u16 {
+            match self {
+                Encoding::Int16 => 16,
+                Encoding::Float32 => 32,
+            }
+        }
+        fn encode(self, sample: f32) -> Vec<u8> {
+            match self {
+                Encoding::Int16 => ((sample * 32767.0) as i16).to_le_bytes().to_vec(),
+                Encoding::Float32 => sample.to_le_bytes().to_vec(),
+            }
+        }
+    }
+
+    // A WAV file of `samples` on each of `channels`, with `data_size` written as the size of
+    // its data chunk, or the real size if `None`
+    fn wav(encoding: Encoding, channels: u16, samples: &[f32], data_size: Option<u32>) -> Vec<u8> {
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| (0..channels).map(move |_| *sample))
+            .flat_map(|sample| encoding.encode(sample))
+            .collect();
+        let bits = encoding.bits();
+        let block_align = channels * bits / 8;
+
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&encoding.format_tag().to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        fmt.extend_from_slice(&(SAMPLE_RATE * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(4 + 8 + fmt.len() as u32 + 8 + data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        // Chunks we don't know are skipped
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&3u32.to_le_bytes());
+        wav.extend_from_slice(&[0; 4]);
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&fmt);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_size.unwrap_or(data.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&data);
+        wav
+    }
+
+    // Runs the audio through a beat tracker, returning how many frames were read and the last
+    // estimate
+    fn track(wav: Vec<u8>) -> (usize, BeatEstimate) {
+        let mut tracker: Option<BeatTracker> = None;
+        let mut estimates = Vec::new();
+        let mut frames = 0;
+
+        let run_end = run_audio_reader(Cursor::new(wav), false, &mut |sample_rate, samples| {
+            let tracker = tracker.get_or_insert_with(|| BeatTracker::new(sample_rate));
+            estimates.extend(tracker.process(samples));
+            frames += samples.len();
+            true
+        })
+        .unwrap();
+
+        assert!(matches!(run_end, RunEnd::Finished));
+        (frames, *estimates.last().unwrap())
+    }
+
+    fn assert_finds_tempo(estimate: BeatEstimate) {
+        assert!((estimate.bpm - BPM).abs() < BPM * 0.01, "{}", estimate.bpm);
+        assert!(estimate.confidence > 0.5, "{}", estimate.confidence);
+    }
+
+    #[test]
+    fn reads_16_bit_pcm() {
+        let samples = kicks(12.0);
+        let (frames, estimate) = track(wav(Encoding::Int16, 2, &samples, None));
+
+        assert_eq!(frames, samples.len());
+        assert_finds_tempo(estimate);
+    }
+
+    #[test]
+    fn reads_float() {
+        let samples = kicks(12.0);
+        let (frames, estimate) = track(wav(Encoding::Float32, 1, &samples, None));
+
+        assert_eq!(frames, samples.len());
+        assert_finds_tempo(estimate);
+    }
+
+    #[test]
+    fn streams_without_a_data_size() {
+        let samples = kicks(12.0);
+
+        for data_size in [0, 0xFFFF_FFFF].iter() {
+            let (frames, estimate) = track(wav(Encoding::Int16, 1, &samples, Some(*data_size)));
+
+            assert_eq!(frames, samples.len());
+            assert_finds_tempo(estimate);
+        }
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_data() {
+        let samples = kicks(12.0);
+        let mut wav = wav(Encoding::Int16, 1, &samples, Some(SAMPLE_RATE * 2 * 10));
+        // Trailing chunks aren't read as audio
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&4u32.to_le_bytes());
+        wav.extend_from_slice(&[0; 4]);
+
+        let (frames, estimate) = track(wav);
+        assert_eq!(frames, SAMPLE_RATE as usize * 10);
+        assert_finds_tempo(estimate);
+    }
+}
@@ -0,0 +1,165 @@
+use std::convert::TryInto;
+use std::io::{self, Read};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SampleFormat {
+    Int,
+    Float,
+}
+
+/// Reads WAV audio as it arrives, so a recording's output can be read while it's being written.
+/// Integer PCM of 8 to 32 bits and 32 or 64 bit float are supported
+pub struct WavReader<R> {
+    reader: R,
+    sample_rate: u32,
+    channels: usize,
+    bytes_per_sample: usize,
+    format: SampleFormat,
+    // `None` when streaming, where the size in the header can't be trusted
+    data_bytes_remaining: Option<u64>,
+    frame_buf: Vec<u8>,
+}
+impl<R: Read> WavReader<R> {
+    pub fn new(mut reader: R) -> io::Result<WavReader<R>> {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            return Err(invalid_data("not a WAV file"));
+        }
+
+        let mut fmt = None;
+        loop {
+            let mut chunk_header = [0; 8];
+            reader.read_exact(&mut chunk_header)?;
+            let chunk_size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap());
+
+            match &chunk_header[0..4] {
+                b"fmt " => {
+                    let mut chunk = vec![0; chunk_size as usize + chunk_size as usize % 2];
+                    reader.read_exact(&mut chunk)?;
+                    if chunk.len() < 16 {
+                        return Err(invalid_data("WAV format chunk is too short"));
+                    }
+                    fmt = Some(chunk);
+                }
+                b"data" => {
+                    let fmt = fmt.ok_or_else(|| invalid_data("WAV data came before its format"))?;
+                    // Recorders writing to a pipe don't know the size up front, and leave it
+                    // empty or as large as it goes
+                    let data_bytes_remaining = match chunk_size {
+                        0 | 0xFFFF_FFFF => None,
+                        size => Some(size as u64),
+                    };
+
+                    return WavReader::with_format(reader, &fmt, data_bytes_remaining);
+                }
+                _ => {
+                    let padded_size = chunk_size as u64 + chunk_size as u64 % 2;
+                    io::copy(&mut (&mut reader).take(padded_size), &mut io::sink())?;
+                }
+            }
+        }
+    }
+    fn with_format(
+        reader: R,
+        fmt: &[u8],
+        data_bytes_remaining: Option<u64>,
+    ) -> io::Result<WavReader<R>> {
+        let read_u16 =
+            |offset: usize| u16::from_le_bytes(fmt[offset..offset + 2].try_into().unwrap());
+
+        let mut format_tag = read_u16(0);
+        if format_tag == FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+            // The first two bytes of the sub-format GUID are the format tag
+            format_tag = read_u16(24);
+        }
+        let channels = read_u16(2) as usize;
+        let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+        let bits_per_sample = read_u16(14) as usize;
+
+        let format = match (format_tag, bits_per_sample) {
+            (FORMAT_PCM, 8) | (FORMAT_PCM, 16) | (FORMAT_PCM, 24) | (FORMAT_PCM, 32) => {
+                SampleFormat::Int
+            }
+            (FORMAT_FLOAT, 32) | (FORMAT_FLOAT, 64) => SampleFormat::Float,
+            _ => {
+                return Err(invalid_data(&format!(
+                    "unsupported WAV format {} with {} bits per sample",
+                    format_tag, bits_per_sample
+                )))
+            }
+        };
+        if channels == 0 || sample_rate == 0 {
+            return Err(invalid_data("WAV file has no channels or sample rate"));
+        }
+
+        let bytes_per_sample = bits_per_sample / 8;
+        Ok(WavReader {
+            reader,
+            sample_rate,
+            channels,
+            bytes_per_sample,
+            format,
+            data_bytes_remaining,
+            frame_buf: vec![0; channels * bytes_per_sample],
+        })
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    fn decode_sample(&self, bytes: &[u8]) -> f32 {
+        match (self.format, bytes.len()) {
+            // 8 bit samples are unsigned
+            (SampleFormat::Int, 1) => (bytes[0] as f32 - 128.0) / 128.0,
+            (SampleFormat::Int, 2) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            (SampleFormat::Int, 3) => {
+                i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as f32 / 2_147_483_648.0
+            }
+            (SampleFormat::Int, _) => {
+                i32::from_le_bytes(bytes.try_into().unwrap()) as f32 / 2_147_483_648.0
+            }
+            (SampleFormat::Float, 4) => f32::from_le_bytes(bytes.try_into().unwrap()),
+            (SampleFormat::Float, _) => f64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+        }
+    }
+    /// Reads up to `max_frames` frames, mixed down to mono, returning how many were read. Zero
+    /// means the audio has ended
+    pub fn read_mono(&mut self, samples: &mut Vec<f32>, max_frames: usize) -> io::Result<usize> {
+        samples.clear();
+
+        while samples.len() < max_frames {
+            let frame_len = self.frame_buf.len() as u64;
+            if let Some(remaining) = self.data_bytes_remaining {
+                if remaining < frame_len {
+                    break;
+                }
+            }
+
+            match self.reader.read_exact(&mut self.frame_buf) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+            if let Some(remaining) = self.data_bytes_remaining.as_mut() {
+                *remaining -= frame_len;
+            }
+
+            let sum: f32 = self
+                .frame_buf
+                .chunks(self.bytes_per_sample)
+                .map(|bytes| self.decode_sample(bytes))
+                .sum();
+            samples.push(sum / self.channels as f32);
+        }
+
+        Ok(samples.len())
+    }
+}
@@ -0,0 +1,725 @@
+//! A participant in an Ableton Link session, following the session's tempo and beat phase over
+//! local multicast. Only the parts of the Link protocol needed to join a session and stay in step
+//! with it are implemented, we never change the session's tempo ourselves.
+//! See https://github.com/Ableton/link for the reference implementation
+use async_std::net::UdpSocket;
+use async_std::sync::TrySendError;
+use futures::stream::{self, Stream, StreamExt};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::convert::TryInto;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use roller_protocol::clock::{ClockEvent, Time};
+
+use crate::utils::tick_stream;
+
+const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 76, 78, 75);
+const MULTICAST_PORT: u16 = 20808;
+const MAX_MESSAGE_SIZE: usize = 512;
+
+const DISCOVERY_HEADER: &[u8; 8] = b"_asdp_v\x01";
+const ALIVE: u8 = 1;
+const RESPONSE: u8 = 2;
+const BYEBYE: u8 = 3;
+// Peers forget us if they don't hear from us for this many seconds
+const TTL_SECS: u8 = 5;
+
+const MEASUREMENT_HEADER: &[u8; 8] = b"_link_v\x01";
+const PING: u8 = 1;
+const PONG: u8 = 2;
+const MEASUREMENT_DATA_POINTS: usize = 100;
+const MEASUREMENT_TIMEOUT: Duration = Duration::from_secs(1);
+// Other sessions are measured again this often, in case their ghost time has drifted
+const REMEASURE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Sessions whose ghost times are closer than this are considered to have been founded together,
+// and the one with the lowest ID wins
+const SESSION_EPS_MICROS: i64 = 500_000;
+
+const BROADCAST_INTERVAL: Duration = Duration::from_millis(250);
+
+// The clock source name of the Link session, as used in a project's clock priority
+pub const LINK_CLOCK_NAME: &str = "Ableton Link";
+
+type NodeId = [u8; 8];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkConfig {
+    // Address of the network interface to join sessions on, defaults to the interface multicast
+    // traffic is routed through. Use 127.0.0.1 to join sessions on this machine only
+    pub interface: Option<Ipv4Addr>,
+    // Beats per bar, the clock's downbeat lines up with the session's bar boundaries
+    #[serde(default = "LinkConfig::default_quantum")]
+    pub quantum: f64,
+    // Tempo of the session we found when there are no other peers
+    #[serde(default = "LinkConfig::default_bpm")]
+    pub bpm: f64,
+}
+impl LinkConfig {
+    fn default_quantum() -> f64 {
+        4.0
+    }
+    fn default_bpm() -> f64 {
+        128.0
+    }
+}
+impl Default for LinkConfig {
+    fn default() -> LinkConfig {
+        LinkConfig {
+            interface: None,
+            quantum: LinkConfig::default_quantum(),
+            bpm: LinkConfig::default_bpm(),
+        }
+    }
+}
+
+fn read_i64(bytes: &[u8]) -> Option<i64> {
+    Some(i64::from_be_bytes(bytes.get(..8)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+fn read_node_id(bytes: &[u8]) -> Option<NodeId> {
+    bytes.get(..8)?.try_into().ok()
+}
+
+fn push_entry(payload: &mut Vec<u8>, key: &[u8; 4], value: &[u8]) {
+    payload.extend_from_slice(key);
+    payload.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    payload.extend_from_slice(value);
+}
+
+// Payloads are a sequence of entries, each a four character key, a big endian u32 size and the
+// value. Unknown keys are skipped
+fn payload_entries(mut payload: &[u8]) -> FxHashMap<[u8; 4], &[u8]> {
+    let mut entries = FxHashMap::default();
+
+    while payload.len() >= 8 {
+        let key = [payload[0], payload[1], payload[2], payload[3]];
+        let size = read_u32(&payload[4..]).unwrap() as usize;
+
+        match payload.get(8..8 + size) {
+            Some(value) => {
+                entries.insert(key, value);
+                payload = &payload[8 + size..];
+            }
+            None => break,
+        }
+    }
+
+    entries
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timeline {
+    micros_per_beat: i64,
+    // In millionths of a beat
+    beat_origin: i64,
+    // Ghost time of `beat_origin`, in microseconds
+    time_origin: i64,
+}
+impl Timeline {
+    fn new(bpm: f64) -> Timeline {
+        Timeline {
+            micros_per_beat: (60_000_000.0 / bpm).round() as i64,
+            beat_origin: 0,
+            time_origin: 0,
+        }
+    }
+    fn bpm(&self) -> f64 {
+        60_000_000.0 / self.micros_per_beat as f64
+    }
+    fn beats_at(&self, ghost_time: i64) -> f64 {
+        self.beat_origin as f64 / 1_000_000.0
+            + (ghost_time - self.time_origin) as f64 / self.micros_per_beat as f64
+    }
+    fn encode(&self) -> Vec<u8> {
+        [self.micros_per_beat, self.beat_origin, self.time_origin]
+            .iter()
+            .flat_map(|value| value.to_be_bytes().to_vec())
+            .collect()
+    }
+    fn decode(bytes: &[u8]) -> Option<Timeline> {
+        let timeline = Timeline {
+            micros_per_beat: read_i64(bytes)?,
+            beat_origin: read_i64(bytes.get(8..)?)?,
+            time_origin: read_i64(bytes.get(16..)?)?,
+        };
+
+        if timeline.micros_per_beat > 0 {
+            Some(timeline)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PeerState {
+    session_id: NodeId,
+    timeline: Timeline,
+    measurement_endpoint: Option<SocketAddrV4>,
+}
+impl PeerState {
+    fn decode(payload: &[u8]) -> Option<PeerState> {
+        let entries = payload_entries(payload);
+
+        let measurement_endpoint = entries.get(b"mep4").and_then(|value| {
+            let ip: [u8; 4] = value.get(..4)?.try_into().ok()?;
+            let port = u16::from_be_bytes(value.get(4..6)?.try_into().ok()?);
+            Some(SocketAddrV4::new(ip.into(), port))
+        });
+
+        Some(PeerState {
+            session_id: read_node_id(entries.get(b"sess")?)?,
+            timeline: Timeline::decode(entries.get(b"tmln")?)?,
+            measurement_endpoint,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    state: PeerState,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+struct Measurement {
+    session_id: NodeId,
+    // Estimates of the offset from our host time to the session's ghost time, in microseconds
+    data: Vec<f64>,
+    last_activity_at: Instant,
+}
+
+enum LinkEvent {
+    Tick,
+    Discovery(Vec<u8>, SocketAddr),
+    Measurement(Vec<u8>, SocketAddr),
+}
+
+struct Participant {
+    node_id: NodeId,
+    quantum: f64,
+    // Host time is measured from here, in microseconds
+    epoch: Instant,
+    session_id: NodeId,
+    // Added to host time to give the session's shared ghost time
+    ghost_intercept: i64,
+    timeline: Timeline,
+    measurement_endpoint: SocketAddrV4,
+    discovery_socket: Arc<UdpSocket>,
+    measurement_socket: Arc<UdpSocket>,
+    peers: FxHashMap<NodeId, Peer>,
+    measurements: FxHashMap<SocketAddr, Measurement>,
+    measured_at: FxHashMap<NodeId, Instant>,
+    last_peer_count: usize,
+}
+impl Participant {
+    fn host_time(&self) -> i64 {
+        self.epoch.elapsed().as_micros() as i64
+    }
+    fn ghost_time(&self) -> i64 {
+        self.host_time() + self.ghost_intercept
+    }
+    fn discovery_message(&self, message_type: u8, ttl: u8) -> Vec<u8> {
+        let mut message = DISCOVERY_HEADER.to_vec();
+        message.push(message_type);
+        message.push(ttl);
+        // Group ID, always zero
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(&self.node_id);
+
+        if message_type != BYEBYE {
+            push_entry(&mut message, b"tmln", &self.timeline.encode());
+            push_entry(&mut message, b"sess", &self.session_id);
+
+            let mut endpoint = self.measurement_endpoint.ip().octets().to_vec();
+            endpoint.extend_from_slice(&self.measurement_endpoint.port().to_be_bytes());
+            push_entry(&mut message, b"mep4", &endpoint);
+        }
+
+        message
+    }
+    async fn send_discovery(&self, message_type: u8, to: SocketAddr) {
+        let message = self.discovery_message(message_type, TTL_SECS);
+        if let Err(err) = self.discovery_socket.send_to(&message, to).await {
+            eprintln!("unable to send Link discovery message: {}", err);
+        }
+    }
+    async fn send_ping(&self, to: SocketAddr, prev_ghost_time: Option<i64>) {
+        let mut message = MEASUREMENT_HEADER.to_vec();
+        message.push(PING);
+        push_entry(&mut message, b"__ht", &self.host_time().to_be_bytes());
+        if let Some(prev_ghost_time) = prev_ghost_time {
+            push_entry(&mut message, b"_pgt", &prev_ghost_time.to_be_bytes());
+        }
+
+        if let Err(err) = self.measurement_socket.send_to(&message, to).await {
+            eprintln!("unable to send Link ping: {}", err);
+        }
+    }
+    async fn tick(&mut self) {
+        let now = Instant::now();
+
+        self.peers.retain(|_, peer| peer.expires_at > now);
+        if self.peers.len() != self.last_peer_count {
+            println!("Link: {} peers", self.peers.len());
+            self.last_peer_count = self.peers.len();
+        }
+
+        self.measurements
+            .retain(|_, measurement| now - measurement.last_activity_at < MEASUREMENT_TIMEOUT);
+
+        self.send_discovery(ALIVE, (MULTICAST_ADDR, MULTICAST_PORT).into())
+            .await;
+    }
+    async fn handle_discovery(&mut self, message: &[u8], from: SocketAddr) {
+        if !message.starts_with(DISCOVERY_HEADER) || message.len() < 20 {
+            return;
+        }
+        let (message_type, ttl) = (message[8], message[9]);
+        let node_id = read_node_id(&message[12..]).unwrap();
+        // Our own broadcasts are looped back to us
+        if node_id == self.node_id || message[10..12] != [0, 0] {
+            return;
+        }
+
+        match message_type {
+            ALIVE | RESPONSE => {
+                if let Some(state) = PeerState::decode(&message[20..]) {
+                    if message_type == ALIVE {
+                        self.send_discovery(RESPONSE, from).await;
+                    }
+                    self.handle_peer_state(node_id, state, ttl).await;
+                }
+            }
+            BYEBYE => {
+                self.peers.remove(&node_id);
+            }
+            _ => {}
+        }
+    }
+    async fn handle_peer_state(&mut self, node_id: NodeId, state: PeerState, ttl: u8) {
+        if state.session_id == self.session_id {
+            // Peers in our session have the latest word on its timeline
+            self.timeline = state.timeline;
+        } else if let Some(endpoint) = state.measurement_endpoint {
+            let measuring = self
+                .measurements
+                .values()
+                .any(|measurement| measurement.session_id == state.session_id);
+            let recently_measured = self
+                .measured_at
+                .get(&state.session_id)
+                .map(|measured_at| measured_at.elapsed() < REMEASURE_INTERVAL)
+                .unwrap_or(false);
+
+            if !measuring && !recently_measured {
+                self.measurements.insert(
+                    endpoint.into(),
+                    Measurement {
+                        session_id: state.session_id,
+                        data: Vec::with_capacity(MEASUREMENT_DATA_POINTS),
+                        last_activity_at: Instant::now(),
+                    },
+                );
+                self.send_ping(endpoint.into(), None).await;
+            }
+        }
+
+        self.peers.insert(
+            node_id,
+            Peer {
+                state,
+                expires_at: Instant::now() + Duration::from_secs(ttl as u64),
+            },
+        );
+    }
+    async fn handle_measurement(&mut self, message: &[u8], from: SocketAddr) {
+        if !message.starts_with(MEASUREMENT_HEADER) || message.len() < 9 {
+            return;
+        }
+        let payload = &message[9..];
+
+        match message[8] {
+            PING => {
+                let mut pong = MEASUREMENT_HEADER.to_vec();
+                pong.push(PONG);
+                push_entry(&mut pong, b"__sm", &self.session_id);
+                push_entry(&mut pong, b"__gt", &self.ghost_time().to_be_bytes());
+                // The ping's payload is echoed back so the pinger can pair it up
+                pong.extend_from_slice(payload);
+
+                if pong.len() <= MAX_MESSAGE_SIZE {
+                    if let Err(err) = self.measurement_socket.send_to(&pong, from).await {
+                        eprintln!("unable to send Link pong: {}", err);
+                    }
+                }
+            }
+            PONG => self.handle_pong(payload, from).await,
+            _ => {}
+        }
+    }
+    async fn handle_pong(&mut self, payload: &[u8], from: SocketAddr) {
+        let host_time = self.host_time();
+        let entries = payload_entries(payload);
+        let session_id = entries.get(b"__sm").and_then(|value| read_node_id(value));
+        let ghost_time = entries.get(b"__gt").and_then(|value| read_i64(value));
+        let prev_host_time = entries.get(b"__ht").and_then(|value| read_i64(value));
+        let prev_ghost_time = entries.get(b"_pgt").and_then(|value| read_i64(value));
+
+        let measurement = match self.measurements.get_mut(&from) {
+            Some(measurement) => measurement,
+            None => return,
+        };
+        let (ghost_time, prev_host_time) = match (session_id, ghost_time, prev_host_time) {
+            (Some(session_id), Some(ghost_time), Some(prev_host_time))
+                if session_id == measurement.session_id =>
+            {
+                (ghost_time, prev_host_time)
+            }
+            // The peer has moved to another session, or is speaking nonsense
+            _ => {
+                self.measurements.remove(&from);
+                return;
+            }
+        };
+
+        // Assume the peer read its ghost time halfway between our ping and their pong
+        measurement
+            .data
+            .push(ghost_time as f64 - (host_time + prev_host_time) as f64 / 2.0);
+        if let Some(prev_ghost_time) = prev_ghost_time {
+            measurement
+                .data
+                .push((ghost_time + prev_ghost_time) as f64 / 2.0 - prev_host_time as f64);
+        }
+        measurement.last_activity_at = Instant::now();
+
+        if measurement.data.len() < MEASUREMENT_DATA_POINTS {
+            self.send_ping(from, Some(ghost_time)).await;
+            return;
+        }
+
+        let mut measurement = self.measurements.remove(&from).unwrap();
+        measurement
+            .data
+            .sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let intercept = measurement.data[measurement.data.len() / 2].round() as i64;
+
+        self.measured_at
+            .insert(measurement.session_id, Instant::now());
+        self.handle_session_measured(measurement.session_id, intercept);
+    }
+    fn handle_session_measured(&mut self, session_id: NodeId, intercept: i64) {
+        if session_id == self.session_id {
+            return;
+        }
+
+        // The session that has been running longest wins, so joining peers don't disrupt it
+        let ghost_time_diff = intercept - self.ghost_intercept;
+        let join = ghost_time_diff > SESSION_EPS_MICROS
+            || (ghost_time_diff.abs() < SESSION_EPS_MICROS && session_id < self.session_id);
+        if !join {
+            return;
+        }
+
+        let timeline = self
+            .peers
+            .values()
+            .find(|peer| peer.state.session_id == session_id)
+            .map(|peer| peer.state.timeline);
+        if let Some(timeline) = timeline {
+            self.session_id = session_id;
+            self.ghost_intercept = intercept;
+            self.timeline = timeline;
+
+            println!("Link: joined session at {:.2} bpm", timeline.bpm());
+        }
+    }
+    fn clock_event(&self) -> ClockEvent {
+        let now = chrono::Utc::now();
+        let beats = self.timeline.beats_at(self.ghost_time());
+        let micros_since_downbeat =
+            beats.rem_euclid(self.quantum) * self.timeline.micros_per_beat as f64;
+
+        ClockEvent::Sync {
+            bpm: self.timeline.bpm(),
+            downbeat_at: now - chrono::Duration::microseconds(micros_since_downbeat as i64),
+            beats_per_bar: self.quantum,
+        }
+    }
+}
+
+// Connecting a UDP socket doesn't send anything, but picks the interface the OS would route
+// through
+fn default_interface() -> Ipv4Addr {
+    let interface = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((MULTICAST_ADDR, MULTICAST_PORT))?;
+            socket.local_addr()
+        })
+        .map(|address| address.ip());
+
+    match interface {
+        Ok(std::net::IpAddr::V4(interface)) if !interface.is_unspecified() => interface,
+        _ => Ipv4Addr::LOCALHOST,
+    }
+}
+
+// Every peer on the machine listens on the same port, so the address is shared
+fn multicast_socket(interface: Ipv4Addr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::ipv4(), Type::dgram(), Some(Protocol::udp()))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MULTICAST_PORT)).into())?;
+    socket.join_multicast_v4(&MULTICAST_ADDR, &interface)?;
+
+    Ok(UdpSocket::from(socket.into_udp_socket()))
+}
+
+fn unicast_socket(interface: Ipv4Addr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::ipv4(), Type::dgram(), Some(Protocol::udp()))?;
+    socket.bind(&SocketAddr::from((interface, 0)).into())?;
+    socket.set_multicast_if_v4(&interface)?;
+    socket.set_multicast_loop_v4(true)?;
+
+    Ok(UdpSocket::from(socket.into_udp_socket()))
+}
+
+fn received_messages(socket: Arc<UdpSocket>) -> impl Stream<Item = (Vec<u8>, SocketAddr)> {
+    stream::unfold(socket, |socket| async move {
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        loop {
+            match socket.recv_from(&mut buf).await {
+                Ok((len, from)) => return Some(((buf[..len].to_vec(), from), socket)),
+                Err(err) => eprintln!("unable to receive Link message: {}", err),
+            }
+        }
+    })
+}
+
+/// Joins the Link session on the configured interface, founding one if there are no other
+/// peers. Yields the session's tempo and bar phase as it changes
+pub fn link_clock_events(config: &LinkConfig) -> io::Result<impl Stream<Item = ClockEvent>> {
+    let interface = config.interface.unwrap_or_else(default_interface);
+
+    let multicast_socket = Arc::new(multicast_socket(interface)?);
+    let discovery_socket = Arc::new(unicast_socket(interface)?);
+    let measurement_socket = Arc::new(unicast_socket(interface)?);
+    let measurement_endpoint = match measurement_socket.local_addr()? {
+        SocketAddr::V4(address) => address,
+        SocketAddr::V6(_) => unreachable!("measurement socket is bound to an IPv4 interface"),
+    };
+
+    let node_id: NodeId = rand::random();
+    let mut participant = Participant {
+        node_id,
+        quantum: config.quantum,
+        epoch: Instant::now(),
+        session_id: node_id,
+        ghost_intercept: 0,
+        timeline: Timeline::new(config.bpm),
+        measurement_endpoint,
+        discovery_socket: discovery_socket.clone(),
+        measurement_socket: measurement_socket.clone(),
+        peers: FxHashMap::default(),
+        measurements: FxHashMap::default(),
+        measured_at: FxHashMap::default(),
+        last_peer_count: 0,
+    };
+
+    let mut events = stream::select_all(vec![
+        tick_stream(Time::system(), BROADCAST_INTERVAL)
+            .map(|()| LinkEvent::Tick)
+            .boxed(),
+        received_messages(multicast_socket)
+            .map(|(message, from)| LinkEvent::Discovery(message, from))
+            .boxed(),
+        received_messages(discovery_socket)
+            .map(|(message, from)| LinkEvent::Discovery(message, from))
+            .boxed(),
+        received_messages(measurement_socket)
+            .map(|(message, from)| LinkEvent::Measurement(message, from))
+            .boxed(),
+    ]);
+
+    let (clock_event_send, clock_event_recv) = async_std::sync::channel(16);
+    async_std::task::spawn(async move {
+        while let Some(event) = events.next().await {
+            match event {
+                LinkEvent::Tick => {
+                    participant.tick().await;
+                    // On our own we'd only be following the tempo we founded the session with
+                    if participant.peers.is_empty() {
+                        continue;
+                    }
+
+                    // A full channel only drops a sync, the next tick sends a fresh one
+                    if let Err(TrySendError::Disconnected(_)) =
+                        clock_event_send.try_send(participant.clock_event())
+                    {
+                        break;
+                    }
+                }
+                LinkEvent::Discovery(message, from) => {
+                    participant.handle_discovery(&message, from).await
+                }
+                LinkEvent::Measurement(message, from) => {
+                    participant.handle_measurement(&message, from).await
+                }
+            }
+        }
+
+        // Let the other peers know we've gone, rather than waiting for us to time out
+        let message = participant.discovery_message(BYEBYE, 0);
+        let _ = participant
+            .discovery_socket
+            .send_to(&message, (MULTICAST_ADDR, MULTICAST_PORT))
+            .await;
+    });
+
+    Ok(clock_event_recv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn participant(bpm: f64) -> Participant {
+        let socket = Arc::new(unicast_socket(Ipv4Addr::LOCALHOST).unwrap());
+        let node_id: NodeId = rand::random();
+
+        Participant {
+            node_id,
+            quantum: 4.0,
+            epoch: Instant::now(),
+            session_id: node_id,
+            ghost_intercept: 0,
+            timeline: Timeline::new(bpm),
+            measurement_endpoint: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 20809),
+            discovery_socket: socket.clone(),
+            measurement_socket: socket,
+            peers: FxHashMap::default(),
+            measurements: FxHashMap::default(),
+            measured_at: FxHashMap::default(),
+            last_peer_count: 0,
+        }
+    }
+
+    #[test]
+    fn timelines_round_trip() {
+        let timeline = Timeline {
+            micros_per_beat: 468_750,
+            beat_origin: -2_500_000,
+            time_origin: 1_234_567_890,
+        };
+
+        assert_eq!(Timeline::decode(&timeline.encode()), Some(timeline));
+        assert_eq!(Timeline::decode(&timeline.encode()[..23]), None);
+        assert_eq!(
+            Timeline::decode(
+                &Timeline {
+                    micros_per_beat: 0,
+                    ..timeline
+                }
+                .encode()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn payload_entries_skip_truncated_values() {
+        let mut payload = Vec::new();
+        push_entry(&mut payload, b"sess", &[1, 2, 3, 4, 5, 6, 7, 8]);
+        push_entry(&mut payload, b"tmln", &[0; 24]);
+        let entries = payload_entries(&payload[..payload.len() - 1]);
+
+        assert_eq!(entries.get(b"sess"), Some(&&[1, 2, 3, 4, 5, 6, 7, 8][..]));
+        assert_eq!(entries.get(b"tmln"), None);
+    }
+
+    #[test]
+    fn discovery_messages_round_trip() {
+        let participant = participant(128.0);
+        let message = participant.discovery_message(ALIVE, TTL_SECS);
+
+        assert!(message.starts_with(DISCOVERY_HEADER));
+        assert_eq!((message[8], message[9]), (ALIVE, TTL_SECS));
+        assert_eq!(read_node_id(&message[12..]), Some(participant.node_id));
+
+        let state = PeerState::decode(&message[20..]).unwrap();
+        assert_eq!(state.session_id, participant.session_id);
+        assert_eq!(state.timeline, participant.timeline);
+        assert_eq!(
+            state.measurement_endpoint,
+            Some(participant.measurement_endpoint)
+        );
+    }
+
+    #[test]
+    fn byebye_messages_carry_no_state() {
+        let participant = participant(128.0);
+        let message = participant.discovery_message(BYEBYE, 0);
+
+        assert_eq!(message.len(), 20);
+        assert!(PeerState::decode(&message[20..]).is_none());
+    }
+
+    #[async_std::test]
+    async fn peers_on_loopback_converge_on_tempo_and_phase() {
+        let config = |bpm| LinkConfig {
+            interface: Some(Ipv4Addr::LOCALHOST),
+            quantum: 4.0,
+            bpm,
+        };
+        let mut events = stream::select(
+            link_clock_events(&config(120.0))
+                .unwrap()
+                .map(|event| (0, event)),
+            link_clock_events(&config(140.0))
+                .unwrap()
+                .map(|event| (1, event)),
+        );
+
+        let converged = async_std::future::timeout(Duration::from_secs(10), async {
+            let mut latest: [Option<(f64, chrono::DateTime<chrono::Utc>)>; 2] = [None, None];
+
+            while let Some((peer, event)) = events.next().await {
+                if let ClockEvent::Sync {
+                    bpm, downbeat_at, ..
+                } = event
+                {
+                    latest[peer] = Some((bpm, downbeat_at));
+                }
+
+                if let [Some((bpm_a, downbeat_a)), Some((bpm_b, downbeat_b))] = latest {
+                    // Downbeats may be a whole number of bars apart
+                    let micros_per_bar = 4.0 * 60_000_000.0 / bpm_a;
+                    let phase = ((downbeat_a - downbeat_b).num_microseconds().unwrap() as f64)
+                        .rem_euclid(micros_per_bar);
+                    let phase_diff = phase.min(micros_per_bar - phase);
+
+                    if (bpm_a - bpm_b).abs() < 0.001 && phase_diff < 5_000.0 {
+                        return bpm_a;
+                    }
+                }
+            }
+            unreachable!("Link clock events never end");
+        })
+        .await;
+
+        let bpm = converged.expect("peers didn't converge");
+        assert!((bpm - 120.0).abs() < 0.001 || (bpm - 140.0).abs() < 0.001);
+    }
+}
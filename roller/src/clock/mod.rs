@@ -0,0 +1,109 @@
+use async_std::prelude::*;
+use chrono::Utc;
+use std::time::{Duration, Instant};
+
+use roller_protocol::clock::{Beats, ClockEvent};
+
+mod audio;
+mod link;
+mod output;
+mod sources;
+mod tracker;
+
+pub use audio::{
+    audio_clock_events, spawn_audio_reader, AudioClockConfig, AudioInput, BeatTracker, WavReader,
+};
+pub use link::{link_clock_events, LinkConfig, LINK_CLOCK_NAME};
+pub use output::midi_clock_output;
+pub use sources::{clock_source_events, ClockSource, ClockSourceEvent};
+use tracker::PulseTracker;
+
+fn duration_as_secs(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1_000_000.0
+}
+
+static PULSES_PER_QUARTER_NOTE: usize = 24;
+// MIDI clock doesn't carry a time signature, bars are assumed to be 4/4 from the last Start
+static BEATS_PER_BAR: usize = 4;
+// Tempo and phase are passed on every sixteenth note
+static PULSES_PER_SYNC: u64 = 6;
+// Estimates from fewer pulses or noisier clocks than this are held back
+static MIN_CONFIDENCE: f64 = 0.25;
+
+// Transport messages take effect on the pulse after them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PendingTransport {
+    Start,
+    Continue,
+}
+
+fn midi_clock_events(name: &str) -> Result<impl Stream<Item = ClockEvent>, midi::MidiIoError> {
+    let input = midi::MidiInput::new(name)?;
+    let epoch = Instant::now();
+    let mut tracker = PulseTracker::new(PULSES_PER_QUARTER_NOTE);
+    let mut pending_transport: Option<PendingTransport> = None;
+    let mut song_position: Option<u64> = None;
+
+    Ok(input.filter_map(move |midi_event| match midi_event {
+        midi::MidiEvent::TimingClock => {
+            let now = Utc::now();
+            let at = duration_as_secs(epoch.elapsed());
+
+            let transport_event = match pending_transport.take() {
+                Some(PendingTransport::Start) => {
+                    tracker.reset(0);
+                    Some(ClockEvent::Start(now))
+                }
+                Some(PendingTransport::Continue) => {
+                    if let Some(position) = song_position.take() {
+                        tracker.reset(position);
+                    }
+                    Some(ClockEvent::Continue(now))
+                }
+                None => None,
+            };
+
+            let estimate = tracker.pulse(at).copied();
+            if transport_event.is_some() {
+                return transport_event;
+            }
+            let estimate = estimate?;
+            if estimate.last_pulse_index % PULSES_PER_SYNC != 0
+                || estimate.confidence < MIN_CONFIDENCE
+            {
+                return None;
+            }
+
+            let pulses_per_bar = (PULSES_PER_QUARTER_NOTE * BEATS_PER_BAR) as u64;
+            let downbeat_pulse_index = estimate.last_pulse_index / pulses_per_bar * pulses_per_bar;
+            let secs_since_downbeat = at - estimate.pulse_at(downbeat_pulse_index);
+
+            Some(ClockEvent::Sync {
+                bpm: estimate.bpm(),
+                downbeat_at: now
+                    - chrono::Duration::microseconds((secs_since_downbeat * 1_000_000.0) as i64),
+                beats_per_bar: BEATS_PER_BAR as f64,
+            })
+        }
+        midi::MidiEvent::Start => {
+            pending_transport = Some(PendingTransport::Start);
+            None
+        }
+        midi::MidiEvent::Continue => {
+            pending_transport = Some(PendingTransport::Continue);
+            None
+        }
+        midi::MidiEvent::Stop => {
+            pending_transport = None;
+            Some(ClockEvent::Stop(Utc::now()))
+        }
+        // Song position is counted in sixteenth notes
+        midi::MidiEvent::SongPositionPointer { position } => {
+            song_position = Some(u64::from(position) * PULSES_PER_QUARTER_NOTE as u64 / 4);
+            Some(ClockEvent::SongPosition(Beats::new(
+                f64::from(position) / 4.0,
+            )))
+        }
+        _ => None,
+    }))
+}
@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use midi::{MidiEvent, MidiOutput};
+use roller_protocol::clock::Clock;
+
+const PULSES_PER_QUARTER_NOTE: f64 = 24.0;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+struct ClockOutput {
+    name: String,
+    output: Option<MidiOutput>,
+    // Followers are sent a Start on the next downbeat, when they first connect, after the
+    // transport stops or when the beat jumps
+    start_pending: bool,
+}
+impl ClockOutput {
+    // A failed send drops the output, so it's reconnected once the device comes back
+    async fn send_at(&mut self, event: MidiEvent, at: Instant) {
+        if let Some(midi_output) = self.output.as_ref() {
+            if let Err(err) = midi_output.send_packet_at(event.to_bytes(), at).await {
+                eprintln!("MIDI clock output \"{}\" disconnected: {}", self.name, err);
+                self.output = None;
+            }
+        }
+    }
+}
+
+/// Sends MIDI clock to the named outputs, following the engine's clock through tempo and phase
+/// changes. The latest clock is read from `clock_recv`, which only needs to be sent a clock when
+/// it has changed
+pub async fn midi_clock_output(
+    names: Vec<String>,
+    mut clock: Clock,
+    clock_recv: async_std::sync::Receiver<Clock>,
+) {
+    let mut outputs: Vec<_> = names
+        .into_iter()
+        .map(|name| ClockOutput {
+            name,
+            output: None,
+            start_pending: true,
+        })
+        .collect();
+    let mut connect_at = Instant::now();
+    let mut stopped = false;
+    let mut last_pulse: Option<(f64, Instant)> = None;
+
+    loop {
+        while let Ok(updated_clock) = clock_recv.try_recv() {
+            clock = updated_clock;
+        }
+
+        let now = Instant::now();
+        if now >= connect_at {
+            for output in outputs.iter_mut().filter(|output| output.output.is_none()) {
+                match MidiOutput::new(&output.name) {
+                    Ok(midi_output) => {
+                        println!("MIDI clock output \"{}\" connected", output.name);
+                        output.output = Some(midi_output);
+                        output.start_pending = true;
+                    }
+                    // Only reported on the first try, we keep trying quietly after that
+                    Err(err) if last_pulse.is_none() => eprintln!(
+                        "unable to connect to MIDI clock output \"{}\": {}",
+                        output.name, err
+                    ),
+                    Err(_) => {}
+                }
+            }
+            connect_at = now + RECONNECT_INTERVAL;
+        }
+
+        let snapshot = clock.snapshot();
+        let secs_per_pulse = 60.0 / snapshot.bpm / PULSES_PER_QUARTER_NOTE;
+        let pulses_elapsed = f64::from(snapshot.beats_elapsed()) * PULSES_PER_QUARTER_NOTE;
+
+        // The next pulse on the clock's grid, or one period on from the last while stopped, as the
+        // grid stands still
+        let (mut pulse, mut pulse_at) = if clock.is_stopped() {
+            let pulse_at = last_pulse
+                .map(|(_, at)| at + Duration::from_secs_f64(secs_per_pulse))
+                .unwrap_or(now)
+                .max(now);
+            (pulses_elapsed.floor(), pulse_at)
+        } else {
+            let pulse = pulses_elapsed.floor() + 1.0;
+            let secs_until = (pulse - pulses_elapsed) * secs_per_pulse;
+            (pulse, now + Duration::from_secs_f64(secs_until))
+        };
+        if let Some((_, last_pulse_at)) = last_pulse {
+            // Waking a little early finds the pulse we just sent
+            if pulse_at < last_pulse_at + Duration::from_secs_f64(secs_per_pulse / 2.0) {
+                pulse += 1.0;
+                pulse_at += Duration::from_secs_f64(secs_per_pulse);
+            }
+        }
+
+        let beat_jumped = match last_pulse {
+            Some((last_pulse, _)) => !clock.is_stopped() && (pulse - last_pulse - 1.0).abs() > 1.0,
+            None => false,
+        };
+        if clock.is_stopped() != stopped {
+            stopped = clock.is_stopped();
+            for output in outputs.iter_mut() {
+                if stopped {
+                    output.send_at(MidiEvent::Stop, now).await;
+                }
+                output.start_pending = true;
+            }
+        }
+        if beat_jumped {
+            for output in outputs.iter_mut() {
+                output.start_pending = true;
+            }
+        }
+
+        let pulses_per_bar =
+            (snapshot.time_signature.beats_per_bar * PULSES_PER_QUARTER_NOTE).round();
+        let on_downbeat = pulse.rem_euclid(pulses_per_bar) == 0.0;
+
+        for output in outputs.iter_mut().filter(|output| output.output.is_some()) {
+            // The pulse after a Start is the first beat
+            if output.start_pending && on_downbeat && !stopped {
+                output.send_at(MidiEvent::Start, pulse_at).await;
+                output.start_pending = false;
+            }
+            output.send_at(MidiEvent::TimingClock, pulse_at).await;
+        }
+        last_pulse = Some((pulse, pulse_at));
+
+        // Pulses are queued a pulse ahead, so the next is worked out as this one goes
+        let now = Instant::now();
+        if pulse_at > now {
+            async_std::task::sleep(pulse_at - now).await;
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use std::time::{Duration, Instant};
+
+use roller_protocol::clock::{ClockEvent, Time};
+
+use crate::clock::midi_clock_events;
+use crate::utils::{reconnecting_midi_stream, tick_stream};
+
+// How often sources are checked for having gone quiet
+const FAILOVER_CHECK_INTERVAL: Duration = Duration::from_millis(25);
+// A source that hasn't sent anything for half a beat has stopped, but Link only sends four
+// times a second
+const MIN_SILENCE: Duration = Duration::from_millis(300);
+const DEFAULT_SILENCE: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum ClockSourceEvent {
+    Clock(ClockEvent),
+    // The name of the source now driving the clock, or `None` when we're back to tapped tempo
+    ActiveSourceChanged(Option<String>),
+}
+
+pub struct ClockSource {
+    name: String,
+    events: BoxStream<'static, ClockEvent>,
+}
+impl ClockSource {
+    pub fn new(
+        name: impl Into<String>,
+        events: impl Stream<Item = ClockEvent> + Send + 'static,
+    ) -> ClockSource {
+        ClockSource {
+            name: name.into(),
+            events: events.boxed(),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// A MIDI clock input, connected to whenever the device is available
+    pub fn midi_clock(name: &str) -> ClockSource {
+        let events = reconnecting_midi_stream(name, "MIDI clock", midi_clock_events);
+
+        ClockSource::new(name, events)
+    }
+}
+
+struct SourceState {
+    name: String,
+    bpm: Option<f64>,
+    ticking_until: Option<Instant>,
+}
+
+/// Chooses which of several clock sources drives the clock. Sources are listed in priority
+/// order, the first that is ticking wins
+struct ClockSourceSelector {
+    sources: Vec<SourceState>,
+    active_idx: Option<usize>,
+}
+impl ClockSourceSelector {
+    fn new<'a>(names: impl IntoIterator<Item = &'a str>) -> ClockSourceSelector {
+        ClockSourceSelector {
+            sources: names
+                .into_iter()
+                .map(|name| SourceState {
+                    name: name.to_owned(),
+                    bpm: None,
+                    ticking_until: None,
+                })
+                .collect(),
+            active_idx: None,
+        }
+    }
+    fn source_event(
+        &mut self,
+        idx: usize,
+        event: ClockEvent,
+        now: Instant,
+    ) -> Vec<ClockSourceEvent> {
+        let source = &mut self.sources[idx];
+        match event {
+            ClockEvent::BpmChanged(bpm) | ClockEvent::Sync { bpm, .. } => {
+                source.bpm = Some(bpm);
+            }
+            _ => {}
+        }
+        // Song position only arrives while the source is stopped, so it isn't taken as a sign of
+        // life. Stops are, as MIDI clocks keep pulsing while their transport is stopped
+        let ticking = match event {
+            ClockEvent::SongPosition(_) => false,
+            _ => true,
+        };
+        if ticking {
+            let silence = match source.bpm {
+                Some(bpm) => Duration::from_secs_f64(30.0 / bpm).max(MIN_SILENCE),
+                None => DEFAULT_SILENCE,
+            };
+            source.ticking_until = Some(now + silence);
+        }
+
+        let mut events = self.select_active_source(now);
+        if self.active_idx == Some(idx) {
+            events.push(ClockSourceEvent::Clock(event));
+        }
+        events
+    }
+    fn select_active_source(&mut self, now: Instant) -> Vec<ClockSourceEvent> {
+        let active_idx = self.sources.iter().position(|source| {
+            source
+                .ticking_until
+                .map(|ticking_until| ticking_until > now)
+                .unwrap_or(false)
+        });
+
+        if active_idx == self.active_idx {
+            return vec![];
+        }
+        self.active_idx = active_idx;
+
+        let name = active_idx.map(|idx| self.sources[idx].name.clone());
+        match name.as_ref() {
+            Some(name) => println!("clock source: {}", name),
+            None => println!("clock source: tap tempo"),
+        }
+        vec![ClockSourceEvent::ActiveSourceChanged(name)]
+    }
+}
+
+/// Follows every source at once, passing on the events of the highest priority source that is
+/// ticking. When a source goes quiet for half a beat the next one takes over, falling back to
+/// tapped tempo when none are left
+pub fn clock_source_events(sources: Vec<ClockSource>) -> impl Stream<Item = ClockSourceEvent> {
+    enum SelectorEvent {
+        Source(usize, ClockEvent),
+        Check,
+    }
+
+    let mut selector = ClockSourceSelector::new(sources.iter().map(ClockSource::name));
+
+    let source_events = sources.into_iter().enumerate().map(|(idx, source)| {
+        source
+            .events
+            .map(move |event| SelectorEvent::Source(idx, event))
+            .boxed()
+    });
+    let checks = tick_stream(Time::system(), FAILOVER_CHECK_INTERVAL)
+        .map(|()| SelectorEvent::Check)
+        .boxed();
+
+    stream::select_all(source_events.chain(std::iter::once(checks))).flat_map(move |event| {
+        let now = Instant::now();
+        stream::iter(match event {
+            SelectorEvent::Source(idx, event) => selector.source_event(idx, event, now),
+            SelectorEvent::Check => selector.select_active_source(now),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roller_protocol::clock::Beats;
+
+    fn active_source(events: &[ClockSourceEvent]) -> Option<Option<&str>> {
+        events.iter().find_map(|event| match event {
+            ClockSourceEvent::ActiveSourceChanged(name) => Some(name.as_deref()),
+            _ => None,
+        })
+    }
+
+    fn passed_on(events: &[ClockSourceEvent]) -> bool {
+        events
+            .iter()
+            .any(|event| matches!(event, ClockSourceEvent::Clock(_)))
+    }
+
+    #[test]
+    fn the_highest_priority_ticking_source_wins() {
+        let mut selector = ClockSourceSelector::new(vec!["MIDI", "Link"]);
+        let start = Instant::now();
+
+        let events = selector.source_event(1, ClockEvent::BpmChanged(120.0), start);
+        assert_eq!(active_source(&events), Some(Some("Link")));
+        assert!(passed_on(&events));
+
+        let now = start + Duration::from_millis(10);
+        let events = selector.source_event(0, ClockEvent::BpmChanged(128.0), now);
+        assert_eq!(active_source(&events), Some(Some("MIDI")));
+        assert!(passed_on(&events));
+
+        let now = start + Duration::from_millis(20);
+        let events = selector.source_event(1, ClockEvent::BpmChanged(120.0), now);
+        assert_eq!(active_source(&events), None);
+        assert!(!passed_on(&events));
+    }
+
+    #[test]
+    fn sources_fail_over_within_half_a_beat() {
+        let mut selector = ClockSourceSelector::new(vec!["MIDI", "Link"]);
+        let start = Instant::now();
+        let half_a_beat = Duration::from_millis(500);
+
+        selector.source_event(0, ClockEvent::BpmChanged(60.0), start);
+        for millis in (0..=1000).step_by(100) {
+            let now = start + Duration::from_millis(millis);
+            selector.source_event(1, ClockEvent::BpmChanged(120.0), now);
+
+            let expected = if now < start + half_a_beat {
+                Some(0)
+            } else {
+                Some(1)
+            };
+            assert_eq!(selector.active_idx, expected, "after {}ms", millis);
+        }
+    }
+
+    #[test]
+    fn song_positions_arent_a_sign_of_life() {
+        let mut selector = ClockSourceSelector::new(vec!["MIDI"]);
+
+        let events =
+            selector.source_event(0, ClockEvent::SongPosition(Beats::new(0.0)), Instant::now());
+        assert_eq!(active_source(&events), None);
+        assert!(!passed_on(&events));
+    }
+
+    #[test]
+    fn silent_sources_fall_back_to_tap_tempo() {
+        let mut selector = ClockSourceSelector::new(vec!["MIDI", "Link"]);
+        let start = Instant::now();
+
+        selector.source_event(0, ClockEvent::BpmChanged(120.0), start);
+        selector.source_event(1, ClockEvent::BpmChanged(120.0), start);
+        assert!(selector
+            .select_active_source(start + MIN_SILENCE / 2)
+            .is_empty());
+
+        let events = selector.select_active_source(start + MIN_SILENCE);
+        assert_eq!(active_source(&events), Some(None));
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::VecDeque;
+
+// Fitting over a beat's worth of pulses smooths USB jitter, while still following tempo ramps
+// within a beat
+const WINDOW_BEATS: usize = 1;
+// Pulses this close together are treated as a glitch, rather than a tempo change
+const MIN_PULSE_RATIO: f64 = 0.5;
+// Gaps of up to this many pulses are assumed to be dropped pulses, longer gaps mean the source
+// stopped sending
+const MAX_MISSED_PULSES: u64 = 8;
+
+/// An estimate of a pulse train's tempo and phase, in the tracker's time base
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoEstimate {
+    pub secs_per_pulse: f64,
+    pub pulses_per_beat: usize,
+    pub last_pulse_index: u64,
+    // Fitted time of the last pulse, with jitter smoothed out
+    pub last_pulse_at: f64,
+    // 1 when pulses are evenly spaced over a full window, falling towards 0 with fewer pulses
+    // or as jitter approaches half a pulse
+    pub confidence: f64,
+}
+impl TempoEstimate {
+    pub fn bpm(&self) -> f64 {
+        60.0 / (self.secs_per_pulse * self.pulses_per_beat as f64)
+    }
+    /// Fitted time of any pulse, past or predicted
+    pub fn pulse_at(&self, pulse_index: u64) -> f64 {
+        self.last_pulse_at
+            + (pulse_index as f64 - self.last_pulse_index as f64) * self.secs_per_pulse
+    }
+}
+
+/// Tracks the tempo and phase of a clock pulse train, such as MIDI clock, by fitting a line
+/// through the most recent pulses
+#[derive(Debug, Clone)]
+pub struct PulseTracker {
+    pulses_per_beat: usize,
+    // Pulse index and time received, in seconds from any epoch
+    window: VecDeque<(u64, f64)>,
+    next_pulse_index: u64,
+    estimate: Option<TempoEstimate>,
+}
+impl PulseTracker {
+    pub fn new(pulses_per_beat: usize) -> PulseTracker {
+        PulseTracker {
+            pulses_per_beat,
+            window: VecDeque::with_capacity(pulses_per_beat * WINDOW_BEATS),
+            next_pulse_index: 0,
+            estimate: None,
+        }
+    }
+    /// Forgets the tempo, the next pulse is numbered `next_pulse_index`
+    pub fn reset(&mut self, next_pulse_index: u64) {
+        self.window.clear();
+        self.next_pulse_index = next_pulse_index;
+        self.estimate = None;
+    }
+    pub fn pulse(&mut self, at: f64) -> Option<&TempoEstimate> {
+        if let (Some(estimate), Some((_, last_at))) = (self.estimate, self.window.back().copied()) {
+            let pulses_since_last = (at - last_at) / estimate.secs_per_pulse;
+
+            if pulses_since_last < MIN_PULSE_RATIO {
+                return self.estimate.as_ref();
+            } else if pulses_since_last.round() as u64 > MAX_MISSED_PULSES {
+                // Phase can't be trusted across a gap this long, start over from here
+                self.window.clear();
+                self.estimate = None;
+            } else {
+                // Number the pulse as if the ones we missed had arrived
+                self.next_pulse_index += pulses_since_last.round() as u64 - 1;
+            }
+        }
+
+        if self.window.len() == self.pulses_per_beat * WINDOW_BEATS {
+            self.window.pop_front();
+        }
+        self.window.push_back((self.next_pulse_index, at));
+        self.next_pulse_index += 1;
+
+        self.estimate = self.fit();
+        self.estimate.as_ref()
+    }
+    fn fit(&self) -> Option<TempoEstimate> {
+        if self.window.len() < 3 {
+            return None;
+        }
+
+        let n = self.window.len() as f64;
+        let mean_index = self.window.iter().map(|(idx, _)| *idx as f64).sum::<f64>() / n;
+        let mean_at = self.window.iter().map(|(_, at)| at).sum::<f64>() / n;
+
+        let (covariance, variance) =
+            self.window
+                .iter()
+                .fold((0.0, 0.0), |(covariance, variance), (idx, at)| {
+                    let x = *idx as f64 - mean_index;
+                    (covariance + x * (at - mean_at), variance + x * x)
+                });
+        let secs_per_pulse = covariance / variance;
+        if secs_per_pulse <= 0.0 {
+            return None;
+        }
+
+        let fitted_at = |idx: u64| mean_at + (idx as f64 - mean_index) * secs_per_pulse;
+        let residual_rms = (self
+            .window
+            .iter()
+            .map(|(idx, at)| (at - fitted_at(*idx)).powi(2))
+            .sum::<f64>()
+            / n)
+            .sqrt();
+
+        let fill = n / (self.pulses_per_beat * WINDOW_BEATS) as f64;
+        let steadiness = (1.0 - residual_rms / secs_per_pulse / 0.5).max(0.0);
+        let (last_pulse_index, _) = *self.window.back().unwrap();
+
+        Some(TempoEstimate {
+            secs_per_pulse,
+            pulses_per_beat: self.pulses_per_beat,
+            last_pulse_index,
+            last_pulse_at: fitted_at(last_pulse_index),
+            confidence: fill * steadiness,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const PPQN: usize = 24;
+
+    // Exact and received pulse times for a tempo that may change over time, with uniform jitter
+    // of up to `jitter_secs` either side
+    fn pulse_train(
+        pulses: usize,
+        jitter_secs: f64,
+        bpm_at: impl Fn(usize) -> f64,
+    ) -> Vec<(f64, f64)> {
+        let mut rng = StdRng::seed_from_u64(24);
+        let mut at = 0.0;
+
+        (0..pulses)
+            .map(|idx| {
+                let exact_at = at;
+                at += 60.0 / bpm_at(idx) / PPQN as f64;
+                let jitter = if jitter_secs > 0.0 {
+                    rng.gen_range(-jitter_secs, jitter_secs)
+                } else {
+                    0.0
+                };
+                (exact_at, exact_at + jitter)
+            })
+            .collect()
+    }
+
+    // Feeds the received pulse times through the tracker, returning the last estimate
+    fn track(tracker: &mut PulseTracker, pulses: &[(f64, f64)]) -> Option<TempoEstimate> {
+        pulses
+            .iter()
+            .map(|(_, at)| tracker.pulse(*at).copied())
+            .last()
+            .flatten()
+    }
+
+    #[test]
+    fn smooths_jitter() {
+        let mut tracker = PulseTracker::new(PPQN);
+        let estimate = track(&mut tracker, &pulse_train(PPQN * 8, 0.002, |_| 120.0)).unwrap();
+
+        assert!((estimate.bpm() - 120.0).abs() < 0.5, "{}", estimate.bpm());
+        assert!(estimate.confidence > 0.8, "{}", estimate.confidence);
+    }
+
+    #[test]
+    fn keeps_phase() {
+        let pulses = pulse_train(PPQN * 9, 0.002, |_| 128.0);
+        let mut tracker = PulseTracker::new(PPQN);
+        let estimate = track(&mut tracker, &pulses[..PPQN * 8]).unwrap();
+
+        // The next downbeat is predicted within a millisecond and a half, despite each pulse
+        // being off by up to two
+        let (exact_at, _) = pulses[PPQN * 8];
+        let predicted_at = estimate.pulse_at((PPQN * 8) as u64);
+        assert!(
+            (predicted_at - exact_at).abs() < 0.0015,
+            "{}",
+            predicted_at - exact_at
+        );
+    }
+
+    #[test]
+    fn follows_tempo_ramps() {
+        // 120 to 130 bpm over 8 beats, then holding
+        let bpm_at = |idx: usize| 120.0 + 10.0 * (idx as f64 / (PPQN * 8) as f64).min(1.0);
+        let mut tracker = PulseTracker::new(PPQN);
+        let mut last_estimate = None;
+
+        for (idx, (_, at)) in pulse_train(PPQN * 12, 0.001, bpm_at)
+            .into_iter()
+            .enumerate()
+        {
+            last_estimate = tracker.pulse(at).copied();
+
+            // Once a full window has been seen, the estimate lags the ramp by half a window
+            if idx >= PPQN && idx % 6 == 0 {
+                let bpm = last_estimate.unwrap().bpm();
+                assert!((bpm - bpm_at(idx)).abs() < 1.0, "{} {}", idx, bpm);
+            }
+        }
+
+        let bpm = last_estimate.unwrap().bpm();
+        assert!((bpm - 130.0).abs() < 0.2, "{}", bpm);
+    }
+
+    #[test]
+    fn numbers_dropped_pulses() {
+        let pulses: Vec<_> = pulse_train(PPQN * 4, 0.001, |_| 120.0)
+            .into_iter()
+            .enumerate()
+            .filter(|(idx, _)| idx % 17 != 5 && idx % 17 != 6)
+            .map(|(_, pulse)| pulse)
+            .collect();
+        let mut tracker = PulseTracker::new(PPQN);
+        let estimate = track(&mut tracker, &pulses).unwrap();
+
+        assert_eq!(estimate.last_pulse_index, (PPQN * 4 - 1) as u64);
+        assert!((estimate.bpm() - 120.0).abs() < 0.5, "{}", estimate.bpm());
+    }
+
+    #[test]
+    fn ignores_doubled_pulses() {
+        // Once there's a tempo to compare against, every pulse arrives twice
+        let pulses: Vec<_> = pulse_train(PPQN * 2, 0.0, |_| 120.0)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(idx, (exact_at, at))| {
+                let doubled = if idx >= PPQN {
+                    Some((exact_at, at + 0.0005))
+                } else {
+                    None
+                };
+                std::iter::once((exact_at, at)).chain(doubled)
+            })
+            .collect();
+        let mut tracker = PulseTracker::new(PPQN);
+        let estimate = track(&mut tracker, &pulses).unwrap();
+
+        assert_eq!(estimate.last_pulse_index, (PPQN * 2 - 1) as u64);
+        assert!((estimate.bpm() - 120.0).abs() < 0.01, "{}", estimate.bpm());
+    }
+
+    #[test]
+    fn restarts_after_gaps() {
+        let mut tracker = PulseTracker::new(PPQN);
+        track(&mut tracker, &pulse_train(PPQN, 0.0, |_| 120.0));
+
+        // The source stopped for a couple of seconds
+        let resumed: Vec<_> = pulse_train(PPQN, 0.0, |_| 90.0)
+            .into_iter()
+            .map(|(exact_at, at)| (exact_at + 3.0, at + 3.0))
+            .collect();
+        let bpm = track(&mut tracker, &resumed).unwrap().bpm();
+
+        assert!((bpm - 90.0).abs() < 0.01, "{}", bpm);
+    }
+
+    #[test]
+    fn confidence_falls_with_jitter() {
+        let confidence = |jitter_secs| {
+            let mut tracker = PulseTracker::new(PPQN);
+            track(&mut tracker, &pulse_train(PPQN * 4, jitter_secs, |_| 120.0))
+                .unwrap()
+                .confidence
+        };
+
+        assert!(confidence(0.0) > 0.99);
+        assert!(confidence(0.001) > confidence(0.004));
+        assert!(confidence(0.004) > confidence(0.008));
+    }
+
+    #[test]
+    fn confidence_grows_with_pulses() {
+        let mut tracker = PulseTracker::new(PPQN);
+        let confidences: Vec<_> = pulse_train(PPQN, 0.0, |_| 120.0)
+            .into_iter()
+            .filter_map(|(_, at)| tracker.pulse(at).map(|estimate| estimate.confidence))
+            .collect();
+
+        assert!(confidences.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(confidences.last().unwrap() > &0.99);
+    }
+
+    #[test]
+    fn reset_renumbers_pulses() {
+        let mut tracker = PulseTracker::new(PPQN);
+        track(&mut tracker, &pulse_train(PPQN, 0.0, |_| 120.0));
+
+        tracker.reset(96);
+        let restarted: Vec<_> = pulse_train(4, 0.0, |_| 120.0)
+            .into_iter()
+            .map(|(exact_at, at)| (exact_at + 10.0, at + 10.0))
+            .collect();
+        assert!(track(&mut tracker, &restarted[..2]).is_none());
+        assert_eq!(
+            track(&mut tracker, &restarted[2..])
+                .unwrap()
+                .last_pulse_index,
+            99
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
 use std::hash::{Hash, Hasher};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
 use roller_protocol::{
-    clock::Rate,
+    clock::{Beats, Quantization, Rate},
     color::Color,
     control::{ButtonCoordinate, ButtonGridLocation, ButtonState, InputEvent, NoteState},
     effect::{ColorEffect, DimmerEffect, PixelEffect, PositionEffect},
@@ -25,6 +26,9 @@ lazy_static::lazy_static! {
     static ref TOGGLE_FIXTURE_GROUP_ID: ButtonGroupId = ButtonGroupId::new();
 }
 
+// How far the tempo moves for each press of the BPM up and down buttons
+const BPM_ADJUSTMENT: f64 = 0.1;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd)]
 pub struct ButtonGroupId(usize);
 impl ButtonGroupId {
@@ -57,7 +61,7 @@ impl GroupToggleState {
 }
 
 // Buttons are used for configurable, creative controls. activating colors, chases, etc
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ButtonAction {
     UpdateGlobalColor(Color),
     UpdateGlobalSecondaryColor(Color),
@@ -66,9 +70,11 @@ pub enum ButtonAction {
     ActivateColorEffect(ColorEffect),
     ActivatePixelEffect(PixelEffect),
     ActivatePositionEffect(PositionEffect),
+    // A named effect from the project's effect library, of any kind
+    ActivateEffect(String),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ButtonType {
     // Once enabled, this button, or a button in its group, must stay on)
     Switch,
@@ -78,11 +84,14 @@ pub enum ButtonType {
     Flash,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ButtonMapping {
     pub label: String,
     pub coordinate: ButtonCoordinate,
     pub on_action: ButtonAction,
+    // Overrides the engine's launch quantization for this button
+    #[serde(default)]
+    pub quantization: Option<Quantization>,
 }
 impl ButtonMapping {
     pub fn into_group(self, button_type: ButtonType) -> ButtonGroup {
@@ -91,14 +100,26 @@ impl ButtonMapping {
 }
 
 // Meta buttons are global controls for things like tap tempo, changing page, activating a bank
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MetaButtonAction {
     EnableShiftMode,
     DisableShiftMode,
     TapTempo,
+    // Marks now as the first beat, for when the tempo is right but the beat is off
+    ResyncBeat,
+    // Makes the nearest beat the first of a bar, so bar length effects start on the one
+    SetDownbeat,
+    // Shifts the beat forward, or back when negative, by a fraction of a beat
+    NudgePhase(Beats),
+    IncreaseBpm,
+    DecreaseBpm,
+    HalveBpm,
+    DoubleBpm,
     UpdateClockRate(Rate),
+    UpdateLaunchQuantization(Quantization),
     SelectScene(SceneId),
     SelectFixtureGroupControl(FixtureGroupId),
+    SelectPalette(String),
 }
 impl MetaButtonAction {
     pub fn control_event(&self, now: Instant) -> ControlEvent {
@@ -110,25 +131,43 @@ impl MetaButtonAction {
                 ControlEvent::UpdateControlMode(ControlMode::Normal)
             }
             MetaButtonAction::TapTempo => ControlEvent::TapTempo(now),
+            MetaButtonAction::ResyncBeat => ControlEvent::ResyncBeat,
+            MetaButtonAction::SetDownbeat => ControlEvent::SetDownbeat,
+            MetaButtonAction::NudgePhase(beats) => ControlEvent::NudgePhase(*beats),
+            MetaButtonAction::IncreaseBpm => ControlEvent::AdjustBpm(BPM_ADJUSTMENT),
+            MetaButtonAction::DecreaseBpm => ControlEvent::AdjustBpm(-BPM_ADJUSTMENT),
+            MetaButtonAction::HalveBpm => ControlEvent::ScaleBpm(0.5),
+            MetaButtonAction::DoubleBpm => ControlEvent::ScaleBpm(2.0),
             MetaButtonAction::UpdateClockRate(rate) => ControlEvent::UpdateClockRate(*rate),
+            MetaButtonAction::UpdateLaunchQuantization(quantization) => {
+                ControlEvent::UpdateLaunchQuantization(*quantization)
+            }
             MetaButtonAction::SelectScene(scene_id) => ControlEvent::SelectScene(*scene_id),
             MetaButtonAction::SelectFixtureGroupControl(group_id) => {
                 ControlEvent::SelectFixtureGroupControl(*group_id)
             }
+            MetaButtonAction::SelectPalette(name) => ControlEvent::SelectPalette(name.clone()),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MetaButtonMapping {
     pub location: ButtonGridLocation,
     pub coordinate: ButtonCoordinate,
     pub on_action: MetaButtonAction,
+    #[serde(default)]
     pub off_action: Option<MetaButtonAction>,
+    #[serde(default)]
+    pub label: Option<String>,
+    // Overrides the engine's launch quantization, for scene buttons
+    #[serde(default)]
+    pub quantization: Option<Quantization>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ButtonGroup {
+    #[serde(skip, default = "ButtonGroupId::new")]
     id: ButtonGroupId,
     pub button_type: ButtonType,
     pub buttons: Vec<ButtonMapping>,
@@ -196,7 +235,7 @@ impl<'a> ButtonRef<'a> {
     pub fn label(&self) -> &'a str {
         match self {
             ButtonRef::Standard(_, button) => &button.label,
-            ButtonRef::Meta(_) => "",
+            ButtonRef::Meta(mapping) => mapping.label.as_deref().unwrap_or(""),
         }
     }
     pub fn location(&self) -> ButtonGridLocation {
@@ -217,15 +256,14 @@ impl<'a> ButtonRef<'a> {
             ButtonRef::Meta(_) => ButtonType::Switch,
         }
     }
-    pub fn into_control_event(
-        self,
-        note_state: NoteState,
-        now: Instant,
-    ) -> Option<ControlEvent<'a>> {
+    pub fn into_control_event(self, note_state: NoteState, now: Instant) -> Option<ControlEvent> {
         match (self, note_state) {
-            (ButtonRef::Standard(group, button), _) => {
-                Some(ControlEvent::UpdateButton(group, button, note_state, now))
-            }
+            (ButtonRef::Standard(group, button), _) => Some(ControlEvent::UpdateButton(
+                group.clone(),
+                button.clone(),
+                note_state,
+                now,
+            )),
             (ButtonRef::Meta(meta_button), NoteState::On) => {
                 Some(meta_button.on_action.control_event(now))
             }
@@ -1,13 +1,61 @@
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use thiserror::Error;
 
-use roller_protocol::control::{ButtonCoordinate, ButtonGridLocation, FaderId};
+use roller_protocol::{
+    color::{Color, ColorPalette},
+    control::{ButtonCoordinate, ButtonGridLocation, FaderId},
+    fixture::FixtureGroupId,
+};
 
-use crate::control::{
-    button::{ButtonGroup, ButtonMapping, ButtonRef, MetaButtonMapping},
-    fader::FaderControlMapping,
+use crate::{
+    control::{
+        button::{
+            ButtonAction, ButtonGroup, ButtonMapping, ButtonRef, MetaButtonAction,
+            MetaButtonMapping,
+        },
+        fader::{FaderControlMapping, FaderType},
+    },
+    effect_library::EffectLibrary,
+    lighting_engine::SceneId,
 };
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Error)]
+pub enum ControlMappingError {
+    #[error("fader {0:?} is mapped more than once")]
+    DuplicateFader(FaderId),
+    #[error("button {1} on {0:?} is mapped more than once")]
+    DuplicateButton(ButtonGridLocation, ButtonCoordinate),
+    #[error("button label \"{0}\" is used more than once")]
+    DuplicateButtonLabel(String),
+    #[error("fixture group {0:?} is not defined in the project")]
+    UndefinedFixtureGroup(FixtureGroupId),
+    #[error("scene {0:?} is not defined in the project")]
+    UndefinedScene(SceneId),
+    #[error("palette \"{0}\" is not defined in the project")]
+    UndefinedPalette(String),
+    #[error("palette color \"{0}\" is not defined in palette \"{1}\"")]
+    UndefinedPaletteColor(String, String),
+    #[error("palette color \"{0}\" is used, but the project has no palettes")]
+    NoPalettes(String),
+    #[error("effect \"{0}\" is not defined in the effect library")]
+    UndefinedEffect(String),
+}
+
+// The on-disk layout of a control mapping, which mirrors `ControlMapping::new`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ControlMappingData {
+    #[serde(default)]
+    faders: Vec<FaderControlMapping>,
+    #[serde(default)]
+    button_groups: Vec<ButtonGroup>,
+    #[serde(default)]
+    meta_buttons: Vec<MetaButtonMapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "ControlMappingData", into = "ControlMappingData")]
 pub struct ControlMapping {
     pub faders: FxHashMap<FaderId, FaderControlMapping>,
     pub button_groups: Vec<ButtonGroup>,
@@ -49,9 +97,266 @@ impl ControlMapping {
                 .map(|meta_button| ButtonRef::Meta(meta_button))
         }
     }
+    /// Labels identify buttons in saved shows, as they survive coordinates being remapped
+    pub fn find_button_by_label(&self, label: &str) -> Option<(&ButtonGroup, &ButtonMapping)> {
+        self.group_buttons()
+            .find(|(_, button)| button.label == label)
+    }
     pub fn button_refs(&self) -> impl Iterator<Item = ButtonRef<'_>> {
         self.group_buttons()
             .map(ButtonRef::from)
             .chain(self.meta_buttons.values().map(ButtonRef::from))
     }
+    /// Checks that button labels are unique, and that every fixture group, scene, palette and
+    /// named effect the mapping refers to exists. Palette colors must be in every palette, as
+    /// any of them may be switched to
+    pub fn validate(
+        &self,
+        fixture_group_ids: &[FixtureGroupId],
+        scene_ids: &[SceneId],
+        palettes: &[ColorPalette],
+        effect_library: &EffectLibrary,
+    ) -> Result<(), ControlMappingError> {
+        self.validate_effects(effect_library)?;
+
+        let palette_colors =
+            self.group_buttons()
+                .filter_map(|(_, button)| match &button.on_action {
+                    ButtonAction::UpdateGlobalColor(Color::Palette(name))
+                    | ButtonAction::UpdateGlobalSecondaryColor(Color::Palette(name)) => Some(name),
+                    _ => None,
+                });
+        for name in palette_colors {
+            if palettes.is_empty() {
+                return Err(ControlMappingError::NoPalettes(name.clone()));
+            }
+            if let Some(palette) = palettes
+                .iter()
+                .find(|palette| palette.entry(name).is_none())
+            {
+                return Err(ControlMappingError::UndefinedPaletteColor(
+                    name.clone(),
+                    palette.name.clone(),
+                ));
+            }
+        }
+
+        // Shows and cues find buttons by label
+        let mut labels: Vec<_> = self
+            .group_buttons()
+            .map(|(_, button)| button.label.as_str())
+            .collect();
+        labels.sort_unstable();
+        if let Some(pair) = labels.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(ControlMappingError::DuplicateButtonLabel(
+                pair[0].to_owned(),
+            ));
+        }
+
+        let fader_group_ids = self
+            .faders
+            .values()
+            .filter_map(|fader| match fader.fader_type {
+                FaderType::GroupDimmer(group_id) => Some(group_id),
+                _ => None,
+            });
+        let meta_actions = self.meta_buttons.values().flat_map(|meta_button| {
+            std::iter::once(&meta_button.on_action).chain(meta_button.off_action.as_ref())
+        });
+
+        for group_id in
+            fader_group_ids.chain(meta_actions.clone().filter_map(|action| match action {
+                MetaButtonAction::SelectFixtureGroupControl(group_id) => Some(*group_id),
+                _ => None,
+            }))
+        {
+            if !fixture_group_ids.contains(&group_id) {
+                return Err(ControlMappingError::UndefinedFixtureGroup(group_id));
+            }
+        }
+
+        for action in meta_actions {
+            match action {
+                MetaButtonAction::SelectScene(scene_id) if !scene_ids.contains(scene_id) => {
+                    return Err(ControlMappingError::UndefinedScene(*scene_id));
+                }
+                MetaButtonAction::SelectPalette(name)
+                    if !palettes.iter().any(|palette| &palette.name == name) =>
+                {
+                    return Err(ControlMappingError::UndefinedPalette(name.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+    /// Checks that every named effect the mapping refers to is in `effect_library`
+    pub fn validate_effects(
+        &self,
+        effect_library: &EffectLibrary,
+    ) -> Result<(), ControlMappingError> {
+        for (_, button) in self.group_buttons() {
+            if let ButtonAction::ActivateEffect(name) = &button.on_action {
+                if !effect_library.contains(name) {
+                    return Err(ControlMappingError::UndefinedEffect(name.clone()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+impl TryFrom<ControlMappingData> for ControlMapping {
+    type Error = ControlMappingError;
+
+    fn try_from(data: ControlMappingData) -> Result<ControlMapping, ControlMappingError> {
+        let mut fader_ids = FxHashSet::default();
+        for fader in data.faders.iter() {
+            if !fader_ids.insert(fader.id) {
+                return Err(ControlMappingError::DuplicateFader(fader.id));
+            }
+        }
+
+        let group_buttons = data
+            .button_groups
+            .iter()
+            .flat_map(|group| group.buttons.iter())
+            .map(|button| (ButtonGridLocation::Main, button.coordinate));
+        let meta_buttons = data
+            .meta_buttons
+            .iter()
+            .map(|meta_button| (meta_button.location, meta_button.coordinate));
+
+        let mut buttons = FxHashSet::default();
+        for (location, coordinate) in group_buttons.chain(meta_buttons) {
+            if !buttons.insert((location, coordinate)) {
+                return Err(ControlMappingError::DuplicateButton(location, coordinate));
+            }
+        }
+
+        Ok(ControlMapping::new(
+            data.faders,
+            data.button_groups,
+            data.meta_buttons,
+        ))
+    }
+}
+impl From<ControlMapping> for ControlMappingData {
+    fn from(mapping: ControlMapping) -> ControlMappingData {
+        let mut faders: Vec<_> = mapping.faders.into_iter().map(|(_, fader)| fader).collect();
+        faders.sort_by_key(|fader| fader.id);
+
+        let mut meta_buttons: Vec<_> = mapping
+            .meta_buttons
+            .into_iter()
+            .map(|(_, meta_button)| meta_button)
+            .collect();
+        meta_buttons
+            .sort_by_key(|meta_button| (meta_button.location.css_name(), meta_button.coordinate));
+
+        ControlMappingData {
+            faders,
+            button_groups: mapping.button_groups,
+            meta_buttons,
+        }
+    }
+}
+
+/// Loads a `.ron` or `.toml` control mapping, depending on the file extension
+pub async fn load_control_mapping(
+    path: impl AsRef<async_std::path::Path>,
+) -> Result<ControlMapping, async_std::io::Error> {
+    let path = path.as_ref();
+    let control_mapping_contents = async_std::fs::read(path).await?;
+
+    let control_mapping = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("ron") => ron::de::from_bytes(&control_mapping_contents)
+            .map_err(|err| async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, err))?,
+        Some("toml") => toml::from_slice(&control_mapping_contents)?,
+        _ => {
+            return Err(async_std::io::Error::new(
+                async_std::io::ErrorKind::InvalidInput,
+                format!(
+                    "control mapping {} is not a .ron or .toml file",
+                    path.display()
+                ),
+            ))
+        }
+    };
+
+    Ok(control_mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::default_control_mapping;
+
+    fn validate(control_mapping: &ControlMapping) -> Result<(), ControlMappingError> {
+        control_mapping.validate(
+            &(1..=3).map(FixtureGroupId::new).collect::<Vec<_>>(),
+            &(1..=4).map(SceneId::new).collect::<Vec<_>>(),
+            &[],
+            &EffectLibrary::default(),
+        )
+    }
+
+    #[test]
+    fn the_built_in_mapping_is_valid_for_its_groups() {
+        assert!(validate(&default_control_mapping()).is_ok());
+    }
+
+    #[test]
+    fn button_labels_must_be_unique() {
+        let mut control_mapping = default_control_mapping();
+        let label = control_mapping.button_groups[0].buttons[0].label.clone();
+        control_mapping.button_groups[1].buttons[0].label = label.clone();
+
+        assert!(matches!(
+            validate(&control_mapping),
+            Err(ControlMappingError::DuplicateButtonLabel(duplicate)) if duplicate == label
+        ));
+    }
+
+    #[test]
+    fn palette_colors_must_be_in_every_palette() {
+        use roller_protocol::color::{ColorValue, PaletteColor};
+
+        let palette = |name: &str, colors: &[&str]| ColorPalette {
+            name: name.to_owned(),
+            colors: colors
+                .iter()
+                .map(|color| PaletteColor {
+                    name: (*color).to_owned(),
+                    color: ColorValue::Kelvin(3200.0.into()),
+                })
+                .collect(),
+        };
+        let mut control_mapping = default_control_mapping();
+        control_mapping.button_groups[0].buttons[0].on_action =
+            ButtonAction::UpdateGlobalColor(Color::Palette("Brand".to_owned()));
+        let validate = |palettes: &[ColorPalette]| {
+            control_mapping.validate(
+                &(1..=3).map(FixtureGroupId::new).collect::<Vec<_>>(),
+                &(1..=4).map(SceneId::new).collect::<Vec<_>>(),
+                palettes,
+                &EffectLibrary::default(),
+            )
+        };
+
+        assert!(validate(&[palette("Day", &["Accent", "Brand"])]).is_ok());
+        assert!(matches!(
+            validate(&[
+                palette("Day", &["Brand"]),
+                palette("Night", &["Accent"])
+            ]),
+            Err(ControlMappingError::UndefinedPaletteColor(color, palette))
+                if color == "Brand" && palette == "Night"
+        ));
+        assert!(matches!(
+            validate(&[]),
+            Err(ControlMappingError::NoPalettes(color)) if color == "Brand"
+        ));
+    }
 }
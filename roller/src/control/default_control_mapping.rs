@@ -69,41 +69,49 @@ pub fn default_control_mapping() -> ControlMapping {
                         label: "White".to_owned(),
                         coordinate: ButtonCoordinate::new(0, 7),
                         on_action: ButtonAction::UpdateGlobalColor(Color::White),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Yellow".to_owned(),
                         coordinate: ButtonCoordinate::new(0, 6),
                         on_action: ButtonAction::UpdateGlobalColor(Color::Yellow),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Deep Orange".to_owned(),
                         coordinate: ButtonCoordinate::new(0, 5),
                         on_action: ButtonAction::UpdateGlobalColor(Color::DeepOrange),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Red".to_owned(),
                         coordinate: ButtonCoordinate::new(0, 4),
                         on_action: ButtonAction::UpdateGlobalColor(Color::Red),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Violet".to_owned(),
                         coordinate: ButtonCoordinate::new(0, 3),
                         on_action: ButtonAction::UpdateGlobalColor(Color::Violet),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Dark Blue".to_owned(),
                         coordinate: ButtonCoordinate::new(0, 2),
                         on_action: ButtonAction::UpdateGlobalColor(Color::DarkBlue),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Teal".to_owned(),
                         coordinate: ButtonCoordinate::new(0, 1),
                         on_action: ButtonAction::UpdateGlobalColor(Color::Teal),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Green".to_owned(),
                         coordinate: ButtonCoordinate::new(0, 0),
                         on_action: ButtonAction::UpdateGlobalColor(Color::Green),
+                        quantization: None,
                     },
                 ],
             ),
@@ -115,41 +123,49 @@ pub fn default_control_mapping() -> ControlMapping {
                         label: "White (Secondary)".to_owned(),
                         coordinate: ButtonCoordinate::new(1, 7),
                         on_action: ButtonAction::UpdateGlobalSecondaryColor(Color::White),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Yellow (Secondary)".to_owned(),
                         coordinate: ButtonCoordinate::new(1, 6),
                         on_action: ButtonAction::UpdateGlobalSecondaryColor(Color::Yellow),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Deep Orange (Secondary)".to_owned(),
                         coordinate: ButtonCoordinate::new(1, 5),
                         on_action: ButtonAction::UpdateGlobalSecondaryColor(Color::DeepOrange),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Red (Secondary)".to_owned(),
                         coordinate: ButtonCoordinate::new(1, 4),
                         on_action: ButtonAction::UpdateGlobalSecondaryColor(Color::Red),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Violet (Secondary)".to_owned(),
                         coordinate: ButtonCoordinate::new(1, 3),
                         on_action: ButtonAction::UpdateGlobalSecondaryColor(Color::Violet),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Dark Blue (Secondary)".to_owned(),
                         coordinate: ButtonCoordinate::new(1, 2),
                         on_action: ButtonAction::UpdateGlobalSecondaryColor(Color::DarkBlue),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Teal (Secondary)".to_owned(),
                         coordinate: ButtonCoordinate::new(1, 1),
                         on_action: ButtonAction::UpdateGlobalSecondaryColor(Color::Teal),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Green (Secondary)".to_owned(),
                         coordinate: ButtonCoordinate::new(1, 0),
                         on_action: ButtonAction::UpdateGlobalSecondaryColor(Color::Green),
+                        quantization: None,
                     },
                 ],
             ),
@@ -160,6 +176,7 @@ pub fn default_control_mapping() -> ControlMapping {
                 on_action: ButtonAction::ActivateDimmerEffect(
                     DimmerModulator::new(Waveform::SineDown, Beats::new(1.0), 1.0).into(),
                 ),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -168,6 +185,7 @@ pub fn default_control_mapping() -> ControlMapping {
                 on_action: ButtonAction::ActivateDimmerEffect(
                     DimmerModulator::new(Waveform::HalfSineUp, Beats::new(1.0), 1.0).into(),
                 ),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -176,6 +194,7 @@ pub fn default_control_mapping() -> ControlMapping {
                 on_action: ButtonAction::ActivateDimmerEffect(
                     DimmerModulator::new(Waveform::HalfSineDown, Beats::new(1.0), 1.0).into(),
                 ),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -184,6 +203,7 @@ pub fn default_control_mapping() -> ControlMapping {
                 on_action: ButtonAction::ActivateDimmerEffect(
                     DimmerModulator::new(Waveform::ShortSquarePulse, Beats::new(1.0), 1.0).into(),
                 ),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             // Dimmer sequences
@@ -203,6 +223,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         Beats::new(1.0),
                     )),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -218,6 +239,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         Beats::new(1.0),
                     )),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -232,6 +254,7 @@ pub fn default_control_mapping() -> ControlMapping {
                     ],
                     None,
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -248,6 +271,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         Beats::new(1.0),
                     )),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -260,6 +284,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         Beats::new(1.0),
                     )),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -280,6 +305,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         Beats::new(2.0),
                     )),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             // Color effects
@@ -294,6 +320,7 @@ pub fn default_control_mapping() -> ControlMapping {
                     )],
                     Some(ClockOffset::new(ClockOffsetMode::GroupId, Beats::new(1.0))),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -307,6 +334,7 @@ pub fn default_control_mapping() -> ControlMapping {
                     )],
                     Some(ClockOffset::new(ClockOffsetMode::Random, Beats::new(0.5))),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             // Color sequences
@@ -324,6 +352,7 @@ pub fn default_control_mapping() -> ControlMapping {
                     ],
                     Some(ClockOffset::new(ClockOffsetMode::GroupId, Beats::new(4.0))),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -340,6 +369,7 @@ pub fn default_control_mapping() -> ControlMapping {
                     ],
                     Some(ClockOffset::new(ClockOffsetMode::Random, Beats::new(0.5))),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -370,6 +400,7 @@ pub fn default_control_mapping() -> ControlMapping {
                     ],
                     Some(ClockOffset::new(ClockOffsetMode::Random, Beats::new(2.0))),
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             ButtonMapping {
@@ -383,6 +414,7 @@ pub fn default_control_mapping() -> ControlMapping {
                     )],
                     None,
                 )),
+                quantization: None,
             }
             .into_group(ButtonType::Toggle),
             // Pixel effects
@@ -420,6 +452,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(0.1),
                             )),
                         )),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "1/2 Offset From Center".to_owned(),
@@ -435,6 +468,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(1.0),
                             )),
                         )),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "1/4 Sigmoid Wave".to_owned(),
@@ -450,6 +484,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(1.0),
                             )),
                         )),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "1/4 1.5 Root Wave".to_owned(),
@@ -472,6 +507,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(0.25),
                             )),
                         )),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "1/4 Offset 3 Up 1 Down".to_owned(),
@@ -504,6 +540,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(2.0),
                             )),
                         )),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "1/4 2 Down 1 Up".to_owned(),
@@ -528,6 +565,7 @@ pub fn default_control_mapping() -> ControlMapping {
                             ],
                             None,
                         )),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "1/8 Offset 4 Up 4 Down".to_owned(),
@@ -580,6 +618,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(0.25),
                             )),
                         )),
+                        quantization: None,
                     },
                 ],
             ),
@@ -591,6 +630,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         label: "0/0".to_owned(),
                         coordinate: ButtonCoordinate::new(3, 7),
                         on_action: ButtonAction::UpdateBasePosition((0.0, 0.0).into()),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "-15/-30 Mirrored".to_owned(),
@@ -598,6 +638,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         on_action: ButtonAction::UpdateBasePosition(
                             ((-15.0, -30.0), BasePositionMode::MirrorPan).into(),
                         ),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "30/-30 Mirrored".to_owned(),
@@ -605,6 +646,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         on_action: ButtonAction::UpdateBasePosition(
                             ((30.0, -30.0), BasePositionMode::MirrorPan).into(),
                         ),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "50/-75 Mirrored".to_owned(),
@@ -612,6 +654,7 @@ pub fn default_control_mapping() -> ControlMapping {
                         on_action: ButtonAction::UpdateBasePosition(
                             ((50.0, -75.0), BasePositionMode::MirrorPan).into(),
                         ),
+                        quantization: None,
                     },
                 ],
             ),
@@ -637,6 +680,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(4.0),
                             )),
                         )),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Movement Effect #2".to_owned(),
@@ -657,6 +701,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(2.0),
                             )),
                         )),
+                        quantization: None,
                     },
                     ButtonMapping {
                         label: "Movement Effect #3".to_owned(),
@@ -677,6 +722,7 @@ pub fn default_control_mapping() -> ControlMapping {
                                 Beats::new(8.0),
                             )),
                         )),
+                        quantization: None,
                     },
                 ],
             ),
@@ -694,78 +740,120 @@ pub fn default_control_mapping() -> ControlMapping {
                 coordinate: ButtonCoordinate::new(0, 0),
                 on_action: MetaButtonAction::TapTempo,
                 off_action: None,
+                label: None,
+                quantization: None,
+            },
+            MetaButtonMapping {
+                location: ButtonGridLocation::MetaRight,
+                coordinate: ButtonCoordinate::new(0, 1),
+                on_action: MetaButtonAction::ResyncBeat,
+                off_action: None,
+                label: None,
+                quantization: None,
+            },
+            MetaButtonMapping {
+                location: ButtonGridLocation::MetaRight,
+                coordinate: ButtonCoordinate::new(0, 2),
+                on_action: MetaButtonAction::SetDownbeat,
+                off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaBottom,
                 coordinate: ButtonCoordinate::new(0, 0),
                 on_action: MetaButtonAction::SelectFixtureGroupControl(FixtureGroupId::new(1)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaBottom,
                 coordinate: ButtonCoordinate::new(1, 0),
                 on_action: MetaButtonAction::SelectFixtureGroupControl(FixtureGroupId::new(2)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaBottom,
                 coordinate: ButtonCoordinate::new(2, 0),
                 on_action: MetaButtonAction::SelectFixtureGroupControl(FixtureGroupId::new(3)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaBottom,
                 coordinate: ButtonCoordinate::new(4, 0),
                 on_action: MetaButtonAction::SelectScene(SceneId::new(1)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaBottom,
                 coordinate: ButtonCoordinate::new(5, 0),
                 on_action: MetaButtonAction::SelectScene(SceneId::new(2)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaBottom,
                 coordinate: ButtonCoordinate::new(6, 0),
                 on_action: MetaButtonAction::SelectScene(SceneId::new(3)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaBottom,
                 coordinate: ButtonCoordinate::new(7, 0),
                 on_action: MetaButtonAction::SelectScene(SceneId::new(4)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaRight,
                 coordinate: ButtonCoordinate::new(0, 7),
                 on_action: MetaButtonAction::UpdateClockRate(Rate::new(1.0 / 3.0)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaRight,
                 coordinate: ButtonCoordinate::new(0, 6),
                 on_action: MetaButtonAction::UpdateClockRate(Rate::new(1.0 / 2.0)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaRight,
                 coordinate: ButtonCoordinate::new(0, 5),
                 on_action: MetaButtonAction::UpdateClockRate(Rate::new(1.0)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaRight,
                 coordinate: ButtonCoordinate::new(0, 4),
                 on_action: MetaButtonAction::UpdateClockRate(Rate::new(2.0)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
             MetaButtonMapping {
                 location: ButtonGridLocation::MetaRight,
                 coordinate: ButtonCoordinate::new(0, 3),
                 on_action: MetaButtonAction::UpdateClockRate(Rate::new(3.0)),
                 off_action: None,
+                label: None,
+                quantization: None,
             },
         ],
     )
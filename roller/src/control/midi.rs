@@ -1,8 +1,9 @@
 use async_std::prelude::*;
 use futures::pin_mut;
 use futures::stream::{self, StreamExt};
-use midi::{MidiEvent, MidiInput, MidiOutput, Note};
+use midi::{ControlChannel, MidiEvent, MidiInput, MidiIoError, MidiOutput, Note};
 use rustc_hash::FxHashMap;
+use serde::Deserialize;
 use std::time::{Duration, Instant};
 
 use roller_protocol::control::{
@@ -73,123 +74,275 @@ fn note_to_coordinate(note: Note) -> Option<(ButtonGridLocation, ButtonCoordinat
     }
 }
 
+/// How a controller's notes and control changes map to buttons and faders
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiControllerProfile {
+    // 8x8 pad grid with meta buttons along the right and bottom, and 9 faders
+    AkaiApcMini,
+    // A bank of `fader_count` faders on consecutive control changes, without any buttons
+    Faders { first_control: u8, fader_count: u8 },
+}
+impl MidiControllerProfile {
+    fn has_pads(&self) -> bool {
+        match self {
+            MidiControllerProfile::AkaiApcMini => true,
+            MidiControllerProfile::Faders { .. } => false,
+        }
+    }
+    fn note_to_coordinate(&self, note: Note) -> Option<(ButtonGridLocation, ButtonCoordinate)> {
+        match self {
+            MidiControllerProfile::AkaiApcMini => note_to_coordinate(note),
+            MidiControllerProfile::Faders { .. } => None,
+        }
+    }
+    fn coordinate_to_note(
+        &self,
+        location: ButtonGridLocation,
+        coordinate: ButtonCoordinate,
+    ) -> Option<Note> {
+        match self {
+            MidiControllerProfile::AkaiApcMini => {
+                let in_grid = match location {
+                    ButtonGridLocation::Main => coordinate.row_idx < 8 && coordinate.column_idx < 8,
+                    ButtonGridLocation::MetaRight => coordinate.row_idx < 8,
+                    ButtonGridLocation::MetaBottom => coordinate.column_idx < 8,
+                };
+
+                if in_grid {
+                    Some(coordinate_to_note(&location, &coordinate))
+                } else {
+                    None
+                }
+            }
+            MidiControllerProfile::Faders { .. } => None,
+        }
+    }
+    fn control_to_fader_idx(&self, control: ControlChannel) -> Option<usize> {
+        let (first_control, fader_count) = match self {
+            MidiControllerProfile::AkaiApcMini => (48, 9),
+            MidiControllerProfile::Faders {
+                first_control,
+                fader_count,
+            } => (*first_control, *fader_count),
+        };
+
+        u8::from(control)
+            .checked_sub(first_control)
+            .filter(|fader_idx| *fader_idx < fader_count)
+            .map(|fader_idx| fader_idx as usize)
+    }
+}
+impl Default for MidiControllerProfile {
+    fn default() -> MidiControllerProfile {
+        MidiControllerProfile::AkaiApcMini
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MidiControllerConfig {
+    pub name: String,
+    // Which of several devices with the same name this is, counting from 0 in the order the
+    // system lists them
+    #[serde(default)]
+    pub device_index: usize,
+    #[serde(default)]
+    pub profile: MidiControllerProfile,
+    // Where this controller sits on the control surface, so several controllers can each cover
+    // their own part of the control mapping
+    #[serde(default)]
+    pub column_offset: usize,
+    #[serde(default)]
+    pub row_offset: usize,
+    #[serde(default)]
+    pub fader_offset: usize,
+}
+impl MidiControllerConfig {
+    pub fn new(name: String) -> MidiControllerConfig {
+        MidiControllerConfig {
+            name,
+            device_index: 0,
+            profile: MidiControllerProfile::default(),
+            column_offset: 0,
+            row_offset: 0,
+            fader_offset: 0,
+        }
+    }
+    /// The device's name, numbered when it isn't the first device with that name
+    pub fn device_description(&self) -> String {
+        if self.device_index == 0 {
+            format!("\"{}\"", self.name)
+        } else {
+            format!("\"{}\" #{}", self.name, self.device_index + 1)
+        }
+    }
+    // Translates a button on the device to its position on the control surface
+    fn surface_coordinate(
+        &self,
+        location: ButtonGridLocation,
+        coordinate: ButtonCoordinate,
+    ) -> ButtonCoordinate {
+        match location {
+            ButtonGridLocation::Main => ButtonCoordinate {
+                row_idx: coordinate.row_idx + self.row_offset,
+                column_idx: coordinate.column_idx + self.column_offset,
+            },
+            ButtonGridLocation::MetaRight => ButtonCoordinate {
+                row_idx: coordinate.row_idx + self.row_offset,
+                column_idx: coordinate.column_idx,
+            },
+            ButtonGridLocation::MetaBottom => ButtonCoordinate {
+                row_idx: coordinate.row_idx,
+                column_idx: coordinate.column_idx + self.column_offset,
+            },
+        }
+    }
+    // The inverse of `surface_coordinate`, `None` for buttons outside this controller's slice
+    fn device_note(
+        &self,
+        location: ButtonGridLocation,
+        coordinate: ButtonCoordinate,
+    ) -> Option<Note> {
+        let row_offset = match location {
+            ButtonGridLocation::Main | ButtonGridLocation::MetaRight => self.row_offset,
+            ButtonGridLocation::MetaBottom => 0,
+        };
+        let column_offset = match location {
+            ButtonGridLocation::Main | ButtonGridLocation::MetaBottom => self.column_offset,
+            ButtonGridLocation::MetaRight => 0,
+        };
+
+        let coordinate = ButtonCoordinate {
+            row_idx: coordinate.row_idx.checked_sub(row_offset)?,
+            column_idx: coordinate.column_idx.checked_sub(column_offset)?,
+        };
+
+        self.profile.coordinate_to_note(location, coordinate)
+    }
+}
+
+type ButtonUpdate = (Note, AkaiPadState, Illumination);
+
 pub struct MidiController {
+    config: MidiControllerConfig,
     midi_input: MidiInput,
-    button_update_send: async_std::sync::Sender<Vec<(Note, AkaiPadState, Illumination)>>,
+    button_update_send: Option<async_std::sync::Sender<Vec<ButtonUpdate>>>,
 }
 impl MidiController {
-    pub fn new_for_device_name<'a>(name: impl Into<String>) -> Result<MidiController, ()> {
-        let name = name.into();
-        let midi_input = MidiInput::new(&name).map_err(|_| ())?;
+    pub fn new(config: MidiControllerConfig) -> Result<MidiController, MidiIoError> {
+        let midi_input = MidiInput::nth(&config.name, config.device_index)?;
 
-        let (button_update_send, button_update_recv) =
-            async_std::sync::channel::<Vec<(Note, AkaiPadState, Illumination)>>(8);
+        // Controllers without pads have no LEDs to give feedback on, and may not have an output
+        let button_update_send = if config.profile.has_pads() {
+            let midi_output = MidiOutput::nth(&config.name, config.device_index)?;
+            let (button_update_send, button_update_recv) =
+                async_std::sync::channel::<Vec<ButtonUpdate>>(64);
 
-        async_std::task::spawn(async move {
-            let started_at = Instant::now();
-            let midi_output = MidiOutput::new(&name).map_err(|_| ()).unwrap();
-            let mut current_button_strobes: FxHashMap<Note, AkaiPadState> = FxHashMap::default();
-
-            enum Event {
-                ButtonUpdates(Vec<(Note, AkaiPadState, Illumination)>),
-                Tick,
-            }
-
-            let ticks = crate::utils::tick_stream(Duration::from_millis(40)).map(|_| Event::Tick);
-            let button_updates = button_update_recv.map(Event::ButtonUpdates);
-            let events = stream::select(ticks, button_updates);
-
-            pin_mut!(events);
-
-            while let Some(event) = events.next().await {
-                match event {
-                    Event::ButtonUpdates(updates) => {
-                        for (note, state, illumination) in updates.into_iter() {
-                            match illumination {
-                                Illumination::Solid => {
-                                    current_button_strobes.remove(&note);
-                                    midi_output
-                                        .send_packet(vec![0x90, u8::from(note), state.as_byte()])
-                                        .await;
-                                }
-                                Illumination::Strobe => {
-                                    current_button_strobes.insert(note, state);
-                                }
-                            }
-                        }
-                    }
-                    Event::Tick => {
-                        for (note, state) in current_button_strobes.iter() {
-                            let state = if started_at.elapsed().as_millis() % 250 < 100 {
-                                *state
-                            } else {
-                                AkaiPadState::Off
-                            };
+            async_std::task::spawn(run_button_updates(midi_output, button_update_recv));
 
-                            midi_output
-                                .send_packet(vec![0x90, u8::from(*note), state.as_byte()])
-                                .await;
-                        }
-                    }
-                }
-            }
-        });
+            Some(button_update_send)
+        } else {
+            None
+        };
 
         Ok(MidiController {
+            config,
             midi_input,
             button_update_send,
         })
     }
     pub fn input_events(&self) -> impl Stream<Item = InputEvent> {
         // TODO this should be moved to a "control device mapping"
-        fn midi_to_input_event(midi_event: &MidiEvent) -> Option<InputEvent> {
+        fn midi_to_input_event(
+            config: &MidiControllerConfig,
+            midi_event: &MidiEvent,
+        ) -> Option<InputEvent> {
             match dbg!(midi_event) {
                 MidiEvent::ControlChange { control, value } => {
-                    let fader_id = FaderId::new((u8::from(*control) - 48) as usize);
+                    let fader_idx = config.profile.control_to_fader_idx(*control)?;
+                    let fader_id = FaderId::new(fader_idx + config.fader_offset);
                     let value = 1.0 / 127.0 * (*value as f64);
                     Some(InputEvent::FaderUpdated(fader_id, value))
                 }
                 MidiEvent::NoteOn { note, .. } => {
-                    let (loc, coord) = note_to_coordinate(*note)?;
-                    Some(InputEvent::ButtonPressed(loc, coord))
+                    let (loc, coord) = config.profile.note_to_coordinate(*note)?;
+                    Some(InputEvent::ButtonPressed(
+                        loc,
+                        config.surface_coordinate(loc, coord),
+                    ))
                 }
                 MidiEvent::NoteOff { note, .. } => {
-                    let (loc, coord) = note_to_coordinate(*note)?;
-                    Some(InputEvent::ButtonReleased(loc, coord))
+                    let (loc, coord) = config.profile.note_to_coordinate(*note)?;
+                    Some(InputEvent::ButtonReleased(
+                        loc,
+                        config.surface_coordinate(loc, coord),
+                    ))
                 }
                 _ => None,
             }
         }
 
-        self.midi_input
-            .clone()
-            .filter_map(move |midi_event| futures::future::ready(midi_to_input_event(&midi_event)))
+        let config = self.config.clone();
+        self.midi_input.clone().filter_map(move |midi_event| {
+            futures::future::ready(midi_to_input_event(&config, &midi_event))
+        })
+    }
+    fn send_button_updates(&self, updates: Vec<ButtonUpdate>) {
+        if updates.is_empty() {
+            return;
+        }
+
+        if let Some(button_update_send) = self.button_update_send.as_ref() {
+            // Never wait on a controller, if it has stalled or gone away the others carry on
+            if let Err(err) = button_update_send.try_send(updates) {
+                eprintln!(
+                    "unable to update LEDs on {}: {}",
+                    self.config.device_description(),
+                    err
+                );
+            }
+        }
     }
-    pub async fn set_button_state(
+    // Takes buttons in device coordinates, rather than control surface coordinates
+    fn set_device_button_states(
         &self,
-        location: ButtonGridLocation,
-        coordinate: ButtonCoordinate,
-        state: ButtonState,
+        states: impl IntoIterator<Item = (ButtonGridLocation, ButtonCoordinate, ButtonState)>,
     ) {
-        let note = coordinate_to_note(&location, &coordinate);
-        let (pad_color, illumination) = button_state_to_akai_pad_color(location, state);
+        self.send_button_updates(
+            states
+                .into_iter()
+                .filter_map(|(location, coordinate, state)| {
+                    let note = self
+                        .config
+                        .profile
+                        .coordinate_to_note(location, coordinate)?;
+                    let (pad_color, illumination) = button_state_to_akai_pad_color(location, state);
 
-        self.button_update_send
-            .send(vec![(note, pad_color, illumination)])
-            .await;
+                    Some((note, pad_color, illumination))
+                })
+                .collect(),
+        );
     }
     pub async fn set_button_states(
         &self,
         states: impl IntoIterator<Item = (ButtonGridLocation, ButtonCoordinate, ButtonState)>,
     ) {
-        for (location, coordinate, state) in states {
-            self.set_button_state(location, coordinate, state).await
-        }
+        self.send_button_updates(
+            states
+                .into_iter()
+                .filter_map(|(location, coordinate, state)| {
+                    let note = self.config.device_note(location, coordinate)?;
+                    let (pad_color, illumination) = button_state_to_akai_pad_color(location, state);
+
+                    Some((note, pad_color, illumination))
+                })
+                .collect(),
+        );
     }
     pub async fn reset_pads(&self) {
-        for row_idx in 0..8 {
-            for column_idx in 0..8 {
-                self.set_button_state(
+        self.set_device_button_states((0..8).flat_map(|row_idx| {
+            (0..8).map(move |column_idx| {
+                (
                     ButtonGridLocation::Main,
                     ButtonCoordinate {
                         row_idx,
@@ -197,22 +350,24 @@ impl MidiController {
                     },
                     ButtonState::Unused,
                 )
-                .await;
-            }
-        }
+            })
+        }));
     }
     pub async fn run_pad_startup(&self) {
+        if !self.config.profile.has_pads() {
+            return;
+        }
+
         for row_idx in 0..8 {
             for column_idx in 0..8 {
-                self.set_button_state(
+                self.set_device_button_states(vec![(
                     ButtonGridLocation::Main,
                     ButtonCoordinate {
                         row_idx,
                         column_idx,
                     },
                     ButtonState::Active,
-                )
-                .await;
+                )]);
                 async_std::task::sleep(Duration::from_millis(10)).await;
             }
         }
@@ -221,6 +376,62 @@ impl MidiController {
     }
 }
 
+async fn run_button_updates(
+    midi_output: MidiOutput,
+    button_update_recv: async_std::sync::Receiver<Vec<ButtonUpdate>>,
+) {
+    let started_at = Instant::now();
+    let mut current_button_strobes: FxHashMap<Note, AkaiPadState> = FxHashMap::default();
+
+    enum Event {
+        ButtonUpdates(Vec<ButtonUpdate>),
+        Tick,
+    }
+
+    let ticks = crate::utils::tick_stream(
+        roller_protocol::clock::Time::system(),
+        Duration::from_millis(40),
+    )
+    .map(|_| Event::Tick);
+    let button_updates = button_update_recv.map(Event::ButtonUpdates);
+    let events = stream::select(ticks, button_updates);
+
+    pin_mut!(events);
+
+    while let Some(event) = events.next().await {
+        match event {
+            Event::ButtonUpdates(updates) => {
+                for (note, state, illumination) in updates.into_iter() {
+                    match illumination {
+                        Illumination::Solid => {
+                            current_button_strobes.remove(&note);
+                            midi_output
+                                .send_packet(vec![0x90, u8::from(note), state.as_byte()])
+                                .await;
+                        }
+                        Illumination::Strobe => {
+                            current_button_strobes.insert(note, state);
+                        }
+                    }
+                }
+            }
+            Event::Tick => {
+                for (note, state) in current_button_strobes.iter() {
+                    let state = if started_at.elapsed().as_millis() % 250 < 100 {
+                        *state
+                    } else {
+                        AkaiPadState::Off
+                    };
+
+                    midi_output
+                        .send_packet(vec![0x90, u8::from(*note), state.as_byte()])
+                        .await;
+                }
+            }
+        }
+    }
+}
+
 enum Illumination {
     Solid,
     Strobe,
@@ -236,12 +447,117 @@ fn button_state_to_akai_pad_color(
             ButtonState::Inactive => (AkaiPadState::Yellow, Illumination::Solid),
             ButtonState::Deactivated => (AkaiPadState::Red, Illumination::Solid),
             ButtonState::Unused => (AkaiPadState::Off, Illumination::Solid),
+            ButtonState::Armed => (AkaiPadState::GreenBlink, Illumination::Solid),
         },
         ButtonGridLocation::MetaBottom | ButtonGridLocation::MetaRight => match state {
             ButtonState::Active => (AkaiPadState::Green, Illumination::Strobe),
             ButtonState::Inactive => (AkaiPadState::Yellow, Illumination::Solid),
             ButtonState::Deactivated => (AkaiPadState::Red, Illumination::Solid),
             ButtonState::Unused => (AkaiPadState::Off, Illumination::Solid),
+            ButtonState::Armed => (AkaiPadState::GreenBlink, Illumination::Solid),
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinate(row_idx: usize, column_idx: usize) -> ButtonCoordinate {
+        ButtonCoordinate {
+            row_idx,
+            column_idx,
+        }
+    }
+
+    // Two APC minis side by side, the second covering columns 8 to 15
+    fn two_apcs() -> (MidiControllerConfig, MidiControllerConfig) {
+        let controllers: Vec<MidiControllerConfig> =
+            toml::from_str::<std::collections::HashMap<String, Vec<MidiControllerConfig>>>(
+                r#"
+            [[midi_controllers]]
+            name = "APC MINI"
+
+            [[midi_controllers]]
+            name = "APC MINI"
+            device_index = 1
+            column_offset = 8
+            fader_offset = 9
+            "#,
+            )
+            .unwrap()
+            .remove("midi_controllers")
+            .unwrap();
+
+        (controllers[0].clone(), controllers[1].clone())
+    }
+
+    #[test]
+    fn identical_devices_cover_their_own_columns() {
+        let (first, second) = two_apcs();
+        let main = ButtonGridLocation::Main;
+
+        assert_eq!((first.device_index, second.device_index), (0, 1));
+        assert_eq!(second.device_description(), "\"APC MINI\" #2");
+
+        // The same pad on each device is a different button on the surface
+        assert_eq!(
+            first.surface_coordinate(main, coordinate(2, 3)),
+            coordinate(2, 3)
+        );
+        assert_eq!(
+            second.surface_coordinate(main, coordinate(2, 3)),
+            coordinate(2, 11)
+        );
+
+        // And each surface button lights on exactly one device
+        assert_eq!(first.device_note(main, coordinate(2, 11)), None);
+        assert_eq!(
+            second.device_note(main, coordinate(2, 11)),
+            Some(Note::new(19))
+        );
+        assert_eq!(
+            first.device_note(main, coordinate(2, 3)),
+            Some(Note::new(19))
+        );
+        assert_eq!(second.device_note(main, coordinate(2, 3)), None);
+    }
+
+    #[test]
+    fn meta_buttons_follow_the_offset_along_their_edge() {
+        let (first, second) = two_apcs();
+
+        // Both devices share the right hand column, while the bottom row runs across them
+        assert_eq!(
+            second.surface_coordinate(ButtonGridLocation::MetaRight, coordinate(4, 0)),
+            coordinate(4, 0)
+        );
+        assert_eq!(
+            second.surface_coordinate(ButtonGridLocation::MetaBottom, coordinate(0, 4)),
+            coordinate(0, 12)
+        );
+        assert_eq!(
+            first.device_note(ButtonGridLocation::MetaBottom, coordinate(0, 12)),
+            None
+        );
+    }
+
+    #[test]
+    fn controls_past_the_last_fader_are_ignored() {
+        let apc = MidiControllerProfile::AkaiApcMini;
+        assert_eq!(apc.control_to_fader_idx(ControlChannel::new(47)), None);
+        assert_eq!(apc.control_to_fader_idx(ControlChannel::new(48)), Some(0));
+        assert_eq!(apc.control_to_fader_idx(ControlChannel::new(56)), Some(8));
+        assert_eq!(apc.control_to_fader_idx(ControlChannel::new(57)), None);
+
+        let faders = MidiControllerProfile::Faders {
+            first_control: 0,
+            fader_count: 16,
+        };
+        assert_eq!(
+            faders.control_to_fader_idx(ControlChannel::new(15)),
+            Some(15)
+        );
+        assert_eq!(faders.control_to_fader_idx(ControlChannel::new(16)), None);
+    }
+}
@@ -3,5 +3,6 @@ pub mod control_mapping;
 mod default_control_mapping;
 pub mod fader;
 pub mod midi;
+pub mod osc;
 
 pub use default_control_mapping::default_control_mapping;
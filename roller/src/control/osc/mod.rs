@@ -0,0 +1,458 @@
+//! Control over OSC, for tablets running TouchOSC or Open Stage Control and tools like Resolume.
+//! Messages to the built-in addresses under the configured prefix, or to addresses mapped in the
+//! project, act as buttons, faders and clock controls. Button states, the beat and group colours
+//! are sent back out as they change
+use async_std::net::UdpSocket;
+use futures::stream::{self, Stream, StreamExt};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+
+use roller_protocol::{
+    clock::{Beats, ClockSnapshot},
+    color::ColorPalette,
+    control::{ButtonCoordinate, ButtonGridLocation, ButtonState, FaderId, InputEvent},
+    fixture::FixtureGroupId,
+    lighting_engine::FixtureGroupState,
+};
+
+use crate::lighting_engine::{ControlEvent, SceneId};
+
+mod packet;
+
+pub use packet::{decode_packet, OscArg, OscMessage};
+
+const MAX_PACKET_SIZE: usize = 8192;
+// Small tempo changes from clock sources aren't worth telling anyone about
+const BPM_EPSILON: f64 = 0.01;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OscConfig {
+    // Address and port control messages are received on
+    #[serde(default = "OscConfig::default_listen")]
+    pub listen: SocketAddr,
+    // Where button states, the beat and group colours are sent
+    #[serde(default)]
+    pub send_to: Vec<SocketAddr>,
+    // Prepended to the built-in addresses, like /roller/button/main/3/4
+    #[serde(default = "OscConfig::default_prefix")]
+    pub prefix: String,
+    // Addresses sent by a particular layout, mapped to the control they act as. These are used
+    // before the built-in addresses
+    #[serde(default)]
+    pub addresses: FxHashMap<String, OscControl>,
+}
+impl OscConfig {
+    fn default_listen() -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, 9000))
+    }
+    fn default_prefix() -> String {
+        "/roller".to_owned()
+    }
+}
+
+/// What a message to an address controls
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OscControl {
+    // Pressed while the first argument is over 0.5, pressed and released by messages without one
+    Button {
+        location: ButtonGridLocation,
+        column: usize,
+        row: usize,
+    },
+    // Set from the first argument, from 0 to 1
+    Fader(FaderId),
+    MasterDimmer,
+    // Triggered by messages without arguments, or with a first argument over 0.5
+    TapTempo,
+    ResyncBeat,
+    SetDownbeat,
+    SelectScene(SceneId),
+    // Shifts the beat by the first argument, in beats
+    NudgePhase,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscEvent {
+    Input(InputEvent),
+    Control(ControlEvent),
+}
+
+fn location_from_name(name: &str) -> Option<ButtonGridLocation> {
+    [
+        ButtonGridLocation::Main,
+        ButtonGridLocation::MetaRight,
+        ButtonGridLocation::MetaBottom,
+    ]
+    .iter()
+    .copied()
+    .find(|location| location.css_name() == name)
+}
+
+fn state_name(state: ButtonState) -> &'static str {
+    match state {
+        ButtonState::Active => "active",
+        ButtonState::Inactive => "inactive",
+        ButtonState::Deactivated => "deactivated",
+        ButtonState::Unused => "unused",
+        ButtonState::Armed => "armed",
+    }
+}
+
+impl OscConfig {
+    fn button_address(&self, location: ButtonGridLocation, coordinate: ButtonCoordinate) -> String {
+        format!(
+            "{}/button/{}/{}/{}",
+            self.prefix,
+            location.css_name(),
+            coordinate.column_idx,
+            coordinate.row_idx
+        )
+    }
+    fn control(&self, address: &str) -> Option<OscControl> {
+        if let Some(control) = self.addresses.get(address) {
+            return Some(control.clone());
+        }
+
+        let parts: Vec<_> = address
+            .strip_prefix(self.prefix.as_str())?
+            .strip_prefix('/')?
+            .split('/')
+            .collect();
+
+        match parts.as_slice() {
+            ["button", location, column, row] => Some(OscControl::Button {
+                location: location_from_name(location)?,
+                column: column.parse().ok()?,
+                row: row.parse().ok()?,
+            }),
+            ["fader", fader_id] => Some(OscControl::Fader(FaderId::new(fader_id.parse().ok()?))),
+            ["master"] => Some(OscControl::MasterDimmer),
+            ["tap"] => Some(OscControl::TapTempo),
+            ["resync"] => Some(OscControl::ResyncBeat),
+            ["downbeat"] => Some(OscControl::SetDownbeat),
+            ["scene", scene_id] => Some(OscControl::SelectScene(SceneId::new(
+                scene_id.parse().ok()?,
+            ))),
+            ["nudge"] => Some(OscControl::NudgePhase),
+            _ => None,
+        }
+    }
+    /// The events a message stands for, messages to unknown addresses are ignored
+    pub fn message_events(&self, message: &OscMessage) -> Vec<OscEvent> {
+        let control = match self.control(&message.address) {
+            Some(control) => control,
+            None => return vec![],
+        };
+        let value = message.args.first().and_then(OscArg::as_f64);
+        // Buttons on most layouts send 1 when pressed and 0 when released, only presses trigger
+        let triggered = value.map(|value| value > 0.5).unwrap_or(true);
+
+        match control {
+            OscControl::Button {
+                location,
+                column,
+                row,
+            } => {
+                let coordinate = ButtonCoordinate::new(column, row);
+                let pressed = InputEvent::ButtonPressed(location, coordinate);
+                let released = InputEvent::ButtonReleased(location, coordinate);
+
+                match value {
+                    Some(value) if value > 0.5 => vec![OscEvent::Input(pressed)],
+                    Some(_) => vec![OscEvent::Input(released)],
+                    None => vec![OscEvent::Input(pressed), OscEvent::Input(released)],
+                }
+            }
+            OscControl::Fader(fader_id) => value
+                .map(|value| {
+                    OscEvent::Input(InputEvent::FaderUpdated(fader_id, value.clamp(0.0, 1.0)))
+                })
+                .into_iter()
+                .collect(),
+            OscControl::MasterDimmer => value
+                .map(|value| {
+                    OscEvent::Control(ControlEvent::UpdateMasterDimmer(value.clamp(0.0, 1.0)))
+                })
+                .into_iter()
+                .collect(),
+            OscControl::NudgePhase => value
+                .map(|value| OscEvent::Control(ControlEvent::NudgePhase(Beats::new(value))))
+                .into_iter()
+                .collect(),
+            _ if !triggered => vec![],
+            OscControl::TapTempo => vec![OscEvent::Control(ControlEvent::TapTempo(Instant::now()))],
+            OscControl::ResyncBeat => vec![OscEvent::Control(ControlEvent::ResyncBeat)],
+            OscControl::SetDownbeat => vec![OscEvent::Control(ControlEvent::SetDownbeat)],
+            OscControl::SelectScene(scene_id) => {
+                vec![OscEvent::Control(ControlEvent::SelectScene(scene_id))]
+            }
+        }
+    }
+}
+
+pub struct OscController {
+    config: OscConfig,
+    socket: Arc<UdpSocket>,
+    message_send: async_std::sync::Sender<Vec<OscMessage>>,
+    last_beat: Option<i64>,
+    last_bpm: Option<f64>,
+}
+impl OscController {
+    pub async fn bind(config: OscConfig) -> io::Result<OscController> {
+        let socket = Arc::new(UdpSocket::bind(config.listen).await?);
+        let (message_send, message_recv) = async_std::sync::channel(64);
+
+        async_std::task::spawn(run_message_sends(
+            socket.clone(),
+            config.send_to.clone(),
+            message_recv,
+        ));
+
+        Ok(OscController {
+            config,
+            socket,
+            message_send,
+            last_beat: None,
+            last_bpm: None,
+        })
+    }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+    pub fn events(&self) -> impl Stream<Item = OscEvent> {
+        let config = self.config.clone();
+
+        stream::unfold(self.socket.clone(), |socket| async move {
+            let mut buf = [0; MAX_PACKET_SIZE];
+            loop {
+                match socket.recv_from(&mut buf).await {
+                    Ok((len, _)) => return Some((buf[..len].to_vec(), socket)),
+                    Err(err) => eprintln!("unable to receive OSC message: {}", err),
+                }
+            }
+        })
+        .flat_map(move |packet| {
+            let events = match decode_packet(&packet) {
+                Some(messages) => messages
+                    .iter()
+                    .flat_map(|message| config.message_events(message))
+                    .collect(),
+                None => vec![],
+            };
+            stream::iter(events)
+        })
+    }
+    fn send(&self, messages: Vec<OscMessage>) {
+        if messages.is_empty() || self.config.send_to.is_empty() {
+            return;
+        }
+
+        // Never wait on the network, if sends have stalled the engine carries on
+        if let Err(err) = self.message_send.try_send(messages) {
+            eprintln!("unable to send OSC messages: {}", err);
+        }
+    }
+    pub fn send_button_states(
+        &self,
+        states: &[(ButtonGridLocation, ButtonCoordinate, ButtonState)],
+    ) {
+        self.send(
+            states
+                .iter()
+                .flat_map(|(location, coordinate, state)| {
+                    let address = self.config.button_address(*location, *coordinate);
+                    let value = if *state == ButtonState::Active {
+                        1.0
+                    } else {
+                        0.0
+                    };
+
+                    vec![
+                        OscMessage::new(address.clone(), vec![OscArg::Float(value)]),
+                        OscMessage::new(
+                            format!("{}/state", address),
+                            vec![OscArg::String(state_name(*state).to_owned())],
+                        ),
+                    ]
+                })
+                .collect(),
+        );
+    }
+    /// Sends the beat as each one starts, counting beats and bars from 1, and the tempo when it
+    /// changes
+    pub fn send_clock(&mut self, snapshot: &ClockSnapshot) {
+        let mut messages = Vec::new();
+
+        let beat = f64::from(snapshot.beats_elapsed()).floor() as i64;
+        if self.last_beat != Some(beat) {
+            self.last_beat = Some(beat);
+            messages.push(OscMessage::new(
+                format!("{}/clock/beat", self.config.prefix),
+                vec![
+                    OscArg::Int(snapshot.beat_in_bar() as i32 + 1),
+                    OscArg::Int(snapshot.current_bar() as i32 + 1),
+                ],
+            ));
+        }
+
+        let bpm_changed = self
+            .last_bpm
+            .map(|last_bpm| (last_bpm - snapshot.bpm).abs() > BPM_EPSILON)
+            .unwrap_or(true);
+        if bpm_changed {
+            self.last_bpm = Some(snapshot.bpm);
+            messages.push(OscMessage::new(
+                format!("{}/clock/bpm", self.config.prefix),
+                vec![OscArg::Float(snapshot.bpm as f32)],
+            ));
+        }
+
+        self.send(messages);
+    }
+    /// Sends each group's colour as red, green and blue from 0 to 1, to `/group/base/color` for
+    /// the scene's base state. Groups without a colour aren't sent
+    pub fn send_group_colors<'a>(
+        &self,
+        states: impl IntoIterator<Item = (Option<FixtureGroupId>, &'a FixtureGroupState)>,
+        palette: &ColorPalette,
+    ) {
+        self.send(
+            states
+                .into_iter()
+                .filter_map(|(group_id, state)| {
+                    let color = state.global_color.as_ref()?;
+                    let (red, green, blue) =
+                        palette::Srgb::<f64>::from(palette.hsl(color)).into_components();
+                    let group = match group_id {
+                        Some(group_id) => usize::from(group_id).to_string(),
+                        None => "base".to_owned(),
+                    };
+
+                    Some(OscMessage::new(
+                        format!("{}/group/{}/color", self.config.prefix, group),
+                        vec![
+                            OscArg::Float(red as f32),
+                            OscArg::Float(green as f32),
+                            OscArg::Float(blue as f32),
+                        ],
+                    ))
+                })
+                .collect(),
+        );
+    }
+}
+
+async fn run_message_sends(
+    socket: Arc<UdpSocket>,
+    send_to: Vec<SocketAddr>,
+    message_recv: async_std::sync::Receiver<Vec<OscMessage>>,
+) {
+    while let Ok(messages) = message_recv.recv().await {
+        for message in messages {
+            let bytes = message.to_bytes();
+            for address in send_to.iter() {
+                if let Err(err) = socket.send_to(&bytes, address).await {
+                    eprintln!("unable to send OSC message to {}: {}", address, err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> OscConfig {
+        toml::from_str(
+            r#"
+            [addresses]
+            "/1/push1" = { button = { location = "MetaRight", column = 0, row = 0 } }
+            "/1/fader5" = { fader = 4 }
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn events(address: &str, args: Vec<OscArg>) -> Vec<OscEvent> {
+        config().message_events(&OscMessage::new(address, args))
+    }
+
+    #[test]
+    fn built_in_addresses_map_to_inputs() {
+        let coordinate = ButtonCoordinate::new(3, 4);
+
+        assert_eq!(
+            events("/roller/button/main/3/4", vec![OscArg::Float(1.0)]),
+            vec![OscEvent::Input(InputEvent::ButtonPressed(
+                ButtonGridLocation::Main,
+                coordinate
+            ))]
+        );
+        assert_eq!(
+            events("/roller/button/main/3/4", vec![OscArg::Int(0)]),
+            vec![OscEvent::Input(InputEvent::ButtonReleased(
+                ButtonGridLocation::Main,
+                coordinate
+            ))]
+        );
+        assert_eq!(
+            events("/roller/fader/8", vec![OscArg::Float(0.25)]),
+            vec![OscEvent::Input(InputEvent::FaderUpdated(
+                FaderId::new(8),
+                0.25
+            ))]
+        );
+        assert_eq!(
+            events("/roller/scene/2", vec![]),
+            vec![OscEvent::Control(ControlEvent::SelectScene(SceneId::new(
+                2
+            )))]
+        );
+    }
+
+    #[test]
+    fn buttons_without_arguments_are_pressed_and_released() {
+        assert_eq!(events("/roller/button/meta-bottom/4/0", vec![]).len(), 2);
+    }
+
+    #[test]
+    fn triggers_ignore_releases() {
+        assert!(events("/roller/tap", vec![OscArg::Float(0.0)]).is_empty());
+        assert_eq!(events("/roller/tap", vec![OscArg::Float(1.0)]).len(), 1);
+        assert_eq!(
+            events("/roller/resync", vec![]),
+            vec![OscEvent::Control(ControlEvent::ResyncBeat)]
+        );
+    }
+
+    #[test]
+    fn mapped_addresses_are_used_first() {
+        assert_eq!(
+            events("/1/push1", vec![OscArg::Float(1.0)]),
+            vec![OscEvent::Input(InputEvent::ButtonPressed(
+                ButtonGridLocation::MetaRight,
+                ButtonCoordinate::new(0, 0)
+            ))]
+        );
+        assert_eq!(
+            events("/1/fader5", vec![OscArg::Float(2.0)]),
+            vec![OscEvent::Input(InputEvent::FaderUpdated(
+                FaderId::new(4),
+                1.0
+            ))]
+        );
+    }
+
+    #[test]
+    fn unknown_addresses_are_ignored() {
+        assert!(events("/roller/button/side/0/0", vec![]).is_empty());
+        assert!(events("/other/tap", vec![]).is_empty());
+        assert!(events("/rollerx/tap", vec![]).is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+//! Encoding and decoding of OSC 1.0 messages and bundles.
+//! See http://opensoundcontrol.org/spec-1_0 for the format
+use std::convert::TryInto;
+
+const BUNDLE_HEADER: &[u8] = b"#bundle\0";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Bool(bool),
+}
+impl OscArg {
+    // Controllers send numbers as whichever type they like, and toggles as booleans
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            OscArg::Int(x) => Some(*x as f64),
+            OscArg::Float(x) => Some(*x as f64),
+            OscArg::Bool(x) => Some(if *x { 1.0 } else { 0.0 }),
+            OscArg::String(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+impl OscMessage {
+    pub fn new(address: impl Into<String>, args: Vec<OscArg>) -> OscMessage {
+        OscMessage {
+            address: address.into(),
+            args,
+        }
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut type_tags = String::from(",");
+        let mut arg_bytes = Vec::new();
+
+        for arg in self.args.iter() {
+            match arg {
+                OscArg::Int(x) => {
+                    type_tags.push('i');
+                    arg_bytes.extend_from_slice(&x.to_be_bytes());
+                }
+                OscArg::Float(x) => {
+                    type_tags.push('f');
+                    arg_bytes.extend_from_slice(&x.to_be_bytes());
+                }
+                OscArg::String(x) => {
+                    type_tags.push('s');
+                    push_string(&mut arg_bytes, x);
+                }
+                OscArg::Bool(true) => type_tags.push('T'),
+                OscArg::Bool(false) => type_tags.push('F'),
+            }
+        }
+
+        let mut bytes = Vec::new();
+        push_string(&mut bytes, &self.address);
+        push_string(&mut bytes, &type_tags);
+        bytes.extend(arg_bytes);
+        bytes
+    }
+}
+
+// Strings are null terminated, and padded with nulls to a multiple of four bytes
+fn push_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(string.as_bytes());
+    let padding = 4 - string.len() % 4;
+    bytes.resize(bytes.len() + padding, 0);
+}
+
+fn padded_len(len: usize) -> usize {
+    (len + 4) & !3
+}
+
+fn read_string(bytes: &[u8]) -> Option<(String, &[u8])> {
+    let len = bytes.iter().position(|byte| *byte == 0)?;
+    let string = std::str::from_utf8(&bytes[..len]).ok()?.to_owned();
+    let rest = bytes.get(padded_len(len)..)?;
+
+    Some((string, rest))
+}
+
+fn read_u32(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let value = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?);
+    Some((value, &bytes[4..]))
+}
+
+fn read_message(bytes: &[u8]) -> Option<OscMessage> {
+    let (address, bytes) = read_string(bytes)?;
+    // Some old implementations leave out the type tags, those messages have no arguments we read
+    let (type_tags, mut bytes) = match read_string(bytes) {
+        Some((type_tags, rest)) if type_tags.starts_with(',') => (type_tags, rest),
+        _ => return Some(OscMessage::new(address, vec![])),
+    };
+
+    let mut args = Vec::new();
+    for type_tag in type_tags.chars().skip(1) {
+        let arg = match type_tag {
+            'i' => {
+                let (value, rest) = read_u32(bytes)?;
+                bytes = rest;
+                OscArg::Int(value as i32)
+            }
+            'f' => {
+                let (value, rest) = read_u32(bytes)?;
+                bytes = rest;
+                OscArg::Float(f32::from_bits(value))
+            }
+            's' | 'S' => {
+                let (value, rest) = read_string(bytes)?;
+                bytes = rest;
+                OscArg::String(value)
+            }
+            'd' => {
+                let value = u64::from_be_bytes(bytes.get(..8)?.try_into().ok()?);
+                bytes = &bytes[8..];
+                OscArg::Float(f64::from_bits(value) as f32)
+            }
+            'h' => {
+                let value = i64::from_be_bytes(bytes.get(..8)?.try_into().ok()?);
+                bytes = &bytes[8..];
+                OscArg::Int(value as i32)
+            }
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            // Blobs, timetags and the like aren't used by anything we map, but are skipped over
+            // so the arguments after them can be read
+            'b' => {
+                let (len, rest) = read_u32(bytes)?;
+                bytes = rest.get((len as usize + 3) & !3..)?;
+                continue;
+            }
+            't' => {
+                bytes = bytes.get(8..)?;
+                continue;
+            }
+            'c' | 'r' | 'm' => {
+                bytes = bytes.get(4..)?;
+                continue;
+            }
+            'N' | 'I' | '[' | ']' => continue,
+            _ => return None,
+        };
+        args.push(arg);
+    }
+
+    Some(OscMessage::new(address, args))
+}
+
+/// Reads the messages in a packet, those in bundles are returned in order without their time
+/// tags, as we apply everything as it arrives
+pub fn decode_packet(bytes: &[u8]) -> Option<Vec<OscMessage>> {
+    if bytes.starts_with(BUNDLE_HEADER) {
+        // The header is followed by an eight byte time tag, then the bundle's elements
+        let mut bytes = bytes.get(BUNDLE_HEADER.len() + 8..)?;
+        let mut messages = Vec::new();
+
+        while !bytes.is_empty() {
+            let (len, rest) = read_u32(bytes)?;
+            let element = rest.get(..len as usize)?;
+            messages.extend(decode_packet(element)?);
+            bytes = &rest[len as usize..];
+        }
+
+        Some(messages)
+    } else if bytes.starts_with(b"/") {
+        read_message(bytes).map(|message| vec![message])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let message = OscMessage::new(
+            "/roller/button/main/3/4",
+            vec![
+                OscArg::Float(1.0),
+                OscArg::Int(-3),
+                OscArg::String("active".to_owned()),
+                OscArg::Bool(true),
+            ],
+        );
+        let bytes = message.to_bytes();
+
+        assert_eq!(bytes.len() % 4, 0);
+        assert_eq!(decode_packet(&bytes), Some(vec![message]));
+    }
+
+    #[test]
+    fn strings_are_padded_to_four_bytes() {
+        let bytes = OscMessage::new("/tap", vec![]).to_bytes();
+
+        assert_eq!(bytes, b"/tap\0\0\0\0,\0\0\0".to_vec());
+    }
+
+    #[test]
+    fn bundles_are_flattened() {
+        let first = OscMessage::new("/roller/tap", vec![]);
+        let second = OscMessage::new("/roller/fader/8", vec![OscArg::Float(0.5)]);
+
+        let mut bytes = BUNDLE_HEADER.to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for message in [first.clone(), second.clone()].iter() {
+            let message_bytes = message.to_bytes();
+            bytes.extend_from_slice(&(message_bytes.len() as u32).to_be_bytes());
+            bytes.extend(message_bytes);
+        }
+
+        assert_eq!(decode_packet(&bytes), Some(vec![first, second]));
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected() {
+        let bytes = OscMessage::new("/roller/fader/8", vec![OscArg::Float(0.5)]).to_bytes();
+
+        assert_eq!(decode_packet(&bytes[..bytes.len() - 2]), None);
+        assert_eq!(decode_packet(b"garbage"), None);
+    }
+}
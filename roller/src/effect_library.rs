@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+use roller_protocol::{
+    clock::offset::ClockOffset,
+    effect::{ColorEffect, DimmerEffect, EffectError, PixelEffect, PositionEffect},
+};
+
+#[derive(Debug, Error)]
+pub enum EffectLibraryError {
+    #[error("effect \"{0}\" is defined more than once")]
+    DuplicateEffect(String),
+    #[error("effect \"{0}\" is invalid: {1}")]
+    InvalidEffect(String, EffectError),
+}
+
+/// Named effects, which buttons activate with `ActivateEffect`. Names are shared between the
+/// kinds of effect, so a name always means the same effect
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EffectLibrary {
+    #[serde(default)]
+    pub dimmer: BTreeMap<String, DimmerEffect>,
+    #[serde(default)]
+    pub color: BTreeMap<String, ColorEffect>,
+    #[serde(default)]
+    pub pixel: BTreeMap<String, PixelEffect>,
+    #[serde(default)]
+    pub position: BTreeMap<String, PositionEffect>,
+}
+impl EffectLibrary {
+    pub fn dimmer_effect(&self, name: &str) -> Option<&DimmerEffect> {
+        self.dimmer.get(name)
+    }
+    pub fn color_effect(&self, name: &str) -> Option<&ColorEffect> {
+        self.color.get(name)
+    }
+    pub fn pixel_effect(&self, name: &str) -> Option<&PixelEffect> {
+        self.pixel.get(name)
+    }
+    pub fn position_effect(&self, name: &str) -> Option<&PositionEffect> {
+        self.position.get(name)
+    }
+    /// Each effect's name and kind
+    pub fn names(&self) -> impl Iterator<Item = (&str, &'static str)> {
+        let dimmer = self.dimmer.keys().map(|name| (name.as_str(), "dimmer"));
+        let color = self.color.keys().map(|name| (name.as_str(), "color"));
+        let pixel = self.pixel.keys().map(|name| (name.as_str(), "pixel"));
+        let position = self.position.keys().map(|name| (name.as_str(), "position"));
+
+        dimmer.chain(color).chain(pixel).chain(position)
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.names().any(|(effect_name, _)| effect_name == name)
+    }
+    // Offsets that left their seed out are seeded from the effect's name, so each effect has an
+    // order of its own, which stays the same as the library reloads
+    fn seed_clock_offsets(&mut self) {
+        let seed = |clock_offset: Option<&mut ClockOffset>, name: &str| {
+            if let Some(clock_offset) = clock_offset {
+                clock_offset.seed_from(name);
+            }
+        };
+
+        for (name, effect) in self.dimmer.iter_mut() {
+            seed(effect.clock_offset.as_mut(), name);
+        }
+        for (name, effect) in self.color.iter_mut() {
+            seed(effect.clock_offset.as_mut(), name);
+        }
+        for (name, effect) in self.pixel.iter_mut() {
+            seed(effect.clock_offset.as_mut(), name);
+        }
+        for (name, effect) in self.position.iter_mut() {
+            seed(effect.clock_offset.as_mut(), name);
+        }
+    }
+    pub fn validate(&self) -> Result<(), EffectLibraryError> {
+        let mut names: Vec<_> = self.names().map(|(name, _)| name).collect();
+        names.sort_unstable();
+        if let Some(name) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(EffectLibraryError::DuplicateEffect(name[0].to_owned()));
+        }
+
+        let invalid = |name: &String| {
+            let name = name.clone();
+            move |err| EffectLibraryError::InvalidEffect(name, err)
+        };
+        for (name, effect) in self.dimmer.iter() {
+            effect.validate().map_err(invalid(name))?;
+        }
+        for (name, effect) in self.color.iter() {
+            effect.validate().map_err(invalid(name))?;
+        }
+        for (name, effect) in self.pixel.iter() {
+            effect.validate().map_err(invalid(name))?;
+        }
+        for (name, effect) in self.position.iter() {
+            effect.validate().map_err(invalid(name))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads and validates a `.ron` or `.toml` effect library, depending on the file extension
+pub async fn load_effect_library(
+    path: impl AsRef<async_std::path::Path>,
+) -> Result<EffectLibrary, async_std::io::Error> {
+    let path = path.as_ref();
+    let effect_library_contents = async_std::fs::read(path).await?;
+
+    let mut effect_library: EffectLibrary =
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => ron::de::from_bytes(&effect_library_contents).map_err(|err| {
+                async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, err)
+            })?,
+            Some("toml") => toml::from_slice(&effect_library_contents)?,
+            _ => {
+                return Err(async_std::io::Error::new(
+                    async_std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "effect library {} is not a .ron or .toml file",
+                        path.display()
+                    ),
+                ))
+            }
+        };
+    effect_library.seed_clock_offsets();
+
+    effect_library.validate().map_err(|err| {
+        async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, err.to_string())
+    })?;
+
+    Ok(effect_library)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EFFECT_LIBRARY: &str = r#"
+        [dimmer.pulse]
+        steps = [
+            { waveform = "SineUp", meter_length = 1.0, scale = { min = 0.2, max = 1.0 } },
+            { waveform = "Off", meter_length = 0.5, scale = { min = 0.0, max = 1.0 } },
+        ]
+        clock_offset = { mode = "FixtureIndex", offset = 0.25 }
+
+        [pixel.sweep]
+        steps = [{ waveform = "SawUp", meter_length = 2.0, direction = "LeftToRight" }]
+
+        [position.circle]
+        pan = { waveform = "SineUp", meter_length = 4.0, range = 90.0 }
+        tilt = { waveform = "SineDown", meter_length = 4.0, range = 45.0 }
+    "#;
+
+    #[test]
+    fn effects_are_read_by_name() {
+        let effect_library: EffectLibrary = toml::from_str(EFFECT_LIBRARY).unwrap();
+
+        assert!(effect_library.validate().is_ok());
+        assert!(effect_library.dimmer_effect("pulse").is_some());
+        assert!(effect_library.pixel_effect("sweep").is_some());
+        assert!(effect_library.position_effect("circle").is_some());
+        assert!(effect_library.color_effect("pulse").is_none());
+        assert_eq!(
+            effect_library.names().collect::<Vec<_>>(),
+            vec![
+                ("pulse", "dimmer"),
+                ("sweep", "pixel"),
+                ("circle", "position")
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_length_steps_are_rejected() {
+        let effect_library: EffectLibrary = toml::from_str(
+            r#"
+            [pixel.stuck]
+            steps = [{ waveform = "SawUp", meter_length = 0.0, direction = "LeftToRight" }]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            effect_library.validate().unwrap_err().to_string(),
+            "effect \"stuck\" is invalid: step length must be more than zero beats, not 0"
+        );
+    }
+
+    #[test]
+    fn names_are_shared_between_kinds() {
+        let effect_library: EffectLibrary = toml::from_str(
+            r#"
+            [dimmer.strobe]
+            steps = [{ waveform = "ShortSquarePulse", meter_length = 0.25, scale = { min = 0.0, max = 1.0 } }]
+
+            [pixel.strobe]
+            steps = [{ waveform = "SawUp", meter_length = 1.0, direction = "BottomToTop" }]
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            effect_library.validate(),
+            Err(EffectLibraryError::DuplicateEffect(name)) if name == "strobe"
+        ));
+    }
+
+    #[test]
+    fn unknown_offset_modes_are_rejected() {
+        let result: Result<EffectLibrary, _> = toml::from_str(
+            r#"
+            [pixel.sweep]
+            steps = [{ waveform = "SawUp", meter_length = 1.0, direction = "LeftToRight" }]
+            clock_offset = { mode = "Sideways", offset = 0.5 }
+            "#,
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn random_offsets_are_seeded_by_name() {
+        let load = || {
+            let mut effect_library: EffectLibrary = toml::from_str(
+                r#"
+                [pixel.sparkle]
+                steps = [{ waveform = "SawUp", meter_length = 1.0, direction = "BottomToTop" }]
+                clock_offset = { mode = "Random", offset = 0.25 }
+
+                [pixel.glitter]
+                steps = [{ waveform = "SawUp", meter_length = 1.0, direction = "BottomToTop" }]
+                clock_offset = { mode = "Random", offset = 0.25 }
+                "#,
+            )
+            .unwrap();
+            effect_library.seed_clock_offsets();
+            effect_library
+        };
+
+        // Reloading an unchanged library leaves effects as they were
+        assert_eq!(load(), load());
+        // While each effect has an order of its own
+        let effect_library = load();
+        assert_ne!(
+            effect_library.pixel_effect("sparkle"),
+            effect_library.pixel_effect("glitter")
+        );
+    }
+}
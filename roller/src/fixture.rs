@@ -1,6 +1,8 @@
 use async_std::prelude::*;
 use rustc_hash::FxHashMap;
 use serde::Deserialize;
+use std::fmt;
+use std::path::PathBuf;
 
 use crate::utils::FxIndexMap;
 
@@ -13,13 +15,80 @@ struct FixtureProfileData {
     channel_count: usize,
     channels: Vec<FixtureProfileChannel>,
     supported_effects: Vec<FixtureEffectType>,
+    #[serde(default)]
+    dimmer_curve: DimmerCurve,
+    #[serde(default)]
+    beams: Vec<FixtureProfileBeamData>,
 }
 
-pub async fn load_fixture_profile(
-    path: impl AsRef<async_std::path::Path>,
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct FixtureProfileBeamData {
+    beam: BeamId,
+    #[serde(flatten)]
+    location: FixtureBeamLocation,
+}
+
+// Profiles shipped with roller, embedded into the binary at compile time
+static BUILT_IN_FIXTURE_PROFILES: &[(&str, &str)] = &[
+    (
+        "ave_ledbar_24_29ch.toml",
+        include_str!("../../fixture_profiles/ave_ledbar_24_29ch.toml"),
+    ),
+    (
+        "ave_quad_pro_flat_4ch.toml",
+        include_str!("../../fixture_profiles/ave_quad_pro_flat_4ch.toml"),
+    ),
+    (
+        "ebay_par_can.toml",
+        include_str!("../../fixture_profiles/ebay_par_can.toml"),
+    ),
+    (
+        "mini_led_moving_head.toml",
+        include_str!("../../fixture_profiles/mini_led_moving_head.toml"),
+    ),
+];
+
+/// A place fixture profiles are searched for. When the same slug is found in
+/// more than one directory, the directory listed first wins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixtureProfileDirectory {
+    Project(PathBuf),
+    User(PathBuf),
+    BuiltIn,
+}
+impl FixtureProfileDirectory {
+    /// `$XDG_CONFIG_HOME/roller/fixture_profiles`, falling back to `~/.config`
+    pub fn user() -> Option<FixtureProfileDirectory> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(FixtureProfileDirectory::User(
+            config_dir.join("roller").join("fixture_profiles"),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixtureProfileSource {
+    Project(PathBuf),
+    User(PathBuf),
+    BuiltIn(&'static str),
+}
+impl fmt::Display for FixtureProfileSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixtureProfileSource::Project(path) => write!(f, "{} (project)", path.display()),
+            FixtureProfileSource::User(path) => write!(f, "{} (user)", path.display()),
+            FixtureProfileSource::BuiltIn(file_name) => write!(f, "{} (built-in)", file_name),
+        }
+    }
+}
+
+pub fn parse_fixture_profile(
+    fixture_profile_contents: &[u8],
 ) -> Result<FixtureProfile, async_std::io::Error> {
-    let fixture_profile_contents = async_std::fs::read(path).await?;
-    let profile_data: FixtureProfileData = toml::from_slice(&fixture_profile_contents)?;
+    let profile_data: FixtureProfileData = toml::from_slice(fixture_profile_contents)?;
 
     let parameters: FxHashMap<_, _> = profile_data
         .channels
@@ -85,8 +154,43 @@ pub async fn load_fixture_profile(
             .collect()
     };
 
-    // flatten beams out into a vec
-    let beams: Vec<_> = beams.into_iter().map(|(_, profile)| profile).collect();
+    // flatten beams out into a vec. Beams without a location are laid out left
+    // to right in a single row
+    let mut beam_locations: FxHashMap<_, _> = FxHashMap::default();
+    for beam in profile_data.beams.iter() {
+        if !beams.contains_key(&beam.beam) {
+            return Err(async_std::io::Error::new(
+                async_std::io::ErrorKind::InvalidData,
+                format!(
+                    "fixture profile \"{}\" locates beam {}, which is not one of its {} beams",
+                    profile_data.slug,
+                    usize::from(beam.beam),
+                    beams.len()
+                ),
+            ));
+        }
+        if beam_locations.insert(beam.beam, beam.location).is_some() {
+            return Err(async_std::io::Error::new(
+                async_std::io::ErrorKind::InvalidData,
+                format!(
+                    "fixture profile \"{}\" locates beam {} more than once",
+                    profile_data.slug,
+                    usize::from(beam.beam)
+                ),
+            ));
+        }
+    }
+    let beams: Vec<_> = beams
+        .into_iter()
+        .enumerate()
+        .map(|(i, (id, mut profile))| {
+            profile.location = beam_locations
+                .get(&id)
+                .copied()
+                .unwrap_or_else(|| FixtureBeamLocation::new(i as f64, 0.0, 0.0));
+            profile
+        })
+        .collect();
 
     // Ensure channel count is correct
     assert_eq!(profile_data.channel_count, profile_data.channels.len());
@@ -99,6 +203,7 @@ pub async fn load_fixture_profile(
         label: profile_data.label,
         channel_count: profile_data.channel_count,
         supported_effects: profile_data.supported_effects,
+        dimmer_curve: profile_data.dimmer_curve,
 
         beams,
         dimmer_channel,
@@ -107,17 +212,138 @@ pub async fn load_fixture_profile(
     })
 }
 
-pub async fn load_fixture_profiles(
-) -> Result<FxHashMap<String, FixtureProfile>, async_std::io::Error> {
-    let mut profile_paths = async_std::fs::read_dir("./fixture_profiles").await?;
+pub async fn load_fixture_profile(
+    path: impl AsRef<async_std::path::Path>,
+) -> Result<FixtureProfile, async_std::io::Error> {
+    let fixture_profile_contents = async_std::fs::read(path).await?;
+    parse_fixture_profile(&fixture_profile_contents)
+}
 
-    let mut fixture_profiles = FxHashMap::default();
+async fn load_fixture_profile_directory(
+    path: &PathBuf,
+) -> Result<Vec<(FixtureProfile, PathBuf)>, async_std::io::Error> {
+    let mut profile_paths = match async_std::fs::read_dir(path).await {
+        Ok(profile_paths) => profile_paths,
+        // Directories that don't exist are skipped, so every project doesn't need its own profiles
+        Err(e) if e.kind() == async_std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut fixture_profiles: Vec<(FixtureProfile, PathBuf)> = Vec::new();
     while let Some(entry) = profile_paths.next().await {
         let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+            continue;
+        }
 
-        let fixture_profile = load_fixture_profile(path).await?;
-        fixture_profiles.insert(fixture_profile.slug.clone(), fixture_profile);
+        let fixture_profile = load_fixture_profile(&path).await?;
+        fixture_profiles.push((fixture_profile, path.into()));
     }
+    fixture_profiles.sort_by(|(_, a), (_, b)| a.cmp(b));
 
     Ok(fixture_profiles)
 }
+
+/// Finds every fixture profile in the given directories, in order of precedence.
+/// The same slug may appear more than once
+pub async fn find_fixture_profiles(
+    directories: &[FixtureProfileDirectory],
+) -> Result<Vec<(FixtureProfile, FixtureProfileSource)>, async_std::io::Error> {
+    let mut fixture_profiles = Vec::new();
+
+    for directory in directories {
+        match directory {
+            FixtureProfileDirectory::Project(path) => {
+                for (profile, path) in load_fixture_profile_directory(path).await? {
+                    fixture_profiles.push((profile, FixtureProfileSource::Project(path)));
+                }
+            }
+            FixtureProfileDirectory::User(path) => {
+                for (profile, path) in load_fixture_profile_directory(path).await? {
+                    fixture_profiles.push((profile, FixtureProfileSource::User(path)));
+                }
+            }
+            FixtureProfileDirectory::BuiltIn => {
+                for (file_name, contents) in BUILT_IN_FIXTURE_PROFILES {
+                    let profile = parse_fixture_profile(contents.as_bytes())?;
+                    fixture_profiles.push((profile, FixtureProfileSource::BuiltIn(file_name)));
+                }
+            }
+        }
+    }
+
+    Ok(fixture_profiles)
+}
+
+/// Resolves each slug to the profile from the highest precedence directory
+pub async fn load_fixture_profiles(
+    directories: &[FixtureProfileDirectory],
+) -> Result<FxIndexMap<String, (FixtureProfile, FixtureProfileSource)>, async_std::io::Error> {
+    let mut fixture_profiles = FxIndexMap::default();
+
+    for (profile, source) in find_fixture_profiles(directories).await? {
+        if !fixture_profiles.contains_key(&profile.slug) {
+            fixture_profiles.insert(profile.slug.clone(), (profile, source));
+        }
+    }
+
+    Ok(fixture_profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_beam_profile(beams: &str) -> Result<FixtureProfile, async_std::io::Error> {
+        parse_fixture_profile(
+            format!(
+                r#"
+                slug = "two_beams"
+                label = "Two beams"
+                channel_count = 2
+                supported_effects = ["dimmer", "pixel"]
+
+                [[channels]]
+                channel = 1
+                parameter = "dimmer"
+                beam = 1
+
+                [[channels]]
+                channel = 2
+                parameter = "dimmer"
+                beam = 2
+
+                {}
+                "#,
+                beams
+            )
+            .as_bytes(),
+        )
+    }
+
+    #[test]
+    fn beam_locations_must_be_for_the_profile_s_beams() {
+        let profile = two_beam_profile(
+            r#"
+            [[beams]]
+            beam = 2
+            x = 0.0
+            y = 1.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            profile.beams[0].location,
+            FixtureBeamLocation::new(0.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            profile.beams[1].location,
+            FixtureBeamLocation::new(0.0, 1.0, 0.0)
+        );
+
+        assert!(two_beam_profile("[[beams]]\nbeam = 3\nx = 0.0").is_err());
+        assert!(
+            two_beam_profile("[[beams]]\nbeam = 1\nx = 0.0\n[[beams]]\nbeam = 1\nx = 1.0").is_err()
+        );
+    }
+}
@@ -15,6 +15,7 @@ use crate::{
     control::button::{
         ButtonAction, ButtonGroup, ButtonGroupId, ButtonMapping, ButtonType, GroupToggleState,
     },
+    effect_library::EffectLibrary,
     utils::{shift_remove_vec, FxIndexMap},
 };
 
@@ -63,11 +64,12 @@ impl SceneControlState {
     }
     pub fn fixture_group_values(
         &self,
+        effect_library: &EffectLibrary,
     ) -> (
         FixtureGroupState,
         FxHashMap<FixtureGroupId, FixtureGroupState>,
     ) {
-        let mut base_values = self.base.fixture_group_value();
+        let mut base_values = self.base.fixture_group_value(effect_library);
 
         // If fixture groups don't have intensities set, apply the default setting from the scene
         if base_values.dimmer_effect_intensity == None {
@@ -77,13 +79,12 @@ impl SceneControlState {
             base_values.color_effect_intensity = Some(self.color_effect_intensity);
         }
 
+        // Group values are merged with the base values per fixture when rendering,
+        // as a fixture may be in several groups
         let group_values = self
             .fixture_groups
             .iter()
-            .map(|(id, state)| {
-                let values = state.fixture_group_value();
-                (*id, values.merge(&base_values))
-            })
+            .map(|(id, state)| (*id, state.fixture_group_value(effect_library)))
             .collect();
 
         (base_values, group_values)
@@ -97,33 +98,34 @@ pub struct FixtureGroupControlState {
     pub button_states: ButtonStates,
 }
 impl FixtureGroupControlState {
-    pub fn fixture_group_value(&self) -> FixtureGroupState {
+    pub fn fixture_group_value(&self, effect_library: &EffectLibrary) -> FixtureGroupState {
         let buttons = &self.button_states;
 
         FixtureGroupState {
             dimmer: self.dimmer,
             dimmer_effect_intensity: None,
             color_effect_intensity: None,
+            pixel_blend_mode: None,
             clock_rate: self.clock_rate,
             global_color: buttons.global_color(),
             secondary_color: buttons.secondary_color(),
             active_dimmer_effects: buttons
-                .active_dimmer_effects()
+                .active_dimmer_effects(effect_library)
                 .into_iter()
                 .map(|(k, v)| (k.clone(), v))
                 .collect(),
             active_color_effects: buttons
-                .active_color_effects()
+                .active_color_effects(effect_library)
                 .into_iter()
                 .map(|(k, v)| (k.clone(), v))
                 .collect(),
             active_pixel_effects: buttons
-                .active_pixel_effects()
+                .active_pixel_effects(effect_library)
                 .into_iter()
                 .map(|(k, v)| (k.clone(), v))
                 .collect(),
             active_position_effects: buttons
-                .active_position_effects()
+                .active_position_effects(effect_library)
                 .into_iter()
                 .map(|(k, v)| (k.clone(), v))
                 .collect(),
@@ -147,16 +149,18 @@ pub struct ButtonStates {
     group_states: FxHashMap<ButtonGroup, GroupStatesValue>,
 }
 impl ButtonStates {
-    fn iter_groups(
+    pub fn iter_groups(
         &self,
     ) -> impl Iterator<Item = (&ButtonGroup, GroupToggleState, &ButtonStateMap)> {
         self.group_states
             .iter()
             .map(|(group, (toggle_state, states))| (group, *toggle_state, states))
     }
-    // Takes a button group and returns an iterator of `Info` summaries
+    // Takes a button group and returns an iterator of `Info` summaries, in the order buttons were
+    // triggered across every group
     pub fn iter_info(&self) -> impl Iterator<Item = (ButtonGroupInfo, ButtonInfo<'_>)> {
-        self.iter_groups()
+        let mut info: Vec<_> = self
+            .iter_groups()
             .flat_map(|(group, toggle_state, states)| {
                 states
                     .iter()
@@ -175,6 +179,12 @@ impl ButtonStates {
                         )
                     })
             })
+            .collect();
+
+        // Groups are kept in a hash map, but effects layer over and colors replace those
+        // triggered before them. The sort is stable, so each group keeps its own order
+        info.sort_by_key(|(_, button_info)| button_info.triggered_at);
+        info.into_iter()
     }
     pub fn iter_group_toggle_states(
         &self,
@@ -185,7 +195,7 @@ impl ButtonStates {
     fn find_active_effects<'a, T, F>(&'a self, extract_effect_fn: F) -> FxIndexMap<&'a T, Rate>
     where
         T: Eq + std::hash::Hash,
-        F: Fn(&ButtonAction) -> Option<&T>,
+        F: Fn(&'a ButtonAction) -> Option<&'a T>,
     {
         let mut effects = FxIndexMap::default();
 
@@ -243,12 +253,12 @@ impl ButtonStates {
             .collect()
     }
     pub fn global_color(&self) -> Option<Color> {
-        let mut on_colors: Vec<(ButtonCoordinate, Color)> = Vec::new();
-        let mut last_off: Option<(ButtonCoordinate, Color)> = None;
+        let mut on_colors: Vec<(ButtonCoordinate, &Color)> = Vec::new();
+        let mut last_off: Option<(ButtonCoordinate, &Color)> = None;
 
         let color_buttons =
             self.iter_info().flat_map(|(group_info, button_info)| {
-                match button_info.button.on_action {
+                match &button_info.button.on_action {
                     ButtonAction::UpdateGlobalColor(color) => match group_info.group.button_type {
                         ButtonType::Switch => {
                             Some((button_info.button.coordinate, button_info.note_state, color))
@@ -274,12 +284,12 @@ impl ButtonStates {
         on_colors
             .last()
             .or_else(|| last_off.as_ref())
-            .map(|(_, color)| *color)
+            .map(|(_, color)| (*color).clone())
     }
     pub fn secondary_color(&self) -> Option<Color> {
         self.iter_info()
             .filter_map(
-                |(group_info, button_info)| match button_info.button.on_action {
+                |(group_info, button_info)| match &button_info.button.on_action {
                     ButtonAction::UpdateGlobalSecondaryColor(color) => {
                         match group_info.group.button_type {
                             ButtonType::Toggle => Some((
@@ -295,7 +305,7 @@ impl ButtonStates {
             )
             .filter_map(|(note, toggle_state, color)| {
                 if GroupToggleState::On(note) == toggle_state {
-                    Some(color)
+                    Some(color.clone())
                 } else {
                     None
                 }
@@ -311,27 +321,44 @@ impl ButtonStates {
         .last()
         .map(|position| **position)
     }
-    pub fn active_dimmer_effects(&self) -> FxIndexMap<&DimmerEffect, Rate> {
+    // Named effects are looked up as they're rendered, so they follow the library as it reloads
+    pub fn active_dimmer_effects<'a>(
+        &'a self,
+        effect_library: &'a EffectLibrary,
+    ) -> FxIndexMap<&'a DimmerEffect, Rate> {
         self.find_active_effects(|action| match action {
             ButtonAction::ActivateDimmerEffect(effect) => Some(effect),
+            ButtonAction::ActivateEffect(name) => effect_library.dimmer_effect(name),
             _ => None,
         })
     }
-    pub fn active_color_effects(&self) -> FxIndexMap<&ColorEffect, Rate> {
+    pub fn active_color_effects<'a>(
+        &'a self,
+        effect_library: &'a EffectLibrary,
+    ) -> FxIndexMap<&'a ColorEffect, Rate> {
         self.find_active_effects(|action| match action {
             ButtonAction::ActivateColorEffect(effect) => Some(effect),
+            ButtonAction::ActivateEffect(name) => effect_library.color_effect(name),
             _ => None,
         })
     }
-    pub fn active_pixel_effects(&self) -> FxIndexMap<&PixelEffect, Rate> {
+    pub fn active_pixel_effects<'a>(
+        &'a self,
+        effect_library: &'a EffectLibrary,
+    ) -> FxIndexMap<&'a PixelEffect, Rate> {
         self.find_active_effects(|action| match action {
             ButtonAction::ActivatePixelEffect(effect) => Some(effect),
+            ButtonAction::ActivateEffect(name) => effect_library.pixel_effect(name),
             _ => None,
         })
     }
-    pub fn active_position_effects(&self) -> FxIndexMap<&PositionEffect, Rate> {
+    pub fn active_position_effects<'a>(
+        &'a self,
+        effect_library: &'a EffectLibrary,
+    ) -> FxIndexMap<&'a PositionEffect, Rate> {
         self.find_active_effects(|action| match action {
             ButtonAction::ActivatePositionEffect(effect) => Some(effect),
+            ButtonAction::ActivateEffect(name) => effect_library.position_effect(name),
             _ => None,
         })
     }
@@ -353,6 +380,14 @@ impl ButtonStates {
         let (_, button_states) = self.button_group_value_mut(group);
         button_states
     }
+    pub fn set_button_group_toggle_state(
+        &mut self,
+        group: &ButtonGroup,
+        toggle_state: GroupToggleState,
+    ) {
+        let (group_toggle_state, _) = self.button_group_value_mut(group);
+        *group_toggle_state = toggle_state;
+    }
     pub fn toggle_button_group(&mut self, group: &ButtonGroup, coordinate: ButtonCoordinate) {
         let (toggle_state, _) = self.button_group_value_mut(group);
         toggle_state.toggle_mut(coordinate);
@@ -1,33 +1,42 @@
-use chrono::Utc;
 use derive_more::Constructor;
 use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::Instant;
 
 use roller_protocol::{
-    clock::{Clock, ClockEvent, Rate},
+    clock::{Beats, Clock, ClockEvent, Quantization, Rate, Time},
+    color::ColorPalette,
     control::{InputEvent, NoteState},
+    effect::PixelBlendMode,
     fixture::FixtureGroupId,
     lighting_engine::FixtureGroupState,
 };
 
 use crate::control::{
-    button::{ButtonGroup, ButtonMapping, ButtonRef, MetaButtonAction},
+    button::{ButtonGroup, ButtonMapping, ButtonRef, ButtonType, MetaButtonAction},
     control_mapping::ControlMapping,
 };
+use crate::effect_library::EffectLibrary;
 
 mod button_states;
+mod show_state;
 
 pub use button_states::{
     ButtonGroupInfo, ButtonInfo, ButtonStateMap, ButtonStateValue, ButtonStates,
     FixtureGroupControlState, SceneControlState, EMPTY_SCENE_STATE,
 };
+pub use show_state::{load_show_state, save_show_state, ShowState};
 
 // This is just for the case where no buttons have been activated yet
 lazy_static::lazy_static! {
     static ref DEFAULT_FIXTURE_GROUP_VALUE: FixtureGroupState = FixtureGroupState::default();
+    static ref EMPTY_PALETTE: ColorPalette = ColorPalette::default();
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Constructor)]
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Constructor, Serialize, Deserialize,
+)]
 pub struct SceneId(usize);
 
 #[derive(Debug, Clone, PartialEq)]
@@ -37,33 +46,103 @@ pub enum ControlMode {
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum ControlEvent<'a> {
+pub enum ControlEvent {
     UpdateMasterDimmer(f64),
     UpdateGroupDimmer(FixtureGroupId, f64),
     UpdateDimmerEffectIntensity(f64),
     UpdateColorEffectIntensity(f64),
     UpdateClockRate(Rate),
+    UpdateLaunchQuantization(Quantization),
     SelectScene(SceneId),
     SelectFixtureGroupControl(FixtureGroupId),
-    UpdateButton(&'a ButtonGroup, &'a ButtonMapping, NoteState, Instant),
+    SelectPalette(String),
+    UpdateButton(ButtonGroup, ButtonMapping, NoteState, Instant),
     TapTempo(Instant),
+    ResyncBeat,
+    SetDownbeat,
+    NudgePhase(Beats),
+    AdjustBpm(f64),
+    ScaleBpm(f64),
     UpdateControlMode(ControlMode),
 }
 
+// A pressed action waiting for the next quantization boundary
+#[derive(Debug)]
+pub struct PendingAction {
+    event: ControlEvent,
+    // Button presses apply where they were made, even if the scene or control group has changed
+    scene_id: SceneId,
+    fixture_group_control: Option<FixtureGroupId>,
+    armed_at: Beats,
+    launch_at: Beats,
+    // Cancelled presses are held until their release, which is dropped with them
+    cancelled: bool,
+}
+impl PendingAction {
+    fn is_for(&self, event: &ControlEvent) -> bool {
+        match (&self.event, event) {
+            (
+                ControlEvent::UpdateButton(group, button, _, _),
+                ControlEvent::UpdateButton(other_group, other_button, _, _),
+            ) => group == other_group && button == other_button,
+            (ControlEvent::SelectScene(scene_id), ControlEvent::SelectScene(other_scene_id)) => {
+                scene_id == other_scene_id
+            }
+            _ => false,
+        }
+    }
+    fn is_due(&self, clock: &Clock) -> bool {
+        let beats_elapsed = clock.snapshot().beats_elapsed();
+
+        // A clock that has jumped back, from a resync or the like, won't reach the boundary soon.
+        // Stopped clocks won't reach it at all
+        !self.cancelled
+            && (clock.is_stopped()
+                || beats_elapsed >= self.launch_at
+                || beats_elapsed < self.armed_at)
+    }
+}
+
 pub struct EngineState<'a> {
-    pub control_mapping: &'a ControlMapping,
+    // Replaced when the mapping file changes, events carry copies of the buttons they're for
+    pub control_mapping: Arc<ControlMapping>,
     pub clock: Clock,
     pub master_dimmer: f64,
     pub control_mode: ControlMode,
     pub active_scene_id: SceneId,
     pub active_fixture_group_control: Option<FixtureGroupId>,
     pub scene_fixture_group_button_states: FxHashMap<SceneId, SceneControlState>,
+    pub palettes: &'a [ColorPalette],
+    pub active_palette_idx: usize,
+    // Replaced when the library file changes, buttons look effects up by name as they render
+    pub effect_library: EffectLibrary,
+    pub launch_quantization: Quantization,
+    pub pixel_blend_mode: PixelBlendMode,
+    pub pending_actions: Vec<PendingAction>,
+    pub time: Time,
 }
 impl<'a> EngineState<'a> {
-    pub fn new(control_mapping: &'a ControlMapping) -> EngineState<'a> {
+    pub fn new(
+        control_mapping: Arc<ControlMapping>,
+        palettes: &'a [ColorPalette],
+    ) -> EngineState<'a> {
+        EngineState::with_time(control_mapping, palettes, Time::system())
+    }
+    pub fn with_time(
+        control_mapping: Arc<ControlMapping>,
+        palettes: &'a [ColorPalette],
+        time: Time,
+    ) -> EngineState<'a> {
         EngineState {
             control_mapping,
-            clock: Clock::new(128.0),
+            palettes,
+            active_palette_idx: 0,
+            effect_library: EffectLibrary::default(),
+            launch_quantization: Quantization::Off,
+            pixel_blend_mode: PixelBlendMode::default(),
+            pending_actions: Vec::new(),
+            clock: Clock::with_time(128.0, time.clone()),
+            time,
             master_dimmer: 1.0,
             control_mode: ControlMode::Normal,
             active_scene_id: SceneId::new(1),
@@ -71,6 +150,21 @@ impl<'a> EngineState<'a> {
             scene_fixture_group_button_states: FxHashMap::default(),
         }
     }
+    /// Projects without any palettes use an empty palette, so palette entries render white
+    pub fn active_palette(&self) -> &ColorPalette {
+        self.palettes
+            .get(self.active_palette_idx)
+            .unwrap_or_else(|| &*EMPTY_PALETTE)
+    }
+    pub fn select_palette(&mut self, name: &str) {
+        if let Some(idx) = self
+            .palettes
+            .iter()
+            .position(|palette| palette.name == name)
+        {
+            self.active_palette_idx = idx;
+        }
+    }
     pub fn active_scene_state(&self) -> &SceneControlState {
         self.scene_fixture_group_button_states
             .get(&self.active_scene_id)
@@ -93,7 +187,7 @@ impl<'a> EngineState<'a> {
             .fixture_group_state_mut(active_fixture_group_control)
     }
     pub fn apply_input_event(&mut self, event: InputEvent) {
-        let now = Instant::now();
+        let now = self.time.instant();
 
         let control_event = match event {
             InputEvent::FaderUpdated(fader_id, value) => self
@@ -112,9 +206,166 @@ impl<'a> EngineState<'a> {
         };
 
         if let Some(control_event) = control_event {
-            self.apply_event(control_event);
+            self.apply_control_event(control_event);
         }
     }
+    /// Applies an event from a control that isn't in the control mapping, like OSC
+    pub fn apply_control_event(&mut self, event: ControlEvent) {
+        // Anything due launches first, so presses apply in the order they were made
+        self.launch_pending_actions();
+
+        if let Some(event) = self.quantize_event(event) {
+            self.apply_event(event);
+        }
+    }
+    /// Applies an event as it is, for those that are already timed, like timecode cues
+    pub fn apply_timed_control_event(&mut self, event: ControlEvent) {
+        self.launch_pending_actions();
+        self.apply_event(event);
+    }
+    fn event_quantization(&self, event: &ControlEvent) -> Quantization {
+        // Flash buttons are held for effect, so always apply as they're pressed
+        let button_quantization = match (&self.control_mode, event) {
+            (_, ControlEvent::UpdateButton(group, button, _, _)) => {
+                if group.button_type == ButtonType::Flash {
+                    return Quantization::Off;
+                }
+                button.quantization
+            }
+            (ControlMode::Normal, ControlEvent::SelectScene(scene_id)) => self
+                .control_mapping
+                .meta_buttons
+                .values()
+                .find(|button| button.on_action == MetaButtonAction::SelectScene(*scene_id))
+                .and_then(|button| button.quantization),
+            _ => return Quantization::Off,
+        };
+
+        button_quantization.unwrap_or(self.launch_quantization)
+    }
+    // Returns the event if it should be applied now, otherwise it's held until the next boundary
+    fn quantize_event(&mut self, event: ControlEvent) -> Option<ControlEvent> {
+        if let Some(pending_action) = self
+            .pending_actions
+            .iter()
+            .find(|pending_action| pending_action.is_for(&event))
+        {
+            let (armed_at, launch_at) = (pending_action.armed_at, pending_action.launch_at);
+            let cancelled = pending_action.cancelled;
+
+            match event {
+                ControlEvent::UpdateButton(_, _, NoteState::Off, _) if cancelled => {
+                    self.pending_actions
+                        .retain(|pending_action| !pending_action.is_for(&event));
+                }
+                // Releases follow their press, so the button isn't left held down
+                ControlEvent::UpdateButton(_, _, NoteState::Off, _) => {
+                    self.queue_event(event, armed_at, launch_at, false);
+                }
+                // Pressing an armed button again cancels it
+                ControlEvent::UpdateButton(_, _, NoteState::On, _) => {
+                    self.pending_actions
+                        .retain(|pending_action| !pending_action.is_for(&event));
+                    self.queue_event(event, armed_at, launch_at, true);
+                }
+                _ => {
+                    self.pending_actions
+                        .retain(|pending_action| !pending_action.is_for(&event));
+                }
+            }
+            return None;
+        }
+
+        if self.clock.is_stopped() {
+            return Some(event);
+        }
+        let snapshot = self.clock.snapshot();
+        let meter_length = match event {
+            ControlEvent::UpdateButton(_, _, NoteState::Off, _) => None,
+            _ => self
+                .event_quantization(&event)
+                .meter_length(snapshot.time_signature),
+        };
+
+        match meter_length {
+            Some(meter_length) => {
+                let launch_at = snapshot.next_meter_start(meter_length);
+                self.queue_event(event, snapshot.beats_elapsed(), launch_at, false);
+                None
+            }
+            None => Some(event),
+        }
+    }
+    fn queue_event(
+        &mut self,
+        event: ControlEvent,
+        armed_at: Beats,
+        launch_at: Beats,
+        cancelled: bool,
+    ) {
+        self.pending_actions.push(PendingAction {
+            event,
+            scene_id: self.active_scene_id,
+            fixture_group_control: self.active_fixture_group_control,
+            armed_at,
+            launch_at,
+            cancelled,
+        });
+    }
+    /// Applies any pending actions whose boundary the clock has reached
+    pub fn launch_pending_actions(&mut self) {
+        if self.pending_actions.is_empty() {
+            return;
+        }
+
+        let clock = &self.clock;
+        let (due, waiting): (Vec<_>, Vec<_>) = self
+            .pending_actions
+            .drain(..)
+            .partition(|pending_action| pending_action.is_due(clock));
+        self.pending_actions = waiting;
+
+        let now = self.time.instant();
+        for pending_action in due {
+            match pending_action.event {
+                ControlEvent::UpdateButton(group, button, note_state, _) => {
+                    self.scene_fixture_group_button_states
+                        .entry(pending_action.scene_id)
+                        .or_default()
+                        .fixture_group_state_mut(pending_action.fixture_group_control)
+                        .button_states
+                        .update_button_state(&group, button, note_state, now);
+                }
+                event => self.apply_event(event),
+            }
+        }
+    }
+    /// Buttons pressed in the current scene and control group that are waiting to launch
+    pub fn armed_buttons(&self) -> impl Iterator<Item = ButtonRef<'_>> + '_ {
+        self.pending_actions
+            .iter()
+            .filter(|pending_action| !pending_action.cancelled)
+            .filter_map(move |pending_action| match &pending_action.event {
+                ControlEvent::UpdateButton(group, button, NoteState::On, _) => {
+                    if (
+                        pending_action.scene_id,
+                        pending_action.fixture_group_control,
+                    ) == (self.active_scene_id, self.active_fixture_group_control)
+                    {
+                        Some(ButtonRef::Standard(group, button))
+                    } else {
+                        None
+                    }
+                }
+                ControlEvent::SelectScene(scene_id) => self
+                    .control_mapping
+                    .meta_buttons
+                    .values()
+                    .find(|button| button.on_action == MetaButtonAction::SelectScene(*scene_id))
+                    .map(ButtonRef::from),
+                _ => None,
+            })
+    }
     fn apply_event(&mut self, event: ControlEvent) {
         // dbg!(&event);
         match (&self.control_mode, event) {
@@ -145,6 +396,9 @@ impl<'a> EngineState<'a> {
                     self.control_fixture_group_state_mut().clock_rate = rate;
                 }
             }
+            (_, ControlEvent::UpdateLaunchQuantization(quantization)) => {
+                self.launch_quantization = quantization;
+            }
             (ControlMode::Normal, ControlEvent::SelectScene(scene_id)) => {
                 self.active_scene_id = scene_id;
                 self.active_fixture_group_control = None;
@@ -165,6 +419,9 @@ impl<'a> EngineState<'a> {
                     .fixture_groups
                     .insert(group_id, FixtureGroupControlState::default());
             }
+            (_, ControlEvent::SelectPalette(name)) => {
+                self.select_palette(&name);
+            }
             (_, ControlEvent::UpdateGroupDimmer(group_id, dimmer)) => {
                 self.active_scene_state_mut()
                     .fixture_group_state_mut(Some(group_id))
@@ -176,34 +433,47 @@ impl<'a> EngineState<'a> {
                     .update_button_state(&group, mapping.clone(), note_state, now);
             }
             (_, ControlEvent::TapTempo(now)) => {
-                self.clock.apply_event(ClockEvent::Tap(Utc::now()));
+                self.clock.apply_event(ClockEvent::Tap(self.time.now()));
                 dbg!(self.clock.bpm());
             }
+            (_, ControlEvent::ResyncBeat) => {
+                self.clock.apply_event(ClockEvent::Resync(self.time.now()));
+            }
+            (_, ControlEvent::SetDownbeat) => {
+                self.clock
+                    .apply_event(ClockEvent::SetDownbeat(self.time.now()));
+            }
+            (_, ControlEvent::NudgePhase(beats)) => {
+                self.clock.apply_event(ClockEvent::NudgePhase(beats));
+            }
+            (_, ControlEvent::AdjustBpm(delta)) => {
+                self.clock.apply_event(ClockEvent::AdjustBpm(delta));
+            }
+            (_, ControlEvent::ScaleBpm(factor)) => {
+                self.clock.apply_event(ClockEvent::ScaleBpm(factor));
+            }
         }
     }
+    // Mappings may not have a button for every scene, fixture group or rate
     fn meta_input_events(&self) -> impl Iterator<Item = InputEvent> + '_ {
-        let active_scene_button = self
-            .control_mapping
-            .meta_buttons
-            .values()
-            .find(|button| button.on_action == MetaButtonAction::SelectScene(self.active_scene_id))
-            .unwrap();
+        let active_scene_button =
+            self.control_mapping.meta_buttons.values().find(|button| {
+                button.on_action == MetaButtonAction::SelectScene(self.active_scene_id)
+            });
 
         let active_fixture_group_toggle_button =
             self.active_fixture_group_control
-                .map(|control_fixture_group_id| {
-                    self.control_mapping
-                        .meta_buttons
-                        .values()
-                        .find(|button| {
-                            button.on_action
-                                == MetaButtonAction::SelectFixtureGroupControl(
-                                    control_fixture_group_id,
-                                )
-                        })
-                        .unwrap()
+                .and_then(|control_fixture_group_id| {
+                    self.control_mapping.meta_buttons.values().find(|button| {
+                        button.on_action
+                            == MetaButtonAction::SelectFixtureGroupControl(control_fixture_group_id)
+                    })
                 });
 
+        let active_palette_button = self.control_mapping.meta_buttons.values().find(|button| {
+            button.on_action == MetaButtonAction::SelectPalette(self.active_palette().name.clone())
+        });
+
         let pressed_button_rate: Option<Rate> = self
             .control_fixture_group_state()
             .button_states
@@ -213,20 +483,23 @@ impl<'a> EngineState<'a> {
             .max();
 
         let clock_rate = self.control_fixture_group_state().clock_rate;
-        let active_clock_rate_button = self
-            .control_mapping
-            .meta_buttons
-            .values()
-            .find(|button| {
+        let active_clock_rate_button = self.control_mapping.meta_buttons.values().find(|button| {
+            button.on_action
+                == MetaButtonAction::UpdateClockRate(pressed_button_rate.unwrap_or(clock_rate))
+        });
+
+        let active_launch_quantization_button =
+            self.control_mapping.meta_buttons.values().find(|button| {
                 button.on_action
-                    == MetaButtonAction::UpdateClockRate(pressed_button_rate.unwrap_or(clock_rate))
-            })
-            .unwrap();
+                    == MetaButtonAction::UpdateLaunchQuantization(self.launch_quantization)
+            });
 
         vec![
-            Some(active_scene_button),
-            Some(active_clock_rate_button),
+            active_scene_button,
+            active_clock_rate_button,
+            active_launch_quantization_button,
             active_fixture_group_toggle_button,
+            active_palette_button,
         ]
         .into_iter()
         .flatten()
@@ -254,3 +527,315 @@ impl<'a> EngineState<'a> {
             .chain(self.meta_input_events())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roller_protocol::{
+        clock::ManualTime,
+        color::Color,
+        control::{ButtonCoordinate, ButtonGridLocation},
+        effect::{
+            DimmerEffect, DimmerModulator, EffectDirection, PixelEffect, PixelModulator, Waveform,
+        },
+    };
+    use std::sync::Arc;
+
+    use crate::control::{
+        button::{ButtonAction, ButtonType},
+        default_control_mapping,
+    };
+
+    fn press_button(
+        state: &mut EngineState,
+        location: ButtonGridLocation,
+        column_idx: usize,
+        row_idx: usize,
+    ) {
+        let coordinate = ButtonCoordinate::new(column_idx, row_idx);
+        state.apply_input_event(InputEvent::ButtonPressed(location, coordinate));
+        state.apply_input_event(InputEvent::ButtonReleased(location, coordinate));
+    }
+
+    fn press_meta_button(state: &mut EngineState, column_idx: usize, row_idx: usize) {
+        let coordinate = ButtonCoordinate::new(column_idx, row_idx);
+        state.apply_input_event(InputEvent::ButtonPressed(
+            ButtonGridLocation::MetaRight,
+            coordinate,
+        ));
+        state.apply_input_event(InputEvent::ButtonReleased(
+            ButtonGridLocation::MetaRight,
+            coordinate,
+        ));
+    }
+
+    #[test]
+    fn tap_tempo_button_sets_tempo() {
+        let control_mapping = Arc::new(default_control_mapping());
+        let time = Arc::new(ManualTime::new());
+        let mut state = EngineState::with_time(control_mapping, &[], Time::from(time.clone()));
+
+        for _ in 0..4 {
+            press_meta_button(&mut state, 0, 0);
+            time.advance_secs(0.4);
+        }
+
+        assert!((state.clock.bpm() - 150.0).abs() < 1e-6);
+        assert!((state.clock.secs_elapsed() - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn resync_button_restarts_the_beat() {
+        let control_mapping = Arc::new(default_control_mapping());
+        let time = Arc::new(ManualTime::new());
+        let mut state = EngineState::with_time(control_mapping, &[], Time::from(time.clone()));
+
+        time.advance_secs(3.3);
+        press_meta_button(&mut state, 0, 1);
+        assert_eq!(state.clock.secs_elapsed(), 0.0);
+    }
+
+    #[test]
+    fn rate_buttons_update_the_clock_rate() {
+        let control_mapping = Arc::new(default_control_mapping());
+        let time = Arc::new(ManualTime::new());
+        let mut state = EngineState::with_time(control_mapping, &[], Time::from(time.clone()));
+
+        press_meta_button(&mut state, 0, 4);
+        assert_eq!(
+            state.control_fixture_group_state().clock_rate,
+            Rate::new(2.0)
+        );
+        press_meta_button(&mut state, 0, 6);
+        assert_eq!(
+            state.control_fixture_group_state().clock_rate,
+            Rate::new(0.5)
+        );
+    }
+
+    fn global_color(state: &EngineState) -> Option<Color> {
+        state
+            .control_fixture_group_state()
+            .button_states
+            .global_color()
+    }
+
+    #[test]
+    fn quantized_buttons_launch_on_the_next_bar() {
+        let control_mapping = Arc::new(default_control_mapping());
+        let time = Arc::new(ManualTime::new());
+        let mut state = EngineState::with_time(control_mapping, &[], Time::from(time.clone()));
+        state.clock.apply_event(ClockEvent::BpmChanged(120.0));
+        state.launch_quantization = Quantization::Bar;
+
+        time.advance_secs(0.5);
+        press_button(&mut state, ButtonGridLocation::Main, 0, 7);
+        assert_eq!(global_color(&state), None);
+        assert_eq!(state.armed_buttons().count(), 1);
+
+        time.advance_secs(1.4);
+        state.launch_pending_actions();
+        assert_eq!(global_color(&state), None);
+
+        time.advance_secs(0.2);
+        state.launch_pending_actions();
+        assert_eq!(global_color(&state), Some(Color::White));
+        assert_eq!(state.armed_buttons().count(), 0);
+        // The release launches with the press, so the button isn't held
+        assert!(state
+            .control_fixture_group_state()
+            .button_states
+            .pressed_buttons()
+            .is_empty());
+    }
+
+    #[test]
+    fn pressing_an_armed_button_cancels_it() {
+        let control_mapping = Arc::new(default_control_mapping());
+        let time = Arc::new(ManualTime::new());
+        let mut state = EngineState::with_time(control_mapping, &[], Time::from(time.clone()));
+        state.launch_quantization = Quantization::Beat;
+
+        time.advance_secs(0.1);
+        press_button(&mut state, ButtonGridLocation::Main, 0, 7);
+        press_button(&mut state, ButtonGridLocation::Main, 0, 7);
+        assert_eq!(state.armed_buttons().count(), 0);
+
+        time.advance_secs(1.0);
+        state.launch_pending_actions();
+        assert_eq!(global_color(&state), None);
+    }
+
+    #[test]
+    fn flash_buttons_bypass_quantization() {
+        let control_mapping = Arc::new(ControlMapping::new(
+            vec![],
+            vec![ButtonMapping {
+                label: "Strobe".to_owned(),
+                coordinate: ButtonCoordinate::new(0, 0),
+                on_action: ButtonAction::ActivateDimmerEffect(
+                    DimmerModulator::new(Waveform::SineDown, Beats::new(1.0), 1.0).into(),
+                ),
+                quantization: Some(Quantization::FourBars),
+            }
+            .into_group(ButtonType::Flash)],
+            vec![],
+        ));
+        let time = Arc::new(ManualTime::new());
+        let mut state = EngineState::with_time(control_mapping, &[], Time::from(time.clone()));
+
+        time.advance_secs(0.1);
+        state.apply_input_event(InputEvent::ButtonPressed(
+            ButtonGridLocation::Main,
+            ButtonCoordinate::new(0, 0),
+        ));
+        assert_eq!(state.armed_buttons().count(), 0);
+        assert_eq!(
+            state
+                .control_fixture_group_state()
+                .button_states
+                .active_dimmer_effects(&state.effect_library)
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn quantized_scene_buttons_switch_scene_on_the_beat() {
+        let control_mapping = Arc::new(default_control_mapping());
+        let time = Arc::new(ManualTime::new());
+        let mut state = EngineState::with_time(control_mapping, &[], Time::from(time.clone()));
+        state.clock.apply_event(ClockEvent::BpmChanged(120.0));
+        state.launch_quantization = Quantization::Beat;
+
+        time.advance_secs(0.1);
+        press_button(&mut state, ButtonGridLocation::MetaBottom, 5, 0);
+        assert_eq!(state.active_scene_id, SceneId::new(1));
+        assert_eq!(state.armed_buttons().count(), 1);
+
+        time.advance_secs(0.4);
+        state.launch_pending_actions();
+        assert_eq!(state.active_scene_id, SceneId::new(2));
+    }
+
+    #[test]
+    fn restoring_a_show_keeps_the_running_clock() {
+        let control_mapping = Arc::new(default_control_mapping());
+        let time = Arc::new(ManualTime::new());
+        let mut state = EngineState::with_time(control_mapping, &[], Time::from(time.clone()));
+        state.clock.apply_event(ClockEvent::BpmChanged(120.0));
+        let show_state = ShowState::new(&state);
+
+        state.clock.apply_event(ClockEvent::BpmChanged(140.0));
+        time.advance_secs(1.3);
+        show_state.restore(&mut state);
+        show_state.restore_tempo(&mut state);
+        assert_eq!(state.clock.bpm(), 140.0);
+        assert!((state.clock.secs_elapsed() - 1.3).abs() < 1e-6);
+
+        // Tapped tempo is picked up from the show, carrying on from the current beat
+        state.clock.set_manual();
+        show_state.restore_tempo(&mut state);
+        assert_eq!(state.clock.bpm(), 120.0);
+        let beats_elapsed = f64::from(state.clock.snapshot().beats_elapsed());
+        assert!((beats_elapsed - 1.3 * 140.0 / 60.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn named_effects_follow_the_effect_library() {
+        let control_mapping = Arc::new(ControlMapping::new(
+            vec![],
+            vec![ButtonMapping {
+                label: "Pulse".to_owned(),
+                coordinate: ButtonCoordinate::new(0, 0),
+                on_action: ButtonAction::ActivateEffect("pulse".to_owned()),
+                quantization: None,
+            }
+            .into_group(ButtonType::Toggle)],
+            vec![],
+        ));
+        let mut state = EngineState::new(control_mapping.clone(), &[]);
+        let effect = |meter_length: f64| -> DimmerEffect {
+            DimmerModulator::new(Waveform::SineDown, Beats::new(meter_length), 1.0).into()
+        };
+        let active_dimmer_effects = |state: &EngineState| -> Vec<DimmerEffect> {
+            let (base_state, _) = state
+                .active_scene_state()
+                .fixture_group_values(&state.effect_library);
+            base_state.active_dimmer_effects.keys().cloned().collect()
+        };
+
+        state
+            .effect_library
+            .dimmer
+            .insert("pulse".to_owned(), effect(1.0));
+        assert_eq!(
+            control_mapping
+                .validate_effects(&EffectLibrary::default())
+                .unwrap_err()
+                .to_string(),
+            "effect \"pulse\" is not defined in the effect library"
+        );
+        assert!(control_mapping
+            .validate_effects(&state.effect_library)
+            .is_ok());
+
+        press_button(&mut state, ButtonGridLocation::Main, 0, 0);
+        assert_eq!(active_dimmer_effects(&state), vec![effect(1.0)]);
+
+        // A reloaded library changes effects that are already on
+        state
+            .effect_library
+            .dimmer
+            .insert("pulse".to_owned(), effect(2.0));
+        assert_eq!(active_dimmer_effects(&state), vec![effect(2.0)]);
+    }
+
+    #[test]
+    fn newer_pixel_effects_lead_across_button_groups() {
+        let effect = |direction: EffectDirection| -> PixelEffect {
+            PixelModulator::new(Waveform::SawUp, Beats::new(1.0), direction).into()
+        };
+        let button = |column_idx: usize, direction: EffectDirection| {
+            ButtonMapping {
+                label: format!("{:?}", direction),
+                coordinate: ButtonCoordinate::new(column_idx, 0),
+                on_action: ButtonAction::ActivatePixelEffect(effect(direction)),
+                quantization: None,
+            }
+            .into_group(ButtonType::Toggle)
+        };
+        let control_mapping = Arc::new(ControlMapping::new(
+            vec![],
+            vec![
+                button(0, EffectDirection::BottomToTop),
+                button(1, EffectDirection::FromCenter),
+            ],
+            vec![],
+        ));
+        let active_pixel_effects = |state: &EngineState| -> Vec<PixelEffect> {
+            let (base_state, _) = state
+                .active_scene_state()
+                .fixture_group_values(&state.effect_library);
+            base_state.active_pixel_effects.keys().cloned().collect()
+        };
+
+        // Whichever order the groups are pressed in, the last effect is layered on top
+        for (first, second) in [(0, 1), (1, 0)].iter() {
+            let time = Arc::new(ManualTime::new());
+            let mut state =
+                EngineState::with_time(control_mapping.clone(), &[], Time::from(time.clone()));
+
+            time.advance_secs(0.1);
+            press_button(&mut state, ButtonGridLocation::Main, *first, 0);
+            time.advance_secs(0.1);
+            press_button(&mut state, ButtonGridLocation::Main, *second, 0);
+
+            let directions = [EffectDirection::BottomToTop, EffectDirection::FromCenter];
+            assert_eq!(
+                active_pixel_effects(&state),
+                vec![effect(directions[*first]), effect(directions[*second])]
+            );
+        }
+    }
+}
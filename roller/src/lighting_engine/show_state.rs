@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use roller_protocol::{clock::Rate, control::NoteState, fixture::FixtureGroupId};
+
+use crate::{
+    control::{button::GroupToggleState, control_mapping::ControlMapping},
+    lighting_engine::{
+        ButtonStates, EngineState, FixtureGroupControlState, SceneControlState, SceneId,
+    },
+};
+
+// Bump this when the layout changes, and migrate older versions in `load_show_state`
+const SHOW_STATE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ButtonData {
+    label: String,
+    note_state: NoteState,
+    rate: Rate,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FixtureGroupData {
+    // `None` for the scene's base state, which applies to all fixtures
+    #[serde(default)]
+    fixture_group: Option<FixtureGroupId>,
+    dimmer: f64,
+    clock_rate: Rate,
+    // Labels of the buttons their groups are toggled on by
+    #[serde(default)]
+    toggled: Vec<String>,
+    // In the order they were triggered, later buttons take precedence
+    #[serde(default)]
+    buttons: Vec<ButtonData>,
+}
+impl FixtureGroupData {
+    fn new(
+        fixture_group: Option<FixtureGroupId>,
+        state: &FixtureGroupControlState,
+    ) -> FixtureGroupData {
+        let toggled = state
+            .button_states
+            .iter_groups()
+            .filter_map(|(group, toggle_state, _)| match toggle_state {
+                GroupToggleState::On(coordinate) => group
+                    .buttons
+                    .iter()
+                    .find(|button| button.coordinate == coordinate)
+                    .map(|button| button.label.clone()),
+                GroupToggleState::Off => None,
+            })
+            .collect();
+
+        let buttons = state
+            .button_states
+            .iter_info()
+            .map(|(_, button_info)| ButtonData {
+                label: button_info.button.label.clone(),
+                note_state: button_info.note_state,
+                rate: button_info.effect_rate,
+            })
+            .collect();
+
+        FixtureGroupData {
+            fixture_group,
+            dimmer: state.dimmer,
+            clock_rate: state.clock_rate,
+            toggled,
+            buttons,
+        }
+    }
+    fn control_state(
+        &self,
+        control_mapping: &ControlMapping,
+        now: Instant,
+    ) -> FixtureGroupControlState {
+        let mut button_states = ButtonStates::default();
+
+        for button in self.buttons.iter() {
+            match control_mapping.find_button_by_label(&button.label) {
+                Some((group, mapping)) => {
+                    button_states
+                        .button_group_state_mut(group)
+                        .insert((mapping.clone(), button.note_state), (now, button.rate));
+                }
+                None => eprintln!(
+                    "show button \"{}\" is not in the control mapping",
+                    button.label
+                ),
+            }
+        }
+
+        for label in self.toggled.iter() {
+            if let Some((group, mapping)) = control_mapping.find_button_by_label(label) {
+                button_states
+                    .set_button_group_toggle_state(group, GroupToggleState::On(mapping.coordinate));
+            }
+        }
+
+        FixtureGroupControlState {
+            dimmer: self.dimmer,
+            clock_rate: self.clock_rate,
+            button_states,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SceneData {
+    scene: SceneId,
+    dimmer_effect_intensity: f64,
+    color_effect_intensity: f64,
+    fixture_groups: Vec<FixtureGroupData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShowStateVersion {
+    version: u32,
+}
+
+/// The programmed control state of the engine, in a form that can be written to disk and restored
+/// after a restart. Buttons are referenced by label so shows survive the mapping being rearranged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShowState {
+    version: u32,
+    bpm: f64,
+    master_dimmer: f64,
+    active_scene: SceneId,
+    // Shows saved before palettes existed keep the project's first palette
+    #[serde(default)]
+    palette: Option<String>,
+    scenes: Vec<SceneData>,
+}
+impl ShowState {
+    pub fn new(state: &EngineState<'_>) -> ShowState {
+        let mut scenes: Vec<_> = state
+            .scene_fixture_group_button_states
+            .iter()
+            .map(|(scene_id, scene_state)| {
+                let mut fixture_groups: Vec<_> = scene_state.fixture_groups.iter().collect();
+                fixture_groups.sort_by_key(|(group_id, _)| usize::from(**group_id));
+
+                SceneData {
+                    scene: *scene_id,
+                    dimmer_effect_intensity: scene_state.dimmer_effect_intensity,
+                    color_effect_intensity: scene_state.color_effect_intensity,
+                    fixture_groups: std::iter::once(FixtureGroupData::new(None, &scene_state.base))
+                        .chain(fixture_groups.into_iter().map(|(group_id, group_state)| {
+                            FixtureGroupData::new(Some(*group_id), group_state)
+                        }))
+                        .collect(),
+                }
+            })
+            .collect();
+        scenes.sort_by_key(|scene| scene.scene);
+
+        ShowState {
+            version: SHOW_STATE_VERSION,
+            bpm: state.clock.bpm(),
+            master_dimmer: state.master_dimmer,
+            active_scene: state.active_scene_id,
+            palette: state
+                .palettes
+                .get(state.active_palette_idx)
+                .map(|palette| palette.name.clone()),
+            scenes,
+        }
+    }
+    /// Replaces the engine's control state with this show. Buttons that are no longer in the
+    /// control mapping are dropped. The clock carries on as it was, see `restore_tempo`
+    pub fn restore(&self, state: &mut EngineState<'_>) {
+        let now = state.time.instant();
+
+        state.master_dimmer = self.master_dimmer;
+        state.active_scene_id = self.active_scene;
+        state.active_fixture_group_control = None;
+        if let Some(palette) = self.palette.as_ref() {
+            state.select_palette(palette);
+        }
+        state.scene_fixture_group_button_states = self
+            .scenes
+            .iter()
+            .map(|scene| {
+                let mut scene_state = SceneControlState {
+                    dimmer_effect_intensity: scene.dimmer_effect_intensity,
+                    color_effect_intensity: scene.color_effect_intensity,
+                    ..SceneControlState::default()
+                };
+
+                for group in scene.fixture_groups.iter() {
+                    *scene_state.fixture_group_state_mut(group.fixture_group) =
+                        group.control_state(&state.control_mapping, now);
+                }
+
+                (scene.scene, scene_state)
+            })
+            .collect();
+    }
+    /// Picks up the show's tapped tempo, for when the show is restored on startup. Clocks
+    /// following a source keep the source's tempo
+    pub fn restore_tempo(&self, state: &mut EngineState<'_>) {
+        state.clock.set_manual_bpm(self.bpm);
+    }
+}
+
+pub async fn load_show_state(
+    path: impl AsRef<async_std::path::Path>,
+) -> Result<ShowState, async_std::io::Error> {
+    let show_state_contents = async_std::fs::read(path).await?;
+    let invalid_data = |err| async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, err);
+
+    let ShowStateVersion { version } =
+        ron::de::from_bytes(&show_state_contents).map_err(invalid_data)?;
+    if version > SHOW_STATE_VERSION {
+        return Err(async_std::io::Error::new(
+            async_std::io::ErrorKind::InvalidData,
+            format!(
+                "show was saved by a newer version of roller (version {}, expected {})",
+                version, SHOW_STATE_VERSION
+            ),
+        ));
+    }
+
+    ron::de::from_bytes(&show_state_contents).map_err(invalid_data)
+}
+
+pub async fn save_show_state(
+    path: impl AsRef<async_std::path::Path>,
+    show_state: &ShowState,
+) -> Result<(), async_std::io::Error> {
+    let path = path.as_ref();
+    let show_state_contents =
+        ron::ser::to_string_pretty(show_state, ron::ser::PrettyConfig::default())
+            .map_err(|err| async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, err))?;
+
+    if let Some(parent) = path.parent() {
+        async_std::fs::create_dir_all(parent).await?;
+    }
+
+    // Write alongside and rename over the previous show, so a crash mid-write can't lose it
+    let tmp_path = path.with_extension("ron.tmp");
+    async_std::fs::write(&tmp_path, show_state_contents).await?;
+    async_std::fs::rename(&tmp_path, path).await?;
+
+    Ok(())
+}
@@ -2,28 +2,37 @@ use clap::Clap;
 use futures::pin_mut;
 use futures::stream::{self, StreamExt};
 use rustc_hash::FxHashMap;
-use std::path::PathBuf;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use roller_protocol::{
-    control::{ButtonState, InputEvent},
-    fixture::{fold_fixture_dmx_data, Fixture, FixtureGroupId, FixtureParams, FixtureState},
+    color::ColorPalette,
+    control::{ButtonCoordinate, ButtonGridLocation, ButtonState, InputEvent, NoteState},
+    fixture::{fold_fixture_dmx_data, Fixture, FixtureGroupId, FixtureId, FixtureParams},
     lighting_engine::{
         render::{render_fixture_states, FixtureStateRenderContext},
         FixtureGroupState,
     },
-    ServerMessage,
+    ClientMessage, ServerMessage,
 };
 
 mod clock;
 mod control;
+mod effect_library;
 mod fixture;
 mod lighting_engine;
 mod project;
+mod timecode;
 mod utils;
 
-use crate::control::button::{pad_states, ButtonRef};
-use crate::lighting_engine::EngineState;
+use crate::control::button::pad_states;
+use crate::control::control_mapping::ControlMapping;
+use crate::effect_library::EffectLibrary;
+use crate::fixture::FixtureProfileSource;
+use crate::lighting_engine::{EngineState, ShowState};
+use crate::utils::FxIndexMap;
 
 #[global_allocator]
 static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
@@ -35,21 +44,321 @@ struct CliArgs {
     config: PathBuf,
     #[clap(long, default_value = "localhost:9010")]
     ola_host: String,
+    /// Address to serve the web UI on, overriding the project
+    #[clap(long)]
+    web_address: Option<IpAddr>,
+    /// Port to serve the web UI on, overriding the project
+    #[clap(long)]
+    web_port: Option<u16>,
+    /// Directory to serve the web UI from, overriding the project
+    #[clap(long, parse(from_os_str))]
+    web_assets: Option<PathBuf>,
+    /// Serve the web UI from assets built into the binary
+    #[clap(long)]
+    web_embedded_assets: bool,
+    /// Start from a named show, rather than the autosaved show
+    #[clap(long)]
+    show: Option<String>,
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-async fn run_tick<'a>(
-    state: &mut EngineState<'a>,
+#[derive(Clap, Debug)]
+enum Command {
+    /// Inspect the fixture profiles available to the project
+    Profile(ProfileCommand),
+    /// Work with control mappings
+    Mapping(MappingCommand),
+    /// Inspect the project's effect library
+    Effect(EffectCommand),
+    /// Inspect the project's fixture groups
+    Group(GroupCommand),
+    /// Inspect the project's saved shows
+    Show(ShowCommand),
+    /// Work with Ableton Link sessions
+    Link(LinkCommand),
+    /// Work with audio beat detection
+    Audio(AudioCommand),
+}
+
+#[derive(Clap, Debug)]
+enum ProfileCommand {
+    /// List each fixture profile slug and the file it was resolved from
+    List,
+}
+
+#[derive(Clap, Debug)]
+enum GroupCommand {
+    /// List each fixture group in precedence order, with its fixtures
+    List,
+}
+
+#[derive(Clap, Debug)]
+enum EffectCommand {
+    /// Check the effect library, then list each named effect and its kind
+    List,
+}
+
+#[derive(Clap, Debug)]
+enum ShowCommand {
+    /// List the named shows saved in the project
+    List,
+}
+
+#[derive(Clap, Debug)]
+enum LinkCommand {
+    /// Join a Link session with the project's settings and print its tempo and phase
+    Monitor {
+        /// Address of the interface to join on, overriding the project
+        #[clap(long)]
+        interface: Option<std::net::Ipv4Addr>,
+        /// Tempo of the session founded when there are no other peers, overriding the project
+        #[clap(long)]
+        bpm: Option<f64>,
+    },
+}
+
+#[derive(Clap, Debug)]
+enum AudioCommand {
+    /// Run beat detection over a WAV file, printing the tempo and downbeats found each second
+    Analyze {
+        #[clap(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+#[derive(Clap, Debug)]
+enum MappingCommand {
+    /// Print the built-in control mapping as RON, as a starting point for a project mapping
+    Export,
+}
+
+async fn run_command(
+    command: Command,
+    project: &project::Project,
+) -> Result<(), async_std::io::Error> {
+    match command {
+        Command::Profile(ProfileCommand::List) => {
+            let fixture_profiles =
+                fixture::find_fixture_profiles(&project.fixture_profile_directories()).await?;
+
+            // Group every copy of a profile under its slug, the first copy is the one that's used
+            let mut slug_sources: FxIndexMap<&str, Vec<(&str, &FixtureProfileSource)>> =
+                FxIndexMap::default();
+            for (profile, source) in fixture_profiles.iter() {
+                slug_sources
+                    .entry(&profile.slug)
+                    .or_default()
+                    .push((&profile.label, source));
+            }
+            slug_sources.sort_keys();
+
+            for (slug, sources) in slug_sources {
+                let (label, source) = sources[0];
+                println!("{:<28} {:<28} {}", slug, label, source);
+
+                for (_, shadowed_source) in &sources[1..] {
+                    println!("{:<28} {:<28} shadows {}", "", "", shadowed_source);
+                }
+            }
+        }
+        Command::Group(GroupCommand::List) => {
+            let fixtures = project.fixtures().await?;
+
+            for group in project.fixture_groups() {
+                let color = group
+                    .color
+                    .as_ref()
+                    .map(|color| format!("{:?}", color))
+                    .unwrap_or_default();
+                println!("{:<4} {:<28} {}", usize::from(group.id), group.name, color);
+
+                // Fixtures the group orders come first, in its order
+                let mut group_fixtures: Vec<_> = fixtures
+                    .iter()
+                    .filter(|fixture| fixture.params.group_ids.contains(&group.id))
+                    .collect();
+                group_fixtures.sort_by_key(|fixture| {
+                    group
+                        .fixtures
+                        .iter()
+                        .position(|key| FixtureId::from_key(key) == fixture.params.id)
+                        .unwrap_or(usize::MAX)
+                });
+
+                for fixture in group_fixtures {
+                    println!(
+                        "{:<4} {:<28} {}/{}",
+                        "",
+                        fixture.params.label(),
+                        fixture.params.universe,
+                        fixture.params.start_channel
+                    );
+                }
+            }
+        }
+        Command::Effect(EffectCommand::List) => {
+            let effect_library = project.effect_library().await?;
+
+            for (name, kind) in effect_library.names() {
+                println!("{:<28} {}", name, kind);
+            }
+        }
+        Command::Show(ShowCommand::List) => {
+            let mut show_names = vec![];
+
+            if let Ok(mut entries) = async_std::fs::read_dir(project.shows_path()).await {
+                while let Some(entry) = entries.next().await {
+                    let path = entry?.path();
+                    if path.extension().and_then(|extension| extension.to_str()) == Some("ron") {
+                        if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                            show_names.push(name.to_owned());
+                        }
+                    }
+                }
+            }
+            show_names.sort();
+
+            for name in show_names {
+                println!("{}", name);
+            }
+        }
+        Command::Link(LinkCommand::Monitor { interface, bpm }) => {
+            let mut config = project.link.clone().unwrap_or_default();
+            if interface.is_some() {
+                config.interface = interface;
+            }
+            if let Some(bpm) = bpm {
+                config.bpm = bpm;
+            }
+
+            let events = clock::link_clock_events(&config)?;
+            pin_mut!(events);
+            while let Some(event) = events.next().await {
+                if let roller_protocol::clock::ClockEvent::Sync {
+                    bpm, downbeat_at, ..
+                } = event
+                {
+                    let secs_since_downbeat = (chrono::Utc::now() - downbeat_at)
+                        .num_microseconds()
+                        .unwrap_or_default() as f64
+                        / 1_000_000.0;
+                    println!(
+                        "{:>7.2} bpm, beat {:.2} of {}",
+                        bpm,
+                        secs_since_downbeat * bpm / 60.0,
+                        config.quantum
+                    );
+                }
+            }
+        }
+        Command::Audio(AudioCommand::Analyze { file }) => {
+            let mut wav =
+                clock::WavReader::new(std::io::BufReader::new(std::fs::File::open(file)?))?;
+            let sample_rate = wav.sample_rate();
+            let mut tracker = clock::BeatTracker::new(sample_rate);
+
+            let mut samples = Vec::new();
+            let mut next_report_secs = 1.0;
+            while wav.read_mono(&mut samples, sample_rate as usize / 100)? > 0 {
+                for estimate in tracker.process(&samples) {
+                    let secs = tracker.frames() as f64 / tracker.frame_rate();
+                    if secs < next_report_secs {
+                        continue;
+                    }
+                    next_report_secs = secs.floor() + 1.0;
+
+                    println!(
+                        "{:>7.2}s {:>7.2} bpm  confidence {:.2}  downbeat at {:.2}s",
+                        secs,
+                        estimate.bpm,
+                        estimate.confidence,
+                        estimate.downbeat_frame / tracker.frame_rate()
+                    );
+                }
+            }
+        }
+        Command::Mapping(MappingCommand::Export) => {
+            let control_mapping = ron::ser::to_string_pretty(
+                &control::default_control_mapping(),
+                ron::ser::PrettyConfig::default(),
+            )
+            .map_err(|err| async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, err))?;
+
+            println!("{}", control_mapping);
+        }
+    }
+
+    Ok(())
+}
+
+async fn load_fixture_params(config: &Path) -> Result<Vec<FixtureParams>, async_std::io::Error> {
+    let project = project::Project::load(config).await?;
+    let fixtures = project.fixtures().await?;
+
+    Ok(fixtures.into_iter().map(|fixture| fixture.params).collect())
+}
+
+async fn reload_fixture_params(
+    config: &Path,
+    fixture_params: &mut Vec<FixtureParams>,
+    web_server_message_send: &async_std::sync::Sender<ServerMessage>,
+) {
+    let new_fixture_params = match load_fixture_params(config).await {
+        Ok(new_fixture_params) => new_fixture_params,
+        Err(err) => {
+            // Keep running with the previous fixtures until the project is fixed
+            eprintln!("unable to reload project: {}", err);
+            return;
+        }
+    };
+
+    let updated_fixture_params: Vec<_> = new_fixture_params
+        .iter()
+        .filter(|params| !fixture_params.contains(params))
+        .map(|params| (params.id, params.clone()))
+        .collect();
+    let removed_fixture_ids: Vec<_> = fixture_params
+        .iter()
+        .map(|params| params.id)
+        .filter(|id| !new_fixture_params.iter().any(|params| &params.id == id))
+        .collect();
+
+    if updated_fixture_params.len() > 0 {
+        web_server_message_send
+            .send(ServerMessage::FixtureParamsUpdated(updated_fixture_params))
+            .await;
+    }
+    if removed_fixture_ids.len() > 0 {
+        web_server_message_send
+            .send(ServerMessage::FixtureParamsRemoved(removed_fixture_ids))
+            .await;
+    }
+
+    *fixture_params = new_fixture_params;
+}
+
+async fn run_tick(
+    state: &mut EngineState<'_>,
     fixtures: &Vec<FixtureParams>,
     dmx_sender: &async_std::sync::Sender<(i32, [u8; 512])>,
-    midi_controller: Option<&control::midi::MidiController>,
+    midi_controllers: &[control::midi::MidiController],
+    osc_controller: Option<&mut control::osc::OscController>,
     current_fixture_group_states: &mut (
         FixtureGroupState,
         FxHashMap<FixtureGroupId, FixtureGroupState>,
     ),
-    current_button_states: &mut rustc_hash::FxHashMap<ButtonRef<'a>, ButtonState>,
+    current_button_states: &mut FxHashMap<(ButtonGridLocation, ButtonCoordinate), ButtonState>,
+    current_palette: &mut ColorPalette,
     web_server_message_send: &async_std::sync::Sender<ServerMessage>,
 ) {
-    let (base_state, fixture_group_states) = state.active_scene_state().fixture_group_values();
+    state.launch_pending_actions();
+
+    let (mut base_state, fixture_group_states) = state
+        .active_scene_state()
+        .fixture_group_values(&state.effect_library);
+    // Groups don't choose their own blend mode, so every fixture falls back to the project's
+    base_state.pixel_blend_mode = Some(state.pixel_blend_mode);
 
     let new_fixture_states: Vec<_> = render_fixture_states(
         FixtureStateRenderContext {
@@ -57,6 +366,7 @@ async fn run_tick<'a>(
             fixture_group_states: &fixture_group_states.iter().collect::<Vec<_>>(),
             clock_snapshot: state.clock.snapshot(),
             master_dimmer: state.master_dimmer,
+            palette: state.active_palette(),
         },
         &fixtures.iter().collect::<Vec<_>>(),
     );
@@ -92,6 +402,34 @@ async fn run_tick<'a>(
         states
     };
 
+    if let Some(osc_controller) = osc_controller.as_ref() {
+        // Palette entries change colour with the palette, so every group is sent when it changes
+        if state.active_palette() != current_palette {
+            osc_controller.send_group_colors(
+                std::iter::once((None, &base_state)).chain(
+                    fixture_group_states
+                        .iter()
+                        .map(|(id, state)| (Some(*id), state)),
+                ),
+                state.active_palette(),
+            );
+        } else {
+            osc_controller.send_group_colors(
+                updated_fixture_group_states
+                    .iter()
+                    .map(|(id, state)| (*id, state)),
+                state.active_palette(),
+            );
+        }
+    }
+
+    if state.active_palette() != current_palette {
+        *current_palette = state.active_palette().clone();
+        web_server_message_send
+            .send(ServerMessage::PaletteUpdated(current_palette.clone()))
+            .await;
+    }
+
     if updated_fixture_group_states.len() > 0 {
         web_server_message_send
             .send(ServerMessage::FixtureGroupStatesUpdated(
@@ -100,7 +438,8 @@ async fn run_tick<'a>(
             .await;
     }
 
-    let new_button_states = pad_states(
+    // Buttons are kept by where they are, as the mapping they're from may be reloaded
+    let mut new_button_states: FxHashMap<_, _> = pad_states(
         &state.control_mapping,
         &state
             .control_fixture_group_state()
@@ -108,26 +447,40 @@ async fn run_tick<'a>(
             .iter_group_toggle_states()
             .collect(),
         state.input_events(),
-    );
+    )
+    .into_iter()
+    .map(|(button_ref, state)| ((button_ref.location(), *button_ref.coordinate()), state))
+    .collect();
+    for button_ref in state.armed_buttons() {
+        new_button_states.insert(
+            (button_ref.location(), *button_ref.coordinate()),
+            ButtonState::Armed,
+        );
+    }
 
     // find the buttons that have updated since the last tick
     let changed_button_states: Vec<_> = new_button_states
         .iter()
-        .filter(|(button_ref, state)| {
+        .filter(|(key, state)| {
             current_button_states
-                .get(button_ref)
+                .get(key)
                 .map(|prev_state| state != &prev_state)
                 .unwrap_or(true)
         })
-        .map(|(button_ref, state)| (button_ref.location(), *button_ref.coordinate(), *state))
+        .map(|((location, coordinate), state)| (*location, *coordinate, *state))
         .collect();
 
-    if let Some(midi_controller) = midi_controller {
+    for midi_controller in midi_controllers {
         midi_controller
             .set_button_states(changed_button_states.clone().into_iter())
             .await;
     }
 
+    if let Some(osc_controller) = osc_controller {
+        osc_controller.send_button_states(&changed_button_states);
+        osc_controller.send_clock(&state.clock.snapshot());
+    }
+
     if changed_button_states.len() > 0 {
         web_server_message_send
             .send(ServerMessage::ButtonStatesUpdated(changed_button_states))
@@ -138,23 +491,204 @@ async fn run_tick<'a>(
     *current_fixture_group_states = (base_state, fixture_group_states);
 }
 
+async fn load_control_mapping(
+    config: &Path,
+    effect_library: &EffectLibrary,
+) -> Result<ControlMapping, async_std::io::Error> {
+    let project = project::Project::load(config).await?;
+    let control_mapping = project.control_mapping(effect_library).await?;
+    // Cues press buttons by label, so are checked against the new mapping too
+    project.timeline(&control_mapping)?;
+
+    Ok(control_mapping)
+}
+
+async fn reload_control_mapping(config: &Path, state: &mut EngineState<'_>) {
+    let control_mapping = match load_control_mapping(config, &state.effect_library).await {
+        Ok(control_mapping) => control_mapping,
+        Err(err) => {
+            // Keep running with the previous mapping until it's fixed
+            eprintln!("unable to reload control mapping: {}", err);
+            return;
+        }
+    };
+    if control_mapping == *state.control_mapping {
+        return;
+    }
+
+    // Buttons carry over by label, as they do when a show is loaded, while the clock and the
+    // selected group carry on as they were. Launches waiting for the beat are dropped
+    let show_state = ShowState::new(state);
+    let active_fixture_group_control = state.active_fixture_group_control;
+
+    state.control_mapping = Arc::new(control_mapping);
+    state.pending_actions.clear();
+    show_state.restore(state);
+    state.active_fixture_group_control = active_fixture_group_control;
+
+    println!("reloaded control mapping");
+}
+
+async fn reload_effect_library(project: &project::Project, state: &mut EngineState<'_>) {
+    let control_mapping = &state.control_mapping;
+    let result = project.effect_library().await.and_then(|effect_library| {
+        // Buttons keep the previous library until the new one has all the effects they use
+        control_mapping
+            .validate_effects(&effect_library)
+            .map(|()| effect_library)
+            .map_err(|err| {
+                async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, err.to_string())
+            })
+    });
+
+    match result {
+        Ok(effect_library) => {
+            if effect_library != state.effect_library {
+                state.effect_library = effect_library;
+                println!("reloaded effect library");
+            }
+        }
+        Err(err) => eprintln!("unable to reload effect library: {}", err),
+    }
+}
+
+// A broken autosave shouldn't stop the show starting, so it's moved aside for a fresh show
+async fn load_autosave(path: &Path) -> Option<ShowState> {
+    match lighting_engine::load_show_state(path).await {
+        Ok(show_state) => Some(show_state),
+        Err(err) => {
+            let mut broken_path = path.as_os_str().to_owned();
+            broken_path.push(".broken");
+            let broken_path = std::path::PathBuf::from(broken_path);
+
+            eprintln!("unable to restore autosave: {}", err);
+            match async_std::fs::rename(path, &broken_path).await {
+                Ok(()) => eprintln!(
+                    "moved it to {}, starting with a fresh show",
+                    broken_path.display()
+                ),
+                Err(err) => eprintln!(
+                    "unable to move it aside, starting with a fresh show: {}",
+                    err
+                ),
+            }
+            None
+        }
+    }
+}
+
+async fn load_show(project: &project::Project, name: &str, state: &mut EngineState<'_>) {
+    let result = match project.show_path(name) {
+        Ok(path) => lighting_engine::load_show_state(path).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(show_state) => {
+            show_state.restore(state);
+            println!("loaded show \"{}\"", name);
+        }
+        Err(err) => eprintln!("unable to load show \"{}\": {}", name, err),
+    }
+}
+
+async fn fire_cue(cue: &timecode::Cue, project: &project::Project, state: &mut EngineState<'_>) {
+    if let Some(label) = cue.label.as_ref() {
+        println!("cue \"{}\"", label);
+    }
+    if let Some(name) = cue.show.as_ref() {
+        load_show(project, name, state).await;
+    }
+    if let Some(scene_id) = cue.scene {
+        state.apply_timed_control_event(lighting_engine::ControlEvent::SelectScene(scene_id));
+    }
+    // Buttons are pressed and released, as if tapped
+    let control_mapping = state.control_mapping.clone();
+    for label in cue.buttons.iter() {
+        if let Some((group, button)) = control_mapping.find_button_by_label(label) {
+            for note_state in [NoteState::On, NoteState::Off].iter() {
+                state.apply_timed_control_event(lighting_engine::ControlEvent::UpdateButton(
+                    group.clone(),
+                    button.clone(),
+                    *note_state,
+                    state.time.instant(),
+                ));
+            }
+        }
+    }
+}
+
 #[async_std::main]
 async fn main() -> Result<(), async_std::io::Error> {
     let args = CliArgs::parse();
 
-    let project = project::Project::load(args.config).await?;
+    let project = project::Project::load(&args.config).await?;
+
+    if let Some(command) = args.command {
+        return run_command(command, &project).await;
+    }
+
     let fixtures = project.fixtures().await?;
-    let fixture_params: Vec<_> = fixtures.into_iter().map(|fixture| fixture.params).collect();
+    let mut fixture_params: Vec<_> = fixtures.into_iter().map(|fixture| fixture.params).collect();
 
-    let midi_controller = match project.midi_controller.as_ref() {
-        Some(midi_controller_name) => {
-            control::midi::MidiController::new_for_device_name(midi_controller_name).ok()
-        }
+    // Controllers that can't be found are skipped, so one missing controller doesn't stop the show
+    let midi_controllers: Vec<_> = project
+        .midi_controllers
+        .iter()
+        .filter_map(
+            |config| match control::midi::MidiController::new(config.clone()) {
+                Ok(midi_controller) => Some(midi_controller),
+                Err(err) => {
+                    eprintln!(
+                        "unable to connect to MIDI controller {}: {}",
+                        config.device_description(),
+                        err
+                    );
+                    None
+                }
+            },
+        )
+        .collect();
+
+    // Like MIDI controllers, the show carries on without OSC if its port can't be bound
+    let mut osc_controller = match project.osc.clone() {
+        Some(config) => match control::osc::OscController::bind(config).await {
+            Ok(osc_controller) => {
+                if let Ok(address) = osc_controller.local_addr() {
+                    println!("OSC listening on {}", address);
+                }
+                Some(osc_controller)
+            }
+            Err(err) => {
+                eprintln!("unable to start OSC: {}", err);
+                None
+            }
+        },
         None => None,
     };
 
-    let control_mapping = control::default_control_mapping();
-    let mut state = EngineState::new(&control_mapping);
+    let effect_library = project.effect_library().await?;
+    let control_mapping = Arc::new(project.control_mapping(&effect_library).await?);
+    let mut state = EngineState::new(control_mapping.clone(), project.palettes());
+    state.effect_library = effect_library;
+    state.clock.set_stop_behaviour(project.clock_stop);
+    state.clock.set_time_signature(project.time_signature);
+    state.launch_quantization = project.launch_quantization;
+    state.pixel_blend_mode = project.pixel_blend_mode;
+
+    let mut timeline = project.timeline(&control_mapping)?;
+
+    let initial_show = match args.show.as_ref() {
+        Some(name) => Some(lighting_engine::load_show_state(project.show_path(name)?).await?),
+        None if project.autosave_path().exists() => load_autosave(&project.autosave_path()).await,
+        None => None,
+    };
+    // Clock sources haven't started yet, so the show's tempo is used until one does
+    if let Some(show_state) = initial_show {
+        show_state.restore(&mut state);
+        show_state.restore_tempo(&mut state);
+    }
+    // Written on the first autosave, so a named show is picked up if the process restarts
+    let mut saved_show_state: Option<ShowState> = None;
 
     let mut ola_client: Option<ola_client::OlaClient> =
         ola_client::OlaClient::connect(&args.ola_host).await.ok();
@@ -172,82 +706,199 @@ async fn main() -> Result<(), async_std::io::Error> {
         }
     });
 
+    // Only sent to when the clock has changed, so the output isn't woken for nothing
+    let (clock_output_send, clock_output_recv) = async_std::sync::channel(1);
+    let mut clock_output_clock = state.clock.clone();
+    if !project.midi_clock_outputs.is_empty() {
+        async_std::task::spawn(clock::midi_clock_output(
+            project.midi_clock_outputs.clone(),
+            state.clock.clone(),
+            clock_output_recv,
+        ));
+    }
+
     enum Event {
         Tick,
         Input(InputEvent),
+        Osc(control::osc::OscEvent),
         Clock(roller_protocol::clock::ClockEvent),
+        Timecode(midi::Timecode),
+        ClockSourceChanged(Option<String>),
+        Reload,
+        ReloadEffects,
+        Autosave,
+        SaveShow(String),
+        LoadShow(String),
     }
 
     let mut current_fixture_group_states = (FixtureGroupState::default(), FxHashMap::default());
-    let mut current_button_states = pad_states(
-        &control_mapping,
+    let mut current_palette = ColorPalette::default();
+    let initial_button_states: Vec<_> = pad_states(
+        &state.control_mapping,
         &state
             .control_fixture_group_state()
             .button_states
             .iter_group_toggle_states()
             .collect(),
         state.input_events(),
-    );
+    )
+    .into_iter()
+    .map(|(button_ref, value)| {
+        (
+            (button_ref.location(), *button_ref.coordinate()),
+            (button_ref.label().to_owned(), value),
+        )
+    })
+    .collect();
+    let mut current_button_states: FxHashMap<_, _> = initial_button_states
+        .iter()
+        .map(|(key, (_, value))| (*key, *value))
+        .collect();
 
-    if let Some(midi_controller) = &midi_controller {
-        midi_controller.run_pad_startup().await;
+    futures::future::join_all(
+        midi_controllers
+            .iter()
+            .map(|midi_controller| midi_controller.run_pad_startup()),
+    )
+    .await;
+    for midi_controller in midi_controllers.iter() {
         midi_controller
             .set_button_states(
-                current_button_states.iter().map(|(button_ref, val)| {
-                    (button_ref.location(), *button_ref.coordinate(), *val)
-                }),
+                current_button_states
+                    .iter()
+                    .map(|((location, coordinate), val)| (*location, *coordinate, *val)),
             )
             .await;
     }
 
-    let (web_input_events_send, web_input_events_recv) = async_std::sync::channel::<InputEvent>(64);
+    if let Some(osc_controller) = osc_controller.as_ref() {
+        osc_controller.send_button_states(
+            &current_button_states
+                .iter()
+                .map(|((location, coordinate), val)| (*location, *coordinate, *val))
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let (web_client_message_send, web_client_message_recv) =
+        async_std::sync::channel::<ClientMessage>(64);
     let (web_server_message_send, web_server_message_recv) =
         async_std::sync::channel::<ServerMessage>(64);
 
-    let web_input_events = Some(
-        web_input_events_recv
-            .map(|event| Event::Input(event))
+    let web_events = Some(
+        web_client_message_recv
+            .map(|message| match message {
+                ClientMessage::Input(event) => Event::Input(event),
+                ClientMessage::SaveShow(name) => Event::SaveShow(name),
+                ClientMessage::LoadShow(name) => Event::LoadShow(name),
+            })
             .boxed(),
     );
 
     let ticks = Some(
-        utils::tick_stream(Duration::from_millis(1000 / 40))
+        utils::tick_stream(state.time.clone(), Duration::from_millis(1000 / 40))
             .map(|()| Event::Tick)
             .boxed(),
     );
-    let input_events = midi_controller
+    let input_events = Some(
+        stream::select_all(
+            midi_controllers
+                .iter()
+                .map(|controller| controller.input_events().boxed()),
+        )
+        .map(Event::Input)
+        .boxed(),
+    );
+    let osc_events = osc_controller
         .as_ref()
-        .map(|controller| controller.input_events().map(Event::Input).boxed());
-    let clock_events = project
-        .midi_clock_events()
-        .map(|events| events.map(Event::Clock).boxed());
+        .map(|osc_controller| osc_controller.events().map(Event::Osc).boxed());
+    let clock_events = Some(
+        clock::clock_source_events(project.clock_sources())
+            .map(|event| match event {
+                clock::ClockSourceEvent::Clock(event) => Event::Clock(event),
+                clock::ClockSourceEvent::ActiveSourceChanged(name) => {
+                    Event::ClockSourceChanged(name)
+                }
+            })
+            .boxed(),
+    );
+    let timecode_events = project.timecode().map(|config| {
+        timecode::timecode_events(&config)
+            .map(Event::Timecode)
+            .boxed()
+    });
+
+    // Fixtures and the control mapping are rebuilt when the project, its fixture profiles or
+    // mapping change, scene, clock and button state carry over
+    let reloads = Some(
+        utils::watch_paths(
+            project
+                .fixture_source_paths()
+                .into_iter()
+                .chain(project.control_mapping_path())
+                .collect(),
+            Duration::from_secs(1),
+        )
+        .map(|()| Event::Reload)
+        .boxed(),
+    );
+
+    let effect_reloads = project.effect_library_path().map(|path| {
+        utils::watch_paths(vec![path], Duration::from_secs(1))
+            .map(|()| Event::ReloadEffects)
+            .boxed()
+    });
+
+    let autosaves = Some(
+        utils::tick_stream(state.time.clone(), Duration::from_secs(1))
+            .map(|()| Event::Autosave)
+            .boxed(),
+    );
 
     let events = stream::select_all(
-        vec![ticks, input_events, clock_events, web_input_events]
-            .into_iter()
-            .flatten(),
+        vec![
+            ticks,
+            input_events,
+            osc_events,
+            clock_events,
+            timecode_events,
+            web_events,
+            reloads,
+            effect_reloads,
+            autosaves,
+        ]
+        .into_iter()
+        .flatten(),
     );
 
     pin_mut!(events);
 
-    roller_web::serve_frontend(
-        current_button_states
-            .iter()
-            .map(|(button_ref, value)| {
-                (
-                    (button_ref.location(), *button_ref.coordinate()),
-                    (button_ref.label().to_owned(), *value),
-                )
-            })
-            .collect(),
+    let mut web_server_config = project.web_server_config();
+    if let Some(address) = args.web_address {
+        web_server_config.address.set_ip(address);
+    }
+    if let Some(port) = args.web_port {
+        web_server_config.address.set_port(port);
+    }
+    if args.web_embedded_assets {
+        web_server_config.assets = roller_web::WebAssets::Embedded;
+    } else if let Some(path) = args.web_assets.as_ref() {
+        web_server_config.assets = roller_web::WebAssets::Directory(path.clone());
+    }
+
+    let web_address = roller_web::serve_frontend(
+        web_server_config,
+        initial_button_states.into_iter().collect(),
         fixture_params
             .clone()
             .into_iter()
             .map(|params| (params.id, params))
             .collect(),
         web_server_message_recv,
-        web_input_events_send,
-    );
+        web_client_message_send,
+    )
+    .map_err(|err| async_std::io::Error::new(async_std::io::ErrorKind::Other, err))?;
+    println!("web UI listening on http://{}", web_address);
 
     while let Some(event) = events.next().await {
         match event {
@@ -256,22 +907,91 @@ async fn main() -> Result<(), async_std::io::Error> {
                     &mut state,
                     &fixture_params,
                     &dmx_sender,
-                    midi_controller.as_ref(),
+                    &midi_controllers,
+                    osc_controller.as_mut(),
                     &mut current_fixture_group_states,
                     &mut current_button_states,
+                    &mut current_palette,
                     &web_server_message_send,
                 )
                 .await;
+
+                if let Some(timeline) = timeline.as_mut() {
+                    for cue in timeline.tick(state.time.instant()) {
+                        fire_cue(cue, &project, &mut state).await;
+                    }
+                }
+
+                if state.clock != clock_output_clock {
+                    clock_output_clock = state.clock.clone();
+                    let _ = clock_output_send.try_send(clock_output_clock.clone());
+                }
             }
             Event::Input(event) => {
                 state.apply_input_event(event);
             }
+            Event::Osc(control::osc::OscEvent::Input(event)) => {
+                state.apply_input_event(event);
+            }
+            Event::Osc(control::osc::OscEvent::Control(event)) => {
+                state.apply_control_event(event);
+            }
             Event::Clock(event) => {
                 state.clock.apply_event(event);
                 web_server_message_send
                     .send(ServerMessage::ClockUpdated(state.clock.clone()))
                     .await;
             }
+            Event::Timecode(timecode) => {
+                if let Some(timeline) = timeline.as_mut() {
+                    for cue in timeline.receive(timecode, state.time.instant()) {
+                        fire_cue(cue, &project, &mut state).await;
+                    }
+                }
+            }
+            Event::ClockSourceChanged(name) => {
+                if name.is_none() {
+                    state.clock.set_manual();
+                }
+                state.clock.reset_transport();
+                web_server_message_send
+                    .send(ServerMessage::ClockSourceChanged(name))
+                    .await;
+            }
+            Event::Reload => {
+                reload_fixture_params(&args.config, &mut fixture_params, &web_server_message_send)
+                    .await;
+                reload_control_mapping(&args.config, &mut state).await;
+            }
+            Event::ReloadEffects => {
+                reload_effect_library(&project, &mut state).await;
+            }
+            Event::Autosave => {
+                // Only written when something has changed since the last save
+                let show_state = ShowState::new(&state);
+                if Some(&show_state) != saved_show_state.as_ref() {
+                    match lighting_engine::save_show_state(project.autosave_path(), &show_state)
+                        .await
+                    {
+                        Ok(()) => saved_show_state = Some(show_state),
+                        Err(err) => eprintln!("unable to autosave show: {}", err),
+                    }
+                }
+            }
+            Event::SaveShow(name) => {
+                let show_state = ShowState::new(&state);
+                let result = match project.show_path(&name) {
+                    Ok(path) => lighting_engine::save_show_state(path, &show_state).await,
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(()) => println!("saved show \"{}\"", name),
+                    Err(err) => eprintln!("unable to save show \"{}\": {}", name, err),
+                }
+            }
+            Event::LoadShow(name) => {
+                load_show(&project, &name, &mut state).await;
+            }
         }
     }
     unreachable!()
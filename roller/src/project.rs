@@ -1,22 +1,79 @@
-use async_std::prelude::*;
 use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
 
 use roller_protocol::{
-    clock::ClockEvent,
-    fixture::{Fixture, FixtureEffectType, FixtureGroupId, FixtureLocation},
+    clock::{Quantization, StopBehaviour, TimeSignature},
+    color::{Color, ColorPalette},
+    effect::PixelBlendMode,
+    fixture::{
+        Fixture, FixtureEffectType, FixtureGroup, FixtureGroupId, FixtureId, FixtureLocation,
+        FixtureOrientation, FixtureOverrides,
+    },
 };
 
-use crate::clock::midi_clock_events;
+use roller_web::{WebAssets, WebServerConfig};
+
+use crate::{
+    clock::{
+        audio_clock_events, link_clock_events, AudioClockConfig, ClockSource, LinkConfig,
+        LINK_CLOCK_NAME,
+    },
+    control::{
+        button::MetaButtonAction,
+        control_mapping::{load_control_mapping, ControlMapping},
+        default_control_mapping,
+        midi::MidiControllerConfig,
+        osc::OscConfig,
+    },
+    effect_library::{load_effect_library, EffectLibrary},
+    fixture::{load_fixture_profiles, FixtureProfileDirectory},
+    lighting_engine::SceneId,
+    timecode::{TimecodeConfig, Timeline},
+};
+
+// `z` is required, as projects from before locations were 3D used `y` for what's now `z`
+#[derive(Debug, Clone, Deserialize)]
+struct ProjectFixtureLocation {
+    x: f64,
+    y: f64,
+    z: Option<f64>,
+    #[serde(default)]
+    orientation: FixtureOrientation,
+}
+impl ProjectFixtureLocation {
+    fn location(&self) -> Result<FixtureLocation, String> {
+        let z = self.z.ok_or_else(|| {
+            format!(
+                "fixture location x = {}, y = {} has no z (y is now the height above the floor \
+                 in metres, and z the distance from the front of the stage, which used to be y)",
+                self.x, self.y
+            )
+        })?;
+
+        Ok(FixtureLocation::new(self.x, self.y, z).with_orientation(self.orientation))
+    }
+}
 
 #[derive(Debug, Clone, Deserialize)]
 struct ProjectFixture {
+    // Identifies the fixture across reloads and in groups' fixture lists, unique within the
+    // project. Defaults to its universe and start channel, as `universe/channel`, so fixtures
+    // patched in around it keep theirs. Set it before re-addressing a fixture, as the default
+    // changes with the address
+    id: Option<String>,
     start_channel: usize,
+    #[serde(default)]
+    groups: Vec<FixtureGroupId>,
+    // Replaced by `groups`, still read so older projects load
     group_id: Option<FixtureGroupId>,
-    location: Option<FixtureLocation>,
+    location: Option<ProjectFixtureLocation>,
     #[serde(rename = "fixture_profile")]
     fixture_profile_slug: String,
     #[serde(default = "FixtureEffectType::all")]
     enabled_effects: Vec<FixtureEffectType>,
+    #[serde(flatten)]
+    overrides: FixtureOverrides,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -25,64 +82,811 @@ struct ProjectUniverse {
     fixtures: Vec<ProjectFixture>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ProjectWebServer {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    // Directory the web UI is served from, ignored when `embedded_assets` is set
+    assets: Option<PathBuf>,
+    #[serde(default)]
+    embedded_assets: bool,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Project {
+    #[serde(skip)]
+    path: PathBuf,
+    // Directory containing the project file, relative paths in the project are resolved from here
+    #[serde(skip)]
+    root: PathBuf,
     label: String,
-    pub midi_controller: Option<String>,
+    #[serde(default)]
+    pub midi_controllers: Vec<MidiControllerConfig>,
+    // Replaced by `midi_controllers`, still read so older projects load
+    midi_controller: Option<String>,
+    // Control from OSC apps like TouchOSC, which are sent state back
+    pub osc: Option<OscConfig>,
+    // Clock sources in priority order, the first that's ticking drives the clock. MIDI clocks
+    // come before Link, then audio clocks, and tapped tempo is used when none are ticking
     #[serde(default = "Vec::new")]
     pub midi_clocks: Vec<String>,
+    // Overrides the order above by source name, a MIDI clock's device name, "Ableton Link" or
+    // an audio clock's name. Sources that aren't listed follow in the order above
+    #[serde(default)]
+    pub clock_priority: Vec<String>,
+    // Follow the tempo and phase of an Ableton Link session on the local network
+    pub link: Option<LinkConfig>,
+    // Follow the beat heard on an audio input, when there's no clock to sync to
+    #[serde(default)]
+    pub audio_clocks: Vec<AudioClockConfig>,
+    // MIDI outputs sent our clock, so other gear can follow tapped tempo or any clock source
+    #[serde(default)]
+    pub midi_clock_outputs: Vec<String>,
+    // What effects do while a clock source's transport is stopped
+    #[serde(default)]
+    pub clock_stop: StopBehaviour,
+    // Used until a clock source says otherwise
+    #[serde(default)]
+    pub time_signature: TimeSignature,
+    // When pressed buttons and scenes launch, buttons may override this in the control mapping
+    #[serde(default)]
+    pub launch_quantization: Quantization,
+    // How the pixel effects active on a fixture combine
+    #[serde(default)]
+    pub pixel_blend_mode: PixelBlendMode,
+    // Timecode to follow, firing cues as it passes them
+    timecode: Option<TimecodeConfig>,
+    // Searched in order, before the user profile directory and the built-in profiles
+    #[serde(default = "Project::default_fixture_profile_paths")]
+    fixture_profile_paths: Vec<PathBuf>,
+    // A RON or TOML control mapping, the built-in mapping is used if not set
+    control_mapping: Option<PathBuf>,
+    // A RON or TOML library of named effects for the control mapping, reloaded when it changes
+    effect_library: Option<PathBuf>,
+    // Groups are listed in precedence order, when a fixture is in more than one
+    // group, groups later in the list win
+    #[serde(default)]
+    groups: Vec<FixtureGroup>,
+    // Show state is saved here as it changes, and restored on startup
+    #[serde(default = "Project::default_autosave_path")]
+    autosave_path: PathBuf,
+    // Directory named shows are saved to and loaded from
+    #[serde(default = "Project::default_shows_path")]
+    shows_path: PathBuf,
+    #[serde(default)]
+    web: ProjectWebServer,
+    // The first palette is active on startup
+    #[serde(default)]
+    palettes: Vec<ColorPalette>,
+    // Scenes the control mapping may select between
+    #[serde(default = "Project::default_scenes")]
+    scenes: Vec<SceneId>,
     universes: Vec<ProjectUniverse>,
 }
 impl Project {
+    fn default_fixture_profile_paths() -> Vec<PathBuf> {
+        vec![PathBuf::from("fixture_profiles")]
+    }
+    fn default_autosave_path() -> PathBuf {
+        PathBuf::from("autosave.show.ron")
+    }
+    fn default_shows_path() -> PathBuf {
+        PathBuf::from("shows")
+    }
+    fn default_scenes() -> Vec<SceneId> {
+        (1..=4).map(SceneId::new).collect()
+    }
     pub async fn load(
         path: impl AsRef<async_std::path::Path>,
     ) -> Result<Project, async_std::io::Error> {
+        let path = path.as_ref();
         let config_file_contents = async_std::fs::read(path).await?;
 
-        let project: Project = toml::from_slice(&config_file_contents)?;
+        let mut project: Project = toml::from_slice(&config_file_contents)?;
+        project.path = path.to_path_buf().into();
+        project.root = path
+            .parent()
+            .map(|parent| parent.to_path_buf().into())
+            .unwrap_or_default();
+        project.migrate_group_ids();
+        project.migrate_midi_controller();
+        project.validate_clock_priority()?;
+
         Ok(project)
     }
+    fn clock_source_names(&self) -> Vec<&str> {
+        self.midi_clocks
+            .iter()
+            .map(String::as_str)
+            .chain(self.link.as_ref().map(|_| LINK_CLOCK_NAME))
+            .chain(self.audio_clocks.iter().map(|config| config.name.as_str()))
+            .collect()
+    }
+    fn validate_clock_priority(&self) -> Result<(), async_std::io::Error> {
+        let names = self.clock_source_names();
+
+        match self
+            .clock_priority
+            .iter()
+            .find(|name| !names.contains(&name.as_str()))
+        {
+            Some(name) => Err(async_std::io::Error::new(
+                async_std::io::ErrorKind::InvalidData,
+                format!(
+                    "clock_priority lists \"{}\", which is not a clock source",
+                    name
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+    // Projects from before several controllers were supported name a single APC mini
+    fn migrate_midi_controller(&mut self) {
+        if let Some(name) = self.midi_controller.take() {
+            eprintln!(
+                "midi_controller is deprecated, use [[midi_controllers]] with a name and profile"
+            );
+            self.midi_controllers
+                .insert(0, MidiControllerConfig::new(name));
+        }
+    }
+    // Projects from before named groups put each fixture in one numbered group with
+    // `group_id`. Those groups are declared for them, named after their number
+    fn migrate_group_ids(&mut self) {
+        let mut migrated = false;
+
+        for fixture in self
+            .universes
+            .iter_mut()
+            .flat_map(|universe| universe.fixtures.iter_mut())
+        {
+            if let Some(group_id) = fixture.group_id.take() {
+                migrated = true;
+                if !fixture.groups.contains(&group_id) {
+                    fixture.groups.push(group_id);
+                }
+                if !self.groups.iter().any(|group| group.id == group_id) {
+                    self.groups.push(FixtureGroup {
+                        id: group_id,
+                        name: format!("Group {}", usize::from(group_id)),
+                        color: None,
+                        fixtures: vec![],
+                    });
+                }
+            }
+        }
+
+        if migrated {
+            eprintln!(
+                "fixtures' group_id is deprecated, use groups = [..] and declare each group in \
+                 [[groups]] with a name"
+            );
+        }
+    }
+    /// Files and directories that affect the project's fixtures when changed
+    pub fn fixture_source_paths(&self) -> Vec<PathBuf> {
+        std::iter::once(self.path.clone())
+            .chain(
+                self.fixture_profile_directories()
+                    .into_iter()
+                    .filter_map(|directory| match directory {
+                        FixtureProfileDirectory::Project(path)
+                        | FixtureProfileDirectory::User(path) => Some(path),
+                        FixtureProfileDirectory::BuiltIn => None,
+                    }),
+            )
+            .collect()
+    }
+    pub fn fixture_profile_directories(&self) -> Vec<FixtureProfileDirectory> {
+        self.fixture_profile_paths
+            .iter()
+            .map(|path| FixtureProfileDirectory::Project(self.root.join(path)))
+            .chain(FixtureProfileDirectory::user())
+            .chain(std::iter::once(FixtureProfileDirectory::BuiltIn))
+            .collect()
+    }
     pub async fn fixtures(&self) -> Result<Vec<Fixture>, async_std::io::Error> {
-        let fixture_profiles = crate::fixture::load_fixture_profiles().await?;
+        self.fixtures_from(&self.fixture_profile_directories())
+            .await
+    }
+    // Profiles are looked up in `directories` rather than the project's, so tests can leave out
+    // the user profile directory of whichever machine they run on
+    async fn fixtures_from(
+        &self,
+        directories: &[FixtureProfileDirectory],
+    ) -> Result<Vec<Fixture>, async_std::io::Error> {
+        let fixture_profiles = load_fixture_profiles(directories).await?;
+        let invalid = |message: String| {
+            async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, message)
+        };
 
-        let fixtures = self
+        let project_fixtures: Vec<(usize, String, ProjectFixture)> = self
             .universes
             .iter()
-            .cloned()
             .flat_map(|universe| {
                 let universe_id = universe.universe_id;
 
-                universe
-                    .fixtures
-                    .into_iter()
-                    .map(|project_fixture| {
-                        let profile = fixture_profiles
-                            .get(&project_fixture.fixture_profile_slug)
-                            .unwrap()
-                            .clone();
-
-                        Fixture::new(
-                            profile,
-                            universe_id,
-                            project_fixture.start_channel,
-                            project_fixture.group_id,
-                            project_fixture.location,
-                            project_fixture.enabled_effects,
-                        )
-                    })
-                    .collect::<Vec<_>>()
+                universe.fixtures.iter().map(move |project_fixture| {
+                    let key = match project_fixture.id.as_ref() {
+                        Some(id) => id.clone(),
+                        None => format!("{}/{}", universe_id, project_fixture.start_channel),
+                    };
+                    (universe_id, key, project_fixture.clone())
+                })
             })
             .collect();
 
+        for (idx, (universe_id, key, project_fixture)) in project_fixtures.iter().enumerate() {
+            for (other_universe_id, other_key, other_fixture) in project_fixtures[..idx].iter() {
+                if key == other_key {
+                    return Err(invalid(format!(
+                        "more than one fixture has the id \"{}\"",
+                        key
+                    )));
+                }
+                if (universe_id, project_fixture.start_channel)
+                    == (other_universe_id, other_fixture.start_channel)
+                {
+                    return Err(invalid(format!(
+                        "fixtures \"{}\" and \"{}\" both start at channel {} of universe {}",
+                        other_key, key, project_fixture.start_channel, universe_id
+                    )));
+                }
+            }
+        }
+
+        for group in self.groups.iter() {
+            for key in group.fixtures.iter() {
+                if !project_fixtures.iter().any(|(_, id, _)| id == key) {
+                    return Err(invalid(format!(
+                        "fixture group \"{}\" lists fixture \"{}\", which is not in the project",
+                        group.name, key
+                    )));
+                }
+            }
+        }
+
+        let fixtures = project_fixtures
+            .into_iter()
+            .map(|(universe_id, key, mut project_fixture)| {
+                for group_id in project_fixture.groups.iter() {
+                    if !self.groups.iter().any(|group| &group.id == group_id) {
+                        return Err(invalid(format!(
+                            "fixture group {:?} is not defined in the project",
+                            group_id
+                        )));
+                    }
+                }
+                project_fixture.groups.extend(
+                    self.groups
+                        .iter()
+                        .filter(|group| group.fixtures.contains(&key))
+                        .map(|group| group.id),
+                );
+                project_fixture.groups.sort_by_key(|group_id| {
+                    self.groups.iter().position(|group| &group.id == group_id)
+                });
+                project_fixture.groups.dedup();
+
+                let group_orders = self
+                    .groups
+                    .iter()
+                    .filter_map(|group| {
+                        let position = group.fixtures.iter().position(|id| id == &key)?;
+                        Some((group.id, position))
+                    })
+                    .collect();
+
+                if let Some(color_trim) = project_fixture.overrides.color_trim {
+                    if !color_trim.is_valid() {
+                        return Err(invalid(format!(
+                            "fixture \"{}\" has a color_trim outside 0.0 - 1.0",
+                            key
+                        )));
+                    }
+                }
+
+                // Parked fixtures hold their color whichever palette is active
+                if let Some(Color::Palette(name)) =
+                    project_fixture.overrides.park.as_ref().map(|park| &park.color)
+                {
+                    if self.palettes.is_empty() {
+                        return Err(invalid(format!(
+                            "fixture \"{}\" is parked on palette color \"{}\", but the project has no palettes",
+                            key, name
+                        )));
+                    }
+                    if let Some(palette) = self
+                        .palettes
+                        .iter()
+                        .find(|palette| palette.entry(name).is_none())
+                    {
+                        return Err(invalid(format!(
+                            "fixture \"{}\" is parked on palette color \"{}\", which is not defined in palette \"{}\"",
+                            key, name, palette.name
+                        )));
+                    }
+                }
+
+                let (profile, _) = fixture_profiles
+                    .get(&project_fixture.fixture_profile_slug)
+                    .ok_or_else(|| {
+                        async_std::io::Error::new(
+                            async_std::io::ErrorKind::NotFound,
+                            format!(
+                                "fixture profile \"{}\" not found",
+                                project_fixture.fixture_profile_slug
+                            ),
+                        )
+                    })?;
+                let location = project_fixture
+                    .location
+                    .as_ref()
+                    .map(ProjectFixtureLocation::location)
+                    .transpose()
+                    .map_err(invalid)?;
+
+                Ok(Fixture::new(
+                    FixtureId::from_key(&key),
+                    profile.clone(),
+                    universe_id,
+                    project_fixture.start_channel,
+                    project_fixture.groups,
+                    location,
+                    project_fixture.enabled_effects,
+                    project_fixture.overrides,
+                )
+                .with_group_orders(group_orders))
+            })
+            .collect::<Result<Vec<_>, async_std::io::Error>>()?;
+
         // TODO validate fixture addresses don't overlap
 
         Ok(fixtures)
     }
-    /// Uses first active MIDI clock
-    pub fn midi_clock_events(&self) -> Option<impl Stream<Item = ClockEvent>> {
-        self.midi_clocks
+    pub fn autosave_path(&self) -> PathBuf {
+        self.root.join(&self.autosave_path)
+    }
+    pub fn shows_path(&self) -> PathBuf {
+        self.root.join(&self.shows_path)
+    }
+    /// The file a named show is stored in, names may not contain dots or path separators
+    pub fn show_path(&self, name: &str) -> Result<PathBuf, async_std::io::Error> {
+        if name.is_empty() || name.contains(|c| std::path::is_separator(c) || c == '.') {
+            return Err(async_std::io::Error::new(
+                async_std::io::ErrorKind::InvalidInput,
+                format!("\"{}\" is not a valid show name", name),
+            ));
+        }
+
+        Ok(self.shows_path().join(format!("{}.ron", name)))
+    }
+    /// The project's web server settings, on top of the defaults
+    pub fn web_server_config(&self) -> WebServerConfig {
+        let mut config = WebServerConfig::default();
+
+        if let Some(address) = self.web.address {
+            config.address.set_ip(address);
+        }
+        if let Some(port) = self.web.port {
+            config.address.set_port(port);
+        }
+        if self.web.embedded_assets {
+            config.assets = WebAssets::Embedded;
+        } else if let Some(path) = self.web.assets.as_ref() {
+            config.assets = WebAssets::Directory(self.root.join(path));
+        }
+
+        config
+    }
+    pub fn palettes(&self) -> &[ColorPalette] {
+        &self.palettes
+    }
+    pub fn fixture_groups(&self) -> &[FixtureGroup] {
+        &self.groups
+    }
+    fn fixture_group_ids(&self) -> Vec<FixtureGroupId> {
+        self.groups.iter().map(|group| group.id).collect()
+    }
+    pub fn control_mapping_path(&self) -> Option<PathBuf> {
+        self.control_mapping
+            .as_ref()
+            .map(|path| self.root.join(path))
+    }
+    pub fn effect_library_path(&self) -> Option<PathBuf> {
+        self.effect_library
+            .as_ref()
+            .map(|path| self.root.join(path))
+    }
+    /// The project's effect library, empty if it doesn't have one
+    pub async fn effect_library(&self) -> Result<EffectLibrary, async_std::io::Error> {
+        match self.effect_library_path() {
+            Some(path) => load_effect_library(path).await,
+            None => Ok(EffectLibrary::default()),
+        }
+    }
+    pub async fn control_mapping(
+        &self,
+        effect_library: &EffectLibrary,
+    ) -> Result<ControlMapping, async_std::io::Error> {
+        let mut control_mapping = match self.control_mapping_path() {
+            Some(path) => {
+                let control_mapping = load_control_mapping(path).await?;
+                control_mapping
+                    .validate(
+                        &self.fixture_group_ids(),
+                        &self.scenes,
+                        &self.palettes,
+                        effect_library,
+                    )
+                    .map_err(|err| {
+                        async_std::io::Error::new(
+                            async_std::io::ErrorKind::InvalidData,
+                            err.to_string(),
+                        )
+                    })?;
+                control_mapping
+            }
+            // The built-in mapping isn't written for any one project, so it isn't checked
+            // against this one. Its controls for groups the project doesn't have reach no fixtures
+            None => default_control_mapping(),
+        };
+
+        // Buttons that select a fixture group or palette are labelled with its name by default
+        for meta_button in control_mapping.meta_buttons.values_mut() {
+            if meta_button.label.is_some() {
+                continue;
+            }
+
+            meta_button.label = match &meta_button.on_action {
+                MetaButtonAction::SelectFixtureGroupControl(group_id) => self
+                    .groups
+                    .iter()
+                    .find(|group| &group.id == group_id)
+                    .map(|group| group.name.clone()),
+                MetaButtonAction::SelectPalette(name) => Some(name.clone()),
+                _ => None,
+            };
+        }
+
+        Ok(control_mapping)
+    }
+    pub fn timecode(&self) -> Option<TimecodeConfig> {
+        self.timecode
+            .clone()
+            .map(|config| config.with_root(&self.root))
+    }
+    /// The timecode cues, checked against the control mapping and scenes
+    pub fn timeline(
+        &self,
+        control_mapping: &ControlMapping,
+    ) -> Result<Option<Timeline>, async_std::io::Error> {
+        self.timecode
+            .as_ref()
+            .map(|config| config.timeline(control_mapping, &self.scenes))
+            .transpose()
+    }
+    /// Clock sources in priority order
+    pub fn clock_sources(&self) -> Vec<ClockSource> {
+        let mut sources = self.default_clock_sources();
+        // A stable sort, so unlisted sources keep their default order
+        sources.sort_by_key(|source| {
+            self.clock_priority
+                .iter()
+                .position(|name| name == source.name())
+                .unwrap_or(self.clock_priority.len())
+        });
+
+        sources
+    }
+    fn default_clock_sources(&self) -> Vec<ClockSource> {
+        let midi_clocks = self
+            .midi_clocks
             .iter()
-            .flat_map(|clock_name| midi_clock_events(&clock_name).ok())
-            .nth(0)
+            .map(|clock_name| ClockSource::midi_clock(clock_name));
+
+        let link = self
+            .link
+            .as_ref()
+            .and_then(|config| match link_clock_events(config) {
+                Ok(events) => Some(ClockSource::new(LINK_CLOCK_NAME, events)),
+                Err(err) => {
+                    eprintln!("unable to join Link session: {}", err);
+                    None
+                }
+            });
+
+        let audio_clocks = self.audio_clocks.iter().map(|config| {
+            let config = config.clone().with_root(&self.root);
+            ClockSource::new(config.name.clone(), audio_clock_events(&config))
+        });
+
+        midi_clocks.chain(link).chain(audio_clocks).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::midi::MidiControllerProfile;
+
+    fn built_in_fixtures(project: &Project) -> Result<Vec<Fixture>, async_std::io::Error> {
+        async_std::task::block_on(project.fixtures_from(&[FixtureProfileDirectory::BuiltIn]))
+    }
+
+    fn project(contents: &str) -> Project {
+        let mut project: Project = toml::from_str(contents).unwrap();
+        project.migrate_group_ids();
+        project.migrate_midi_controller();
+        project
+    }
+
+    #[test]
+    fn clock_priority_reorders_clock_sources() {
+        let project = project(
+            r#"
+            label = "clocks"
+            midi_clocks = ["Deck", "Drum machine"]
+            clock_priority = ["Drum machine"]
+            universes = []
+            "#,
+        );
+
+        assert!(project.validate_clock_priority().is_ok());
+        assert_eq!(
+            project
+                .clock_sources()
+                .iter()
+                .map(ClockSource::name)
+                .collect::<Vec<_>>(),
+            vec!["Drum machine", "Deck"]
+        );
+    }
+
+    #[test]
+    fn clock_priority_may_only_list_clock_sources() {
+        let project = project(
+            r#"
+            label = "clocks"
+            midi_clocks = ["Deck"]
+            clock_priority = ["Ableton Link", "Deck"]
+            universes = []
+            "#,
+        );
+
+        assert!(project.validate_clock_priority().is_err());
+    }
+
+    #[test]
+    fn a_single_midi_controller_is_migrated() {
+        let project = project(
+            r#"
+            label = "old"
+            midi_controller = "APC MINI"
+            universes = []
+            "#,
+        );
+
+        assert_eq!(project.midi_controllers.len(), 1);
+        assert_eq!(project.midi_controllers[0].name, "APC MINI");
+        assert_eq!(
+            project.midi_controllers[0].profile,
+            MidiControllerProfile::AkaiApcMini
+        );
+    }
+
+    #[test]
+    fn group_ids_are_migrated_to_groups() {
+        let project = project(
+            r#"
+            label = "old"
+
+            [[universes]]
+            universe_id = 1
+
+                [[universes.fixtures]]
+                start_channel = 1
+                fixture_profile = "ave_quad_pro_flat_4ch"
+                group_id = 2
+            "#,
+        );
+
+        assert_eq!(project.fixture_group_ids(), vec![FixtureGroupId::new(2)]);
+        assert_eq!(project.fixture_groups()[0].name, "Group 2");
+        assert_eq!(
+            project.universes[0].fixtures[0].groups,
+            vec![FixtureGroupId::new(2)]
+        );
+    }
+
+    #[test]
+    fn groups_order_the_fixtures_they_list() {
+        let project = project(
+            r#"
+            label = "ordered"
+
+            [[groups]]
+            id = 1
+            name = "Pars"
+
+            [[groups]]
+            id = 2
+            name = "Pars backwards"
+            fixtures = ["right", "left"]
+
+            [[universes]]
+            universe_id = 1
+
+                [[universes.fixtures]]
+                id = "left"
+                start_channel = 1
+                fixture_profile = "ave_quad_pro_flat_4ch"
+                groups = [1]
+
+                [[universes.fixtures]]
+                id = "right"
+                start_channel = 5
+                fixture_profile = "ave_quad_pro_flat_4ch"
+                groups = [1]
+            "#,
+        );
+
+        let fixtures = built_in_fixtures(&project).unwrap();
+        let group_ids = vec![FixtureGroupId::new(1), FixtureGroupId::new(2)];
+
+        assert_eq!(fixtures[0].params.group_ids, group_ids);
+        assert_eq!(
+            fixtures[0].params.group_orders.get(&FixtureGroupId::new(2)),
+            Some(&1)
+        );
+        assert_eq!(
+            fixtures[1].params.group_orders.get(&FixtureGroupId::new(2)),
+            Some(&0)
+        );
+        assert_eq!(
+            fixtures[0].params.group_orders.get(&FixtureGroupId::new(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn groups_may_only_list_fixtures_in_the_project() {
+        let project = project(
+            r#"
+            label = "missing"
+
+            [[groups]]
+            id = 1
+            name = "Pars"
+            fixtures = ["nowhere"]
+
+            [[universes]]
+            universe_id = 1
+            fixtures = []
+            "#,
+        );
+
+        assert!(built_in_fixtures(&project).is_err());
+    }
+
+    #[test]
+    fn fixture_locations_need_a_z() {
+        let fixtures = |location: &str| {
+            let project = project(&format!(
+                r#"
+                label = "located"
+
+                [[universes]]
+                universe_id = 1
+
+                    [[universes.fixtures]]
+                    start_channel = 1
+                    fixture_profile = "ave_quad_pro_flat_4ch"
+                    location = {}
+                "#,
+                location
+            ));
+            built_in_fixtures(&project)
+        };
+
+        assert!(fixtures("{ x = 1.0, y = 3.0 }").is_err());
+        assert_eq!(
+            fixtures("{ x = 1.0, y = 2.5, z = 3.0 }").unwrap()[0]
+                .params
+                .location,
+            Some(FixtureLocation::new(1.0, 2.5, 3.0))
+        );
+    }
+
+    #[test]
+    fn fixture_ids_and_addresses_must_be_unique() {
+        let fixtures = |second_fixture: &str| {
+            let project = project(&format!(
+                r#"
+                label = "clashing"
+
+                [[universes]]
+                universe_id = 1
+
+                    [[universes.fixtures]]
+                    start_channel = 1
+                    fixture_profile = "ave_quad_pro_flat_4ch"
+
+                    [[universes.fixtures]]
+                    {}
+                    fixture_profile = "ave_quad_pro_flat_4ch"
+                "#,
+                second_fixture
+            ));
+            built_in_fixtures(&project)
+        };
+
+        assert!(fixtures("start_channel = 5").is_ok());
+        assert!(fixtures("id = \"1/1\"\nstart_channel = 5").is_err());
+        assert!(fixtures("id = \"other\"\nstart_channel = 1").is_err());
+    }
+
+    #[test]
+    fn color_trims_must_be_between_zero_and_one() {
+        let fixtures = |color_trim: &str| {
+            let project = project(&format!(
+                r#"
+                label = "trimmed"
+
+                [[universes]]
+                universe_id = 1
+
+                    [[universes.fixtures]]
+                    start_channel = 1
+                    fixture_profile = "ave_quad_pro_flat_4ch"
+                    color_trim = {}
+                "#,
+                color_trim
+            ));
+            built_in_fixtures(&project)
+        };
+
+        assert!(fixtures("{ red = 0.8, blue = 1.0 }").is_ok());
+        assert!(fixtures("{ red = 1.2 }").is_err());
+        assert!(fixtures("{ green = -0.1 }").is_err());
+    }
+
+    #[test]
+    fn park_colors_must_be_in_every_palette() {
+        let fixtures = |park_color: &str| {
+            let project = project(&format!(
+                r#"
+                label = "parked"
+
+                [[palettes]]
+                name = "Warm"
+                colors = [
+                    {{ name = "Accent", color = {{ rgb = {{ red = 255, green = 128, blue = 0 }} }} }},
+                    {{ name = "Wash", color = {{ rgb = {{ red = 255, green = 200, blue = 150 }} }} }},
+                ]
+
+                [[palettes]]
+                name = "Cold"
+                colors = [
+                    {{ name = "Accent", color = {{ rgb = {{ red = 0, green = 128, blue = 255 }} }} }},
+                ]
+
+                [[universes]]
+                universe_id = 1
+
+                    [[universes.fixtures]]
+                    start_channel = 1
+                    fixture_profile = "ave_quad_pro_flat_4ch"
+                    park = {{ color = {} }}
+                "#,
+                park_color
+            ));
+            built_in_fixtures(&project)
+        };
+
+        assert!(fixtures("\"Red\"").is_ok());
+        assert!(fixtures("{ Palette = \"Accent\" }").is_ok());
+        assert!(fixtures("{ Palette = \"Wash\" }").is_err());
+        assert!(fixtures("{ Palette = \"Missing\" }").is_err());
     }
 }
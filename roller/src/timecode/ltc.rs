@@ -0,0 +1,194 @@
+//! Decoding of SMPTE linear timecode from audio. Each 80 bit frame is biphase mark coded: the
+//! level changes at the start of every bit, and again half way through ones
+use midi::{FrameRate, Timecode};
+
+const BITS_PER_FRAME: u32 = 80;
+// Bits 64 to 79 of every frame, in the order they're sent from bit 64
+const SYNC_WORD: u128 = 0xBFFC;
+// Samples within this of silence don't change the level, so noise isn't read as transitions
+const THRESHOLD: f32 = 0.02;
+
+#[derive(Debug)]
+pub struct LtcDecoder {
+    rate: FrameRate,
+    // Estimated samples per bit, followed as the source speeds up or slows down
+    bit_period: f64,
+    level: bool,
+    samples_since_transition: usize,
+    half_bit_pending: bool,
+    // The last 80 bits, the oldest in bit 0
+    bits: u128,
+    bits_received: u32,
+}
+impl LtcDecoder {
+    /// LTC frames don't say whether they're 24, 25 or 30 frames per second, so the source's
+    /// rate is given. Frames flagged as drop frame are read as 29.97 drop frame regardless
+    pub fn new(sample_rate: u32, rate: FrameRate) -> LtcDecoder {
+        LtcDecoder {
+            rate,
+            bit_period: sample_rate as f64 / (rate.fps() * BITS_PER_FRAME as f64),
+            level: false,
+            samples_since_transition: 0,
+            half_bit_pending: false,
+            bits: 0,
+            bits_received: 0,
+        }
+    }
+    /// Timecodes of the frames completed in `samples`. Each is the time at the end of its frame,
+    /// as that's when it's read
+    pub fn process(&mut self, samples: &[f32]) -> Vec<Timecode> {
+        let mut timecodes = vec![];
+
+        for sample in samples.iter() {
+            self.samples_since_transition += 1;
+
+            let level = match *sample {
+                sample if sample > THRESHOLD => true,
+                sample if sample < -THRESHOLD => false,
+                _ => self.level,
+            };
+            if level == self.level {
+                continue;
+            }
+            self.level = level;
+
+            let interval = self.samples_since_transition as f64;
+            self.samples_since_transition = 0;
+            if let Some(timecode) = self.transition(interval) {
+                timecodes.push(timecode);
+            }
+        }
+
+        timecodes
+    }
+    fn transition(&mut self, interval: f64) -> Option<Timecode> {
+        if interval < self.bit_period * 0.75 {
+            // Half of a one, the bit is complete on its second half
+            self.bit_period = self.bit_period * 0.9 + interval * 2.0 * 0.1;
+            self.half_bit_pending = !self.half_bit_pending;
+            if self.half_bit_pending {
+                return None;
+            }
+            self.push_bit(true)
+        } else if interval < self.bit_period * 1.5 && !self.half_bit_pending {
+            self.bit_period = self.bit_period * 0.9 + interval * 0.1;
+            self.push_bit(false)
+        } else {
+            // A gap in the signal or a lone half bit, start over from the next sync word
+            self.half_bit_pending = false;
+            self.bits_received = 0;
+            None
+        }
+    }
+    fn push_bit(&mut self, bit: bool) -> Option<Timecode> {
+        self.bits = (self.bits >> 1) | (u128::from(bit) << (BITS_PER_FRAME - 1));
+        self.bits_received += 1;
+
+        // Frames played backwards have their sync word reversed, they're not followed
+        if self.bits_received < BITS_PER_FRAME || (self.bits >> 64) & 0xFFFF != SYNC_WORD {
+            return None;
+        }
+        self.bits_received = 0;
+
+        let field = |start: u32, len: u32| ((self.bits >> start) & ((1 << len) - 1)) as u8;
+        let rate = if field(10, 1) == 1 {
+            FrameRate::Fps2997Drop
+        } else {
+            self.rate
+        };
+        let timecode = Timecode::new(
+            field(56, 2) * 10 + field(48, 4),
+            field(40, 3) * 10 + field(32, 4),
+            field(24, 3) * 10 + field(16, 4),
+            field(8, 2) * 10 + field(0, 4),
+            rate,
+        );
+
+        Some(timecode.add_frames(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_bits(timecode: &Timecode) -> u128 {
+        let bcd = |value: u8, units_at: u32, tens_at: u32| {
+            (u128::from(value % 10) << units_at) | (u128::from(value / 10) << tens_at)
+        };
+        let drop_flag = u128::from(timecode.rate == FrameRate::Fps2997Drop) << 10;
+
+        bcd(timecode.frames, 0, 8)
+            | drop_flag
+            | bcd(timecode.seconds, 16, 24)
+            | bcd(timecode.minutes, 32, 40)
+            | bcd(timecode.hours, 48, 56)
+            | (SYNC_WORD << 64)
+    }
+
+    // Biphase mark coded audio of consecutive frames from `start`, at the given speed
+    fn ltc_samples(start: Timecode, frames: u64, sample_rate: u32, speed: f64) -> Vec<f32> {
+        let samples_per_bit = sample_rate as f64 / (start.rate.fps() * speed * 80.0);
+        let mut half_bit_levels = vec![];
+        let mut level = false;
+
+        for frame in 0..frames {
+            let bits = frame_bits(&start.add_frames(frame));
+            for bit in 0..80 {
+                level = !level;
+                half_bit_levels.push(level);
+                if (bits >> bit) & 1 == 1 {
+                    level = !level;
+                }
+                half_bit_levels.push(level);
+            }
+        }
+
+        // The last bit is read at the transition that starts the next
+        half_bit_levels.push(!level);
+
+        let len = (half_bit_levels.len() as f64 * samples_per_bit / 2.0) as usize;
+        let silence = std::iter::repeat(0.0).take(100);
+        let signal = (0..len).map(|idx| {
+            let half_bit = (idx as f64 * 2.0 / samples_per_bit) as usize;
+            if half_bit_levels[half_bit] {
+                0.5
+            } else {
+                -0.5
+            }
+        });
+        silence.clone().chain(signal).chain(silence).collect()
+    }
+
+    #[test]
+    fn frames_are_decoded_as_they_end() {
+        let start = Timecode::new(1, 59, 59, 20, FrameRate::Fps25);
+        let samples = ltc_samples(start, 10, 48_000, 1.0);
+        let mut decoder = LtcDecoder::new(48_000, FrameRate::Fps25);
+
+        let decoded = decoder.process(&samples);
+
+        // The first sync word ends the first frame, nothing before it can be read
+        let expected: Vec<_> = (1..=10).map(|frame| start.add_frames(frame)).collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn drop_frame_and_varying_speed_are_followed() {
+        let start = Timecode::new(0, 9, 59, 28, FrameRate::Fps2997Drop);
+        let samples = ltc_samples(start, 30, 44_100, 1.05);
+        let mut decoder = LtcDecoder::new(44_100, FrameRate::Fps30);
+
+        let decoded: Vec<_> = samples
+            .chunks(441)
+            .flat_map(|chunk| decoder.process(chunk))
+            .collect();
+
+        assert_eq!(decoded.len(), 30);
+        assert_eq!(decoded[0], start.add_frames(1));
+        assert_eq!(
+            decoded[29],
+            Timecode::new(0, 10, 0, 28, FrameRate::Fps2997Drop)
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use futures::stream::{Stream, StreamExt};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+use midi::{FrameRate, MidiEvent, MtcDecoder, Timecode};
+
+use crate::{
+    clock::{spawn_audio_reader, AudioInput},
+    control::control_mapping::ControlMapping,
+    lighting_engine::SceneId,
+    utils::reconnecting_midi_stream,
+};
+
+mod ltc;
+mod timeline;
+
+pub use ltc::LtcDecoder;
+pub use timeline::Timeline;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimecodeSource {
+    // MIDI timecode from the named MIDI input
+    Mtc(String),
+    // Linear timecode on an audio input
+    Ltc(AudioInput),
+}
+
+/// Something to do when timecode reaches a point in the show
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Cue {
+    // Printed as the cue fires
+    pub label: Option<String>,
+    // A named show to load, before the cue's scene and buttons apply
+    pub show: Option<String>,
+    pub scene: Option<SceneId>,
+    // Labels of buttons to press, in the active scene
+    #[serde(default)]
+    pub buttons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CueConfig {
+    // `hh:mm:ss:ff`, at the timecode's frame rate
+    at: String,
+    #[serde(flatten)]
+    cue: Cue,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimecodeConfig {
+    pub source: TimecodeSource,
+    // Cue times are read at this rate, as is LTC, which doesn't carry it
+    #[serde(default = "TimecodeConfig::default_frame_rate")]
+    pub frame_rate: FrameRate,
+    // How long cues carry on firing after timecode drops out
+    #[serde(default = "TimecodeConfig::default_freewheel_secs")]
+    pub freewheel_secs: f64,
+    #[serde(default)]
+    cues: Vec<CueConfig>,
+}
+impl TimecodeConfig {
+    fn default_frame_rate() -> FrameRate {
+        FrameRate::Fps25
+    }
+    fn default_freewheel_secs() -> f64 {
+        2.0
+    }
+    /// Resolves an LTC file relative to `root`
+    pub fn with_root(mut self, root: &Path) -> TimecodeConfig {
+        if let TimecodeSource::Ltc(input) = self.source {
+            self.source = TimecodeSource::Ltc(input.with_root(root));
+        }
+        self
+    }
+    /// Checks the cues' times, and that the scenes and buttons they refer to exist
+    pub fn timeline(
+        &self,
+        control_mapping: &ControlMapping,
+        scenes: &[SceneId],
+    ) -> Result<Timeline, async_std::io::Error> {
+        let invalid = |message: String| {
+            async_std::io::Error::new(async_std::io::ErrorKind::InvalidData, message)
+        };
+
+        let cues = self
+            .cues
+            .iter()
+            .map(|config| {
+                let at = Timecode::parse(&config.at, self.frame_rate).ok_or_else(|| {
+                    invalid(format!("cue at \"{}\" is not a timecode", config.at))
+                })?;
+
+                if let Some(scene_id) = config.cue.scene {
+                    if !scenes.contains(&scene_id) {
+                        return Err(invalid(format!(
+                            "cue at {} selects scene {:?}, which is not defined in the project",
+                            at, scene_id
+                        )));
+                    }
+                }
+                for label in config.cue.buttons.iter() {
+                    if control_mapping.find_button_by_label(label).is_none() {
+                        return Err(invalid(format!(
+                            "cue at {} presses button \"{}\", which is not in the control mapping",
+                            at, label
+                        )));
+                    }
+                }
+
+                Ok((at, config.cue.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Timeline::new(
+            cues,
+            Duration::from_secs_f64(self.freewheel_secs.max(0.0)),
+        ))
+    }
+}
+
+fn mtc_events(name: &str) -> Result<impl Stream<Item = Timecode>, midi::MidiIoError> {
+    let input = midi::MidiInput::new(name)?;
+    let mut decoder = MtcDecoder::new();
+
+    Ok(input.filter_map(move |midi_event| {
+        let timecode = match midi_event {
+            MidiEvent::MtcQuarterFrame { piece, value } => decoder.push_quarter_frame(piece, value),
+            MidiEvent::MtcFullFrame(timecode) => Some(decoder.push_full_frame(timecode)),
+            _ => None,
+        };
+        async move { timecode }
+    }))
+}
+
+/// Timecode as it's received from the configured source. MIDI inputs are connected to whenever
+/// they're available, like MIDI clocks
+pub fn timecode_events(config: &TimecodeConfig) -> impl Stream<Item = Timecode> {
+    match config.source.clone() {
+        TimecodeSource::Mtc(device_name) => {
+            reconnecting_midi_stream(&device_name, "MIDI timecode", mtc_events)
+        }
+        TimecodeSource::Ltc(input) => {
+            let frame_rate = config.frame_rate;
+            let (timecode_send, timecode_recv) = async_std::sync::channel(16);
+
+            spawn_audio_reader("LTC input".to_owned(), input, move |sample_rate| {
+                let timecode_send = timecode_send.clone();
+                let mut decoder = LtcDecoder::new(sample_rate, frame_rate);
+
+                move |samples: &[f32]| {
+                    for timecode in decoder.process(samples) {
+                        if let Err(async_std::sync::TrySendError::Disconnected(_)) =
+                            timecode_send.try_send(timecode)
+                        {
+                            return false;
+                        }
+                    }
+                    true
+                }
+            });
+
+            timecode_recv.boxed()
+        }
+    }
+}
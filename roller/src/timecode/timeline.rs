@@ -0,0 +1,247 @@
+use std::time::{Duration, Instant};
+
+use midi::Timecode;
+
+use crate::timecode::Cue;
+
+// Timecode arrives every frame or two, longer than this without any and it has dropped out
+const DROPOUT: Duration = Duration::from_millis(250);
+// Timecode this far behind where we've got to is jitter, rather than the source going back
+const JITTER_SECS: f64 = 0.1;
+// Timecode this far ahead of where we expected is the source jumping forward
+const JUMP_SECS: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimelineState {
+    Stopped,
+    // Following timecode, last received at `received_at`
+    Locked { position: f64, received_at: Instant },
+    // Timecode has dropped out, so we're running on from where it was
+    Freewheeling { position: f64, received_at: Instant },
+}
+
+/// Fires cues as timecode passes them. Cues are fired in order while timecode runs through
+/// them, when it jumps, or we first lock to it, only the last cue before its position fires, so
+/// the show chases to where it would be. Cues carry on firing through short dropouts
+#[derive(Debug)]
+pub struct Timeline {
+    // Ordered by their time in seconds
+    cues: Vec<(f64, Cue)>,
+    freewheel: Duration,
+    state: TimelineState,
+    // Cues up to here have fired
+    fired_until: f64,
+}
+impl Timeline {
+    pub fn new(cues: Vec<(Timecode, Cue)>, freewheel: Duration) -> Timeline {
+        let mut cues: Vec<_> = cues
+            .into_iter()
+            .map(|(timecode, cue)| (timecode.as_secs(), cue))
+            .collect();
+        cues.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+
+        Timeline {
+            cues,
+            freewheel,
+            state: TimelineState::Stopped,
+            fired_until: 0.0,
+        }
+    }
+    fn position_at(&self, now: Instant) -> Option<f64> {
+        match self.state {
+            TimelineState::Stopped => None,
+            TimelineState::Locked {
+                position,
+                received_at,
+            }
+            | TimelineState::Freewheeling {
+                position,
+                received_at,
+            } => Some(position + now.saturating_duration_since(received_at).as_secs_f64()),
+        }
+    }
+    pub fn receive(&mut self, timecode: Timecode, now: Instant) -> Vec<&Cue> {
+        let position = timecode.as_secs();
+        let chase = match self.position_at(now) {
+            Some(expected) => {
+                position < self.fired_until - JITTER_SECS || position > expected + JUMP_SECS
+            }
+            None => true,
+        };
+
+        if self.state == TimelineState::Stopped {
+            println!("timecode locked at {}", timecode);
+        } else if chase {
+            println!("timecode jumped to {}", timecode);
+        }
+        self.state = TimelineState::Locked {
+            position,
+            received_at: now,
+        };
+
+        if chase {
+            self.fired_until = position;
+            self.cues
+                .iter()
+                .rev()
+                .find(|(at, _)| *at <= position)
+                .map(|(_, cue)| cue)
+                .into_iter()
+                .collect()
+        } else {
+            self.run_until(position)
+        }
+    }
+    /// Moves on to freewheeling, then stops, as timecode goes missing
+    pub fn tick(&mut self, now: Instant) -> Vec<&Cue> {
+        match self.state {
+            TimelineState::Locked {
+                position,
+                received_at,
+            } if now.saturating_duration_since(received_at) > DROPOUT => {
+                println!("timecode dropped out, freewheeling");
+                self.state = TimelineState::Freewheeling {
+                    position,
+                    received_at,
+                };
+            }
+            TimelineState::Freewheeling { received_at, .. }
+                if now.saturating_duration_since(received_at) > DROPOUT + self.freewheel =>
+            {
+                println!("timecode stopped");
+                self.state = TimelineState::Stopped;
+            }
+            _ => {}
+        }
+
+        match (self.state, self.position_at(now)) {
+            (TimelineState::Freewheeling { .. }, Some(position)) => self.run_until(position),
+            _ => vec![],
+        }
+    }
+    // Cues passed since those last fired
+    fn run_until(&mut self, position: f64) -> Vec<&Cue> {
+        let fired_until = self.fired_until;
+        self.fired_until = fired_until.max(position);
+
+        self.cues
+            .iter()
+            .filter(|(at, _)| *at > fired_until && *at <= position)
+            .map(|(_, cue)| cue)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midi::FrameRate;
+
+    fn timecode(seconds: u8, frames: u8) -> Timecode {
+        Timecode::new(0, 0, seconds, frames, FrameRate::Fps25)
+    }
+
+    fn timeline() -> Timeline {
+        let cue = |label: &str| Cue {
+            label: Some(label.to_owned()),
+            ..Cue::default()
+        };
+        Timeline::new(
+            vec![
+                (timecode(2, 0), cue("two")),
+                (timecode(1, 0), cue("one")),
+                (timecode(10, 0), cue("ten")),
+            ],
+            Duration::from_secs(2),
+        )
+    }
+
+    fn labels(cues: Vec<&Cue>) -> Vec<&str> {
+        cues.into_iter()
+            .map(|cue| cue.label.as_deref().unwrap())
+            .collect()
+    }
+
+    // Sends timecode every frame from `from` seconds, returning the cues fired
+    fn run(
+        timeline: &mut Timeline,
+        started_at: Instant,
+        from_secs: u8,
+        frames: u64,
+    ) -> Vec<String> {
+        (0..frames)
+            .flat_map(|frame| {
+                let now = started_at + Duration::from_millis(frame * 40);
+                let timecode = timecode(from_secs, 0).add_frames(frame);
+                labels(timeline.receive(timecode, now))
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn running_timecode_fires_cues_in_order() {
+        let mut timeline = timeline();
+        let now = Instant::now();
+
+        assert_eq!(run(&mut timeline, now, 0, 75), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn locking_part_way_chases_to_the_last_cue() {
+        let mut timeline = timeline();
+        let now = Instant::now();
+
+        assert_eq!(run(&mut timeline, now, 3, 25), vec!["two"]);
+        // Jumping back chases again
+        let later = now + Duration::from_secs(1);
+        assert_eq!(run(&mut timeline, later, 1, 10), vec!["one"]);
+        // As does jumping forward
+        let later = later + Duration::from_secs(1);
+        assert_eq!(run(&mut timeline, later, 12, 10), vec!["ten"]);
+    }
+
+    #[test]
+    fn dropouts_freewheel_then_stop() {
+        let mut timeline = timeline();
+        let now = Instant::now();
+
+        // Locking part way chases to the last cue
+        assert_eq!(run(&mut timeline, now, 8, 25), vec!["two"]);
+        let last_received_at = now + Duration::from_millis(24 * 40);
+
+        let dropped_at = last_received_at + Duration::from_millis(300);
+        assert!(timeline.tick(dropped_at).is_empty());
+        assert!(matches!(timeline.state, TimelineState::Freewheeling { .. }));
+
+        // Cues passed while freewheeling still fire
+        let ten_secs_at = last_received_at + Duration::from_millis(1050);
+        assert_eq!(labels(timeline.tick(ten_secs_at)), vec!["ten"]);
+
+        let stopped_at = last_received_at + Duration::from_secs(3);
+        assert!(timeline.tick(stopped_at).is_empty());
+        assert_eq!(timeline.state, TimelineState::Stopped);
+    }
+
+    #[test]
+    fn timecode_returning_after_freewheel_carries_on() {
+        let mut timeline = timeline();
+        let now = Instant::now();
+
+        run(&mut timeline, now, 0, 10);
+        let resumed_at = now + Duration::from_millis(800);
+        timeline.tick(resumed_at);
+
+        // The first cue passed during the dropout, so isn't fired again
+        assert_eq!(
+            labels(timeline.tick(now + Duration::from_millis(1050))),
+            vec!["one"]
+        );
+        assert_eq!(
+            run(&mut timeline, now + Duration::from_millis(1200), 1, 30),
+            vec!["two"]
+        );
+    }
+}
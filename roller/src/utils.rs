@@ -1,24 +1,144 @@
 use async_std::prelude::*;
-use futures::stream::{self, StreamExt};
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt};
 use indexmap::IndexMap;
 use rustc_hash::FxHasher;
 use std::hash::BuildHasherDefault;
-use std::time::{Duration, Instant};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+use roller_protocol::clock::Time;
 
 pub type FxIndexMap<K, V> = IndexMap<K, V, BuildHasherDefault<FxHasher>>;
 
-pub fn tick_stream(interval: Duration) -> impl Stream<Item = ()> {
-    let mut next_tick_at = Instant::now();
+const MIDI_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn tick_stream(time: Time, interval: Duration) -> impl Stream<Item = ()> {
+    let mut next_tick_at = time.instant();
 
     stream::repeat(()).then(move |()| {
         let until = next_tick_at;
         next_tick_at += interval;
-        let now = Instant::now();
-        async_std::task::sleep(if now < until {
-            until - now
-        } else {
-            Duration::from_secs(0)
-        })
+        let time = time.clone();
+
+        async move {
+            loop {
+                // Time sources other than the system's wake us when they move on, rather than
+                // being slept on
+                let reached = future::poll_fn(|cx| {
+                    if time.instant() >= until {
+                        Poll::Ready(true)
+                    } else if time.wake_when_advanced(cx.waker()) {
+                        Poll::Pending
+                    } else {
+                        Poll::Ready(false)
+                    }
+                })
+                .await;
+                if reached {
+                    break;
+                }
+
+                let now = time.instant();
+                if now < until {
+                    async_std::task::sleep(until - now).await;
+                }
+            }
+        }
+    })
+}
+
+/// Events from the named MIDI input, opened with `open`. The input is connected to whenever it's
+/// available, and reconnected to if it goes away. `label` describes the input in log messages
+pub fn reconnecting_midi_stream<T, S, F>(
+    name: &str,
+    label: &'static str,
+    open: F,
+) -> BoxStream<'static, T>
+where
+    T: Send + 'static,
+    S: Stream<Item = T> + Send + 'static,
+    F: Fn(&str) -> Result<S, midi::MidiIoError> + Send + 'static,
+{
+    let device_name = name.to_owned();
+
+    stream::unfold(
+        (None, open),
+        move |(events, open): (Option<BoxStream<'static, T>>, F)| {
+            let device_name = device_name.clone();
+            async move {
+                let mut events = events;
+                loop {
+                    match events.as_mut() {
+                        Some(connected_events) => match connected_events.next().await {
+                            Some(event) => return Some((event, (events, open))),
+                            None => {
+                                eprintln!("{} \"{}\" disconnected", label, device_name);
+                                events = None;
+                            }
+                        },
+                        None => match open(&device_name) {
+                            Ok(connected_events) => {
+                                println!("{} \"{}\" connected", label, device_name);
+                                events = Some(connected_events.boxed());
+                            }
+                            Err(_) => async_std::task::sleep(MIDI_RECONNECT_INTERVAL).await,
+                        },
+                    }
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+fn modified_times(paths: &[PathBuf]) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let modified_time = |path: &PathBuf| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+
+    let mut times = vec![];
+    for path in paths {
+        times.push((path.clone(), modified_time(path)));
+
+        // Files directly inside a directory are watched too, editing a file doesn't always
+        // update its directory's modified time
+        if let Ok(entries) = std::fs::read_dir(path) {
+            let mut entry_paths: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+            entry_paths.sort();
+
+            for entry_path in entry_paths {
+                let modified = modified_time(&entry_path);
+                times.push((entry_path, modified));
+            }
+        }
+    }
+    times
+}
+
+/// Polls the given files and directories, yielding whenever any of them are created, removed or
+/// modified. Paths are checked on a blocking thread, so a slow filesystem doesn't stall the show
+pub fn watch_paths(paths: Vec<PathBuf>, interval: Duration) -> impl Stream<Item = ()> {
+    let paths = Arc::new(paths);
+    // The first tick is straight away, and only records where we're starting from
+    let mut last_modified_times = None;
+
+    let modified_times = StreamExt::then(tick_stream(Time::system(), interval), move |()| {
+        let paths = paths.clone();
+        async_std::task::spawn_blocking(move || modified_times(&paths))
+    });
+    StreamExt::filter_map(modified_times, move |modified_times| {
+        let changed = match &last_modified_times {
+            Some(last_modified_times) => modified_times != *last_modified_times,
+            None => false,
+        };
+        last_modified_times = Some(modified_times);
+
+        future::ready(if changed { Some(()) } else { None })
     })
 }
 
@@ -49,3 +169,48 @@ where
         min
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use roller_protocol::clock::ManualTime;
+    use std::sync::Arc;
+
+    #[test]
+    fn tick_stream_follows_its_time_source() {
+        let time = Arc::new(ManualTime::new());
+        let ticks = tick_stream(Time::from(time.clone()), Duration::from_secs(1));
+        futures::pin_mut!(ticks);
+
+        // The first tick is straight away, the next waits until the time source reaches it
+        assert_eq!(ticks.next().now_or_never(), Some(Some(())));
+        assert_eq!(ticks.next().now_or_never(), None);
+
+        time.advance_secs(2.5);
+        assert_eq!(ticks.next().now_or_never(), Some(Some(())));
+        assert_eq!(ticks.next().now_or_never(), Some(Some(())));
+        assert_eq!(ticks.next().now_or_never(), None);
+    }
+
+    #[test]
+    fn watch_paths_notices_new_files() {
+        let dir = std::env::temp_dir().join(format!("roller-watch-paths-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        async_std::task::block_on(async {
+            let changes = watch_paths(vec![dir.clone()], Duration::from_millis(10));
+            futures::pin_mut!(changes);
+
+            // Nothing has changed while the first checks record the empty directory
+            let unchanged = async_std::future::timeout(Duration::from_millis(50), changes.next());
+            assert!(unchanged.await.is_err());
+
+            std::fs::write(dir.join("effects.ron"), "[]").unwrap();
+            let changed = async_std::future::timeout(Duration::from_secs(5), changes.next()).await;
+            assert_eq!(changed, Ok(Some(())));
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,12 +1,20 @@
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::utils::clamp;
+
 pub mod offset;
 pub mod snapshot;
+pub mod time;
 pub mod units;
 
 pub use snapshot::ClockSnapshot;
-pub use units::{Beats, Rate};
+pub use time::{ManualTime, SystemTime, Time, TimeSource};
+pub use units::{Bars, Beats, Rate};
+
+// Relative tempo changes stay within this range, so repeated halving can't stop the clock
+const MIN_BPM: f64 = 20.0;
+const MAX_BPM: f64 = 999.0;
 
 fn duration_as_secs(duration: Duration) -> f64 {
     duration.num_microseconds().unwrap() as f64 / 1_000_000.0
@@ -20,6 +28,83 @@ fn duration_from_secs(secs: f64) -> Duration {
 pub enum ClockEvent {
     BpmChanged(f64),
     Tap(DateTime<Utc>),
+    // From sources that know the beat phase as well as the tempo, `downbeat_at` is the start of
+    // any bar
+    Sync {
+        bpm: f64,
+        downbeat_at: DateTime<Utc>,
+        beats_per_bar: f64,
+    },
+    // Transport messages, at the time the source's playback started, resumed or stopped
+    Start(DateTime<Utc>),
+    Continue(DateTime<Utc>),
+    Stop(DateTime<Utc>),
+    // Where playback resumes from on the next Continue
+    SongPosition(Beats),
+    // Restarts effects from their first beat at this time, which also starts a new phrase
+    Resync(DateTime<Utc>),
+    // Makes the beat nearest this time the first of a bar, keeping the beat's phase
+    SetDownbeat(DateTime<Utc>),
+    // Shifts effects forward, or back when negative, by a fraction of a beat
+    NudgePhase(Beats),
+    // Tempo changes relative to the current tempo, carrying on from the current beat
+    AdjustBpm(f64),
+    ScaleBpm(f64),
+}
+
+/// What effects do while a clock source's transport is stopped
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopBehaviour {
+    // Effects keep running at the last tempo
+    #[default]
+    Ignore,
+    // Effects hold where they were when the transport stopped
+    Freeze,
+    // Effects hold at their first beat, where the next Start picks up from
+    Park,
+}
+/// Which boundary of the clock launched actions wait for, like clip launching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantization {
+    #[default]
+    Off,
+    Beat,
+    Bar,
+    FourBars,
+}
+impl Quantization {
+    pub fn meter_length(&self, time_signature: TimeSignature) -> Option<Beats> {
+        match self {
+            Quantization::Off => None,
+            Quantization::Beat => Some(Beats::new(1.0)),
+            Quantization::Bar => Some(Beats::new(time_signature.beats_per_bar)),
+            Quantization::FourBars => Some(Beats::new(time_signature.beats_per_bar * 4.0)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeSignature {
+    pub beats_per_bar: f64,
+    // Phrases are the sections music is built from, usually 16 or 32 bars
+    pub bars_per_phrase: u32,
+}
+impl Default for TimeSignature {
+    fn default() -> TimeSignature {
+        TimeSignature {
+            beats_per_bar: 4.0,
+            bars_per_phrase: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Transport {
+    Running,
+    Stopped { at: DateTime<Utc> },
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -33,15 +118,43 @@ pub struct Clock {
     started_at: DateTime<Utc>,
     bpm: f64,
     state: ClockState,
+    transport: Transport,
+    song_position: Option<Beats>,
+    stop_behaviour: StopBehaviour,
+    time_signature: TimeSignature,
+    // Nudges made while following a source, kept so they survive the source's next sync
+    phase_nudge: Beats,
+    #[serde(skip)]
+    time: Time,
 }
 impl Clock {
     pub fn new(bpm: f64) -> Clock {
+        Clock::with_time(bpm, Time::system())
+    }
+    pub fn with_time(bpm: f64, time: Time) -> Clock {
         Clock {
             bpm,
-            started_at: Utc::now(),
+            started_at: time.now(),
             state: ClockState::Manual { taps: Vec::new() },
+            transport: Transport::Running,
+            song_position: None,
+            stop_behaviour: StopBehaviour::default(),
+            time_signature: TimeSignature::default(),
+            phase_nudge: Beats::new(0.0),
+            time,
         }
     }
+    fn beats_as_duration(&self, beats: Beats) -> Duration {
+        duration_from_secs(f64::from(beats) * 60.0 / self.bpm)
+    }
+    // Carry on from the current beat at the new tempo, rather than jumping to where the new tempo
+    // would have been since `started_at`
+    fn rebase_bpm(&mut self, bpm: f64) {
+        let now = self.time.now();
+        let beats_elapsed = duration_as_secs(now - self.started_at) * self.bpm / 60.0;
+        self.started_at = now - duration_from_secs(beats_elapsed * 60.0 / bpm);
+        self.bpm = bpm;
+    }
     pub fn apply_event(&mut self, event: ClockEvent) {
         match event {
             ClockEvent::Tap(now) => {
@@ -73,32 +186,479 @@ impl Clock {
                 }
             }
             ClockEvent::BpmChanged(bpm) => {
-                // Periodically reset the start time to avoid glitchiness when tempo slightly drifts
-                let snapshot = self.snapshot();
-                let beat_secs = snapshot.secs_per_meter(Beats::new(1.0));
-
-                if snapshot.secs_elapsed() - (beat_secs * 48.0) >= 0.0 {
-                    self.started_at = self.started_at + duration_from_secs(beat_secs * 48.0);
-                }
+                self.rebase_bpm(bpm);
+                self.state = ClockState::Automatic;
+            }
+            ClockEvent::Sync {
+                bpm,
+                downbeat_at,
+                beats_per_bar,
+            } => {
+                // Line up with the source's bars while keeping our own beat count, so effects
+                // longer than a bar carry on. The bar nearest to where we think the downbeat was
+                // is the one we line up with
+                let downbeat_at = downbeat_at - self.beats_as_duration(self.phase_nudge);
+                let beats_at_downbeat =
+                    duration_as_secs(downbeat_at - self.started_at) * self.bpm / 60.0;
+                let bars_at_downbeat = (beats_at_downbeat / beats_per_bar).round();
+                self.started_at =
+                    downbeat_at - duration_from_secs(bars_at_downbeat * beats_per_bar * 60.0 / bpm);
 
                 self.state = ClockState::Automatic;
                 self.bpm = bpm;
+                self.time_signature.beats_per_bar = beats_per_bar;
+            }
+            ClockEvent::Start(at) => {
+                // Beat one of the source lines up with beat one of our effects
+                self.started_at = at - self.beats_as_duration(self.phase_nudge);
+                self.transport = Transport::Running;
+                self.song_position = None;
+            }
+            ClockEvent::Continue(at) => {
+                match (self.song_position.take(), &self.transport) {
+                    (Some(position), _) => {
+                        self.started_at = at
+                            - self.beats_as_duration(position)
+                            - self.beats_as_duration(self.phase_nudge);
+                    }
+                    // Pick up from wherever effects were held while stopped
+                    (None, Transport::Stopped { at: stopped_at }) => match self.stop_behaviour {
+                        StopBehaviour::Ignore => {}
+                        StopBehaviour::Freeze => {
+                            self.started_at = self.started_at + (at - *stopped_at);
+                        }
+                        StopBehaviour::Park => {
+                            self.started_at = at - self.beats_as_duration(self.phase_nudge);
+                        }
+                    },
+                    (None, Transport::Running) => {}
+                }
+                self.transport = Transport::Running;
+            }
+            ClockEvent::Stop(at) => {
+                if self.transport == Transport::Running {
+                    self.transport = Transport::Stopped { at };
+                }
+            }
+            ClockEvent::SongPosition(position) => {
+                self.song_position = Some(position);
+            }
+            ClockEvent::Resync(at) => {
+                if let ClockState::Automatic = self.state {
+                    // Our beat count restarts here, so the source's next sync has to be nudged by
+                    // however far through the count we were
+                    let beats_elapsed = duration_as_secs(at - self.started_at) * self.bpm / 60.0;
+                    self.phase_nudge = self.phase_nudge - Beats::new(beats_elapsed);
+                }
+                self.started_at = at;
+            }
+            ClockEvent::SetDownbeat(at) => {
+                // Shift by whole beats, the fewest that put the nearest beat on the one
+                let beats_per_bar = self.time_signature.beats_per_bar;
+                let beats_elapsed = duration_as_secs(at - self.started_at) * self.bpm / 60.0;
+                let beat_in_bar = beats_elapsed.round().rem_euclid(beats_per_bar);
+                let shift = if beat_in_bar > beats_per_bar / 2.0 {
+                    beats_per_bar - beat_in_bar
+                } else {
+                    -beat_in_bar
+                };
+
+                self.apply_event(ClockEvent::NudgePhase(Beats::new(shift)));
+            }
+            ClockEvent::NudgePhase(beats) => {
+                self.started_at = self.started_at - self.beats_as_duration(beats);
+                if let ClockState::Automatic = self.state {
+                    self.phase_nudge = self.phase_nudge + beats;
+                }
+            }
+            ClockEvent::AdjustBpm(delta) => {
+                self.rebase_bpm(clamp(self.bpm + delta, MIN_BPM, MAX_BPM));
+            }
+            ClockEvent::ScaleBpm(factor) => {
+                self.rebase_bpm(clamp(self.bpm * factor, MIN_BPM, MAX_BPM));
             }
         }
     }
+    /// Hands the tempo back to tapping, carrying on at the last tempo and phase
+    pub fn set_manual(&mut self) {
+        self.state = ClockState::Manual { taps: Vec::new() };
+        self.phase_nudge = Beats::new(0.0);
+    }
+    /// Forgets the transport of the source that was driving the clock, for when another takes
+    /// over. Link and tapped tempo never send Start or Continue, so effects held by a stop are
+    /// picked up as if continued
+    pub fn reset_transport(&mut self) {
+        self.song_position = None;
+        self.apply_event(ClockEvent::Continue(self.time.now()));
+    }
+    /// Sets the tempo while it's tapped, carrying on from the current beat. Clocks following a
+    /// source keep the source's tempo
+    pub fn set_manual_bpm(&mut self, bpm: f64) {
+        if let ClockState::Manual { .. } = self.state {
+            self.rebase_bpm(bpm);
+        }
+    }
+    pub fn time(&self) -> &Time {
+        &self.time
+    }
     pub fn started_at(&self) -> DateTime<Utc> {
         self.started_at
     }
     pub fn bpm(&self) -> f64 {
         self.bpm
     }
+    pub fn stop_behaviour(&self) -> StopBehaviour {
+        self.stop_behaviour
+    }
+    pub fn set_stop_behaviour(&mut self, stop_behaviour: StopBehaviour) {
+        self.stop_behaviour = stop_behaviour;
+    }
+    /// Whether a clock source's transport has stopped
+    pub fn is_stopped(&self) -> bool {
+        match self.transport {
+            Transport::Stopped { .. } => true,
+            Transport::Running => false,
+        }
+    }
+    pub fn time_signature(&self) -> TimeSignature {
+        self.time_signature
+    }
+    pub fn set_time_signature(&mut self, time_signature: TimeSignature) {
+        self.time_signature = time_signature;
+    }
     pub fn secs_elapsed(&self) -> f64 {
-        duration_as_secs(Utc::now() - self.started_at())
+        match (&self.transport, self.stop_behaviour) {
+            (Transport::Stopped { at }, StopBehaviour::Freeze) => {
+                duration_as_secs(*at - self.started_at())
+            }
+            (Transport::Stopped { .. }, StopBehaviour::Park) => 0.0,
+            _ => duration_as_secs(self.time.now() - self.started_at()),
+        }
     }
     pub fn snapshot(&self) -> ClockSnapshot {
         ClockSnapshot {
             secs_elapsed: self.secs_elapsed(),
             bpm: self.bpm(),
+            time_signature: self.time_signature,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effect::{DimmerEffect, DimmerModulator, Waveform};
+    use std::sync::Arc;
+
+    fn manual_clock(bpm: f64) -> (Arc<ManualTime>, Clock) {
+        let time = Arc::new(ManualTime::new());
+        let clock = Clock::with_time(bpm, Time::from(time.clone()));
+        (time, clock)
+    }
+
+    fn beats_elapsed(clock: &Clock) -> f64 {
+        f64::from(clock.snapshot().beats_elapsed())
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    fn tap(time: &ManualTime, clock: &mut Clock, taps: usize, secs_apart: f64) {
+        for idx in 0..taps {
+            if idx > 0 {
+                time.advance_secs(secs_apart);
+            }
+            clock.apply_event(ClockEvent::Tap(time.now()));
+        }
+    }
+
+    #[test]
+    fn four_taps_set_tempo_and_phase() {
+        let (time, mut clock) = manual_clock(128.0);
+        time.advance_secs(0.3);
+        tap(&time, &mut clock, 4, 0.5);
+
+        assert_near(clock.bpm(), 120.0);
+        assert_near(beats_elapsed(&clock), 0.0);
+        time.advance_secs(0.25);
+        assert_near(beats_elapsed(&clock), 0.5);
+    }
+
+    #[test]
+    fn fewer_than_four_taps_keep_tempo() {
+        let (time, mut clock) = manual_clock(128.0);
+        tap(&time, &mut clock, 3, 0.5);
+
+        assert_near(clock.bpm(), 128.0);
+    }
+
+    #[test]
+    fn taps_after_a_pause_start_over() {
+        let (time, mut clock) = manual_clock(128.0);
+        tap(&time, &mut clock, 3, 0.5);
+        time.advance_secs(2.0);
+        tap(&time, &mut clock, 4, 0.4);
+
+        assert_near(clock.bpm(), 150.0);
+    }
+
+    #[test]
+    fn bpm_changes_carry_on_from_the_current_beat() {
+        let (time, mut clock) = manual_clock(120.0);
+        time.advance_secs(2.0);
+        clock.apply_event(ClockEvent::BpmChanged(60.0));
+
+        assert_near(beats_elapsed(&clock), 4.0);
+        time.advance_secs(1.0);
+        assert_near(beats_elapsed(&clock), 5.0);
+    }
+
+    #[test]
+    fn relative_tempo_changes_carry_on_from_the_current_beat() {
+        let (time, mut clock) = manual_clock(120.0);
+        time.advance_secs(1.0);
+
+        clock.apply_event(ClockEvent::ScaleBpm(2.0));
+        assert_near(clock.bpm(), 240.0);
+        assert_near(beats_elapsed(&clock), 2.0);
+
+        clock.apply_event(ClockEvent::ScaleBpm(0.5));
+        clock.apply_event(ClockEvent::AdjustBpm(0.1));
+        assert_near(clock.bpm(), 120.1);
+        assert_near(beats_elapsed(&clock), 2.0);
+    }
+
+    #[test]
+    fn relative_tempo_changes_stay_in_range() {
+        let (_, mut clock) = manual_clock(120.0);
+
+        for _ in 0..10 {
+            clock.apply_event(ClockEvent::ScaleBpm(0.5));
+        }
+        assert_near(clock.bpm(), MIN_BPM);
+        clock.apply_event(ClockEvent::AdjustBpm(-1.0));
+        assert_near(clock.bpm(), MIN_BPM);
+
+        for _ in 0..10 {
+            clock.apply_event(ClockEvent::ScaleBpm(2.0));
+        }
+        assert_near(clock.bpm(), MAX_BPM);
+    }
+
+    #[test]
+    fn sync_lines_up_bars_keeping_the_beat_count() {
+        let (time, mut clock) = manual_clock(120.0);
+        time.advance_secs(10.1);
+
+        // The source's bar started a twentieth of a second ago, near our 21st beat
+        clock.apply_event(ClockEvent::Sync {
+            bpm: 120.0,
+            downbeat_at: time.now() - duration_from_secs(0.05),
+            beats_per_bar: 4.0,
+        });
+        assert_near(beats_elapsed(&clock), 20.1);
+    }
+
+    #[test]
+    fn nudges_shift_phase() {
+        let (time, mut clock) = manual_clock(120.0);
+        time.advance_secs(1.0);
+
+        clock.apply_event(ClockEvent::NudgePhase(Beats::new(0.25)));
+        assert_near(beats_elapsed(&clock), 2.25);
+        clock.apply_event(ClockEvent::NudgePhase(Beats::new(-0.5)));
+        assert_near(beats_elapsed(&clock), 1.75);
+    }
+
+    #[test]
+    fn nudges_survive_source_syncs() {
+        let (time, mut clock) = manual_clock(120.0);
+        let downbeat_at = time.now();
+        let sync = || ClockEvent::Sync {
+            bpm: 120.0,
+            downbeat_at,
+            beats_per_bar: 4.0,
+        };
+        time.advance_secs(1.0);
+
+        clock.apply_event(sync());
+        clock.apply_event(ClockEvent::NudgePhase(Beats::new(0.25)));
+        clock.apply_event(sync());
+        assert_near(beats_elapsed(&clock), 2.25);
+
+        // Once the source has gone, nudges are forgotten
+        clock.set_manual();
+        clock.apply_event(ClockEvent::Sync {
+            bpm: 120.0,
+            downbeat_at,
+            beats_per_bar: 4.0,
+        });
+        assert_near(beats_elapsed(&clock), 2.0);
+    }
+
+    #[test]
+    fn resync_restarts_the_beat() {
+        let (time, mut clock) = manual_clock(120.0);
+        time.advance_secs(1.3);
+
+        clock.apply_event(ClockEvent::Resync(time.now()));
+        assert_near(beats_elapsed(&clock), 0.0);
+    }
+
+    #[test]
+    fn resync_survives_source_syncs() {
+        let (time, mut clock) = manual_clock(120.0);
+        let downbeat_at = time.now();
+        time.advance_secs(1.0);
+        clock.apply_event(ClockEvent::Sync {
+            bpm: 120.0,
+            downbeat_at,
+            beats_per_bar: 4.0,
+        });
+
+        // Beat three of the source's bar is our first
+        clock.apply_event(ClockEvent::Resync(time.now()));
+        time.advance_secs(0.5);
+        clock.apply_event(ClockEvent::Sync {
+            bpm: 120.0,
+            downbeat_at,
+            beats_per_bar: 4.0,
+        });
+        assert_near(beats_elapsed(&clock), 1.0);
+    }
+
+    #[test]
+    fn set_downbeat_moves_the_nearest_beat_to_the_one() {
+        let (time, mut clock) = manual_clock(120.0);
+
+        // Beat 3 of the second bar moves back to the start of it
+        time.advance_secs(3.05);
+        clock.apply_event(ClockEvent::SetDownbeat(time.now()));
+        assert_near(beats_elapsed(&clock), 4.1);
+        assert_eq!(clock.snapshot().beat_in_bar(), 0);
+
+        // Beat 4 moves forward to the start of the next bar
+        time.advance_secs(1.5);
+        clock.apply_event(ClockEvent::SetDownbeat(time.now()));
+        assert_near(beats_elapsed(&clock), 8.1);
+        assert_eq!(clock.snapshot().current_bar(), 2);
+    }
+
+    #[test]
+    fn stopped_transport_freezes_or_parks() {
+        let (time, mut clock) = manual_clock(120.0);
+        time.advance_secs(1.0);
+        clock.apply_event(ClockEvent::Stop(time.now()));
+        time.advance_secs(1.0);
+
+        assert_near(beats_elapsed(&clock), 4.0);
+        clock.set_stop_behaviour(StopBehaviour::Freeze);
+        assert_near(beats_elapsed(&clock), 2.0);
+        clock.set_stop_behaviour(StopBehaviour::Park);
+        assert_near(beats_elapsed(&clock), 0.0);
+
+        // Continuing picks up where the transport stopped
+        clock.set_stop_behaviour(StopBehaviour::Freeze);
+        clock.apply_event(ClockEvent::Continue(time.now()));
+        assert!(!clock.is_stopped());
+        assert_near(beats_elapsed(&clock), 2.0);
+    }
+
+    #[test]
+    fn continuing_picks_up_where_effects_were_held() {
+        let continued_beats = |stop_behaviour: StopBehaviour| {
+            let (time, mut clock) = manual_clock(120.0);
+            clock.set_stop_behaviour(stop_behaviour);
+            time.advance_secs(1.0);
+            clock.apply_event(ClockEvent::Stop(time.now()));
+            time.advance_secs(1.0);
+            clock.apply_event(ClockEvent::Continue(time.now()));
+            time.advance_secs(0.5);
+            beats_elapsed(&clock)
+        };
+
+        // Effects ran through the stop, so carry on without a jump
+        assert_near(continued_beats(StopBehaviour::Ignore), 5.0);
+        assert_near(continued_beats(StopBehaviour::Freeze), 3.0);
+        assert_near(continued_beats(StopBehaviour::Park), 1.0);
+    }
+
+    #[test]
+    fn changing_source_resets_the_transport() {
+        let (time, mut clock) = manual_clock(120.0);
+        clock.set_stop_behaviour(StopBehaviour::Freeze);
+        time.advance_secs(1.0);
+        clock.apply_event(ClockEvent::Stop(time.now()));
+        clock.apply_event(ClockEvent::SongPosition(Beats::new(16.0)));
+        time.advance_secs(1.0);
+
+        clock.set_manual();
+        clock.reset_transport();
+        assert!(!clock.is_stopped());
+        time.advance_secs(0.5);
+        assert_near(beats_elapsed(&clock), 3.0);
+    }
+
+    #[test]
+    fn rates_scale_beats() {
+        let (time, clock) = manual_clock(120.0);
+        time.advance_secs(1.0);
+        let snapshot = clock.snapshot();
+
+        assert_near(
+            f64::from(snapshot.with_rate(Rate::new(0.5)).beats_elapsed()),
+            1.0,
+        );
+        assert_near(
+            f64::from(snapshot.with_rate(Rate::new(2.0)).beats_elapsed()),
+            4.0,
+        );
+    }
+
+    #[test]
+    fn bars_and_phrases_follow_the_time_signature() {
+        let (time, mut clock) = manual_clock(120.0);
+        clock.set_time_signature(TimeSignature {
+            beats_per_bar: 3.0,
+            bars_per_phrase: 8,
+        });
+        // 28 and a half beats in, half way through the second beat of the tenth bar
+        time.advance_secs(14.25);
+        let snapshot = clock.snapshot();
+
+        assert_eq!(snapshot.current_bar(), 9);
+        assert_eq!(snapshot.beat_in_bar(), 1);
+        assert_near(snapshot.bar_elapsed_percent(), 1.5 / 3.0);
+        assert_near(f64::from(snapshot.phrase_position()), 1.5);
+        assert_near(f64::from(snapshot.bars_as_beats(Bars::new(2.0))), 6.0);
+    }
+
+    #[test]
+    fn effects_follow_clock_phase_and_rate() {
+        let (time, mut clock) = manual_clock(120.0);
+        let effect = DimmerEffect::from(DimmerModulator::new(
+            Waveform::SawUp,
+            Beats::new(4.0),
+            (0.0, 1.0),
+        ));
+        let dimmer_at_rate = |clock: &Clock, rate| effect.dimmer(&clock.snapshot().with_rate(rate));
+
+        time.advance_secs(0.5);
+        assert_near(dimmer_at_rate(&clock, Rate::new(1.0)), 0.25);
+        assert_near(dimmer_at_rate(&clock, Rate::new(2.0)), 0.5);
+        assert_near(dimmer_at_rate(&clock, Rate::new(0.5)), 0.125);
+
+        // Restarting the beat restarts the effect
+        time.advance_secs(0.75);
+        clock.apply_event(ClockEvent::Resync(time.now()));
+        assert_near(dimmer_at_rate(&clock, Rate::new(1.0)), 0.0);
+        time.advance_secs(1.0);
+        assert_near(dimmer_at_rate(&clock, Rate::new(1.0)), 0.5);
+    }
+}
@@ -1,15 +1,61 @@
 use std::borrow::Cow;
+use std::convert::TryInto;
 
 use itertools::Itertools;
-use rand::{seq::SliceRandom, thread_rng};
+use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     clock::{Beats, ClockSnapshot},
-    effect::EffectDirection,
-    fixture::FixtureParams,
+    effect::{EffectDirection, EffectError},
+    fixture::{FixtureGroupId, FixtureParams},
 };
 
+// Locations are compared at millimetre precision, so fixtures rigged side by
+// side are treated as being at the same point
+fn millimetres(metres: f64) -> i64 {
+    (metres * 1000.0).round() as i64
+}
+
+// Index of `value` in the unique `values`, largest first
+fn location_rank(value: i64, values: impl Iterator<Item = i64>) -> usize {
+    values
+        .unique()
+        .sorted()
+        .rev()
+        .position(|x| x == value)
+        .unwrap()
+}
+
+// Where a fixture comes in the group an effect is running in. Fixtures the group lists come
+// first, in its order, followed by the group's other fixtures in patch order. Effects that
+// aren't running in a group go through every fixture in patch order
+fn fixture_index(
+    fixture: &FixtureParams,
+    fixtures: &[&FixtureParams],
+    group_id: Option<FixtureGroupId>,
+) -> usize {
+    let group_id = match group_id {
+        Some(group_id) => group_id,
+        None => return fixtures.iter().position(|x| x.id == fixture.id).unwrap(),
+    };
+    if let Some(group_order) = fixture.group_orders.get(&group_id) {
+        return *group_order;
+    }
+
+    let ordered_count = fixtures
+        .iter()
+        .filter(|x| x.group_orders.contains_key(&group_id))
+        .count();
+    let unordered_idx = fixtures
+        .iter()
+        .filter(|x| x.group_ids.contains(&group_id) && !x.group_orders.contains_key(&group_id))
+        .position(|x| x.id == fixture.id)
+        .unwrap_or(0);
+
+    ordered_count + unordered_idx
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum ClockOffsetMode {
@@ -19,40 +65,79 @@ pub enum ClockOffsetMode {
     Location(EffectDirection),
 }
 
+// A shuffled array of the numbers 0 - 31
+fn shuffled_seed(rng: &mut impl Rng) -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    for (i, x) in seed.iter_mut().enumerate() {
+        *x = i as u8;
+    }
+    seed.shuffle(rng);
+    seed
+}
+
+fn random_seed() -> [u8; 32] {
+    shuffled_seed(&mut thread_rng())
+}
+
+// The same shuffle every time for the same key
+fn keyed_seed(key: &str) -> [u8; 32] {
+    let hash = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, key.as_bytes());
+    let hash = u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap());
+    shuffled_seed(&mut StdRng::seed_from_u64(hash))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ClockOffset {
     mode: ClockOffsetMode,
     offset: Beats,
+    // Effects written by hand leave this out. They get the same order every time they're
+    // loaded, so reloading a file doesn't reshuffle effects that haven't changed
+    #[serde(default = "ClockOffset::default_seed")]
     seed: [u8; 32],
 }
 impl ClockOffset {
+    fn default_seed() -> [u8; 32] {
+        keyed_seed("")
+    }
     pub fn new(mode: ClockOffsetMode, offset: Beats) -> ClockOffset {
-        // create a seed array of the numbers 0 - 31
-        let mut rng = thread_rng();
-        let mut seed = [0u8; 32];
-        for (i, x) in seed.iter_mut().enumerate() {
-            *x = i as u8;
+        ClockOffset {
+            mode,
+            offset,
+            seed: random_seed(),
+        }
+    }
+    /// Gives an offset that left its seed out an order of its own, from `key`
+    pub fn seed_from(&mut self, key: &str) {
+        if self.seed == ClockOffset::default_seed() {
+            self.seed = keyed_seed(key);
         }
-        seed.shuffle(&mut rng);
-
-        ClockOffset { mode, offset, seed }
     }
+    pub fn validate(&self) -> Result<(), EffectError> {
+        let beats = f64::from(self.offset);
+        if beats.is_finite() {
+            Ok(())
+        } else {
+            Err(EffectError::InvalidClockOffset(beats))
+        }
+    }
+    /// `group_id` is the group the effect is running in for this fixture, if any
     pub fn offset_for_fixture(
         &self,
         fixture: &FixtureParams,
         fixtures: &[&FixtureParams],
+        group_id: Option<FixtureGroupId>,
     ) -> Beats {
         match self.mode {
             ClockOffsetMode::GroupId => {
                 self.offset
                     * fixture
-                        .group_id
-                        .map(|group_id| usize::from(group_id) as f64 - 1.0)
+                        .group_ids
+                        .first()
+                        .map(|group_id| usize::from(*group_id) as f64 - 1.0)
                         .unwrap_or(0.0)
             }
             ClockOffsetMode::FixtureIndex => {
-                let fixture_idx = fixtures.iter().position(|x| x == &fixture).unwrap();
-                self.offset * fixture_idx as f64
+                self.offset * fixture_index(fixture, fixtures, group_id) as f64
             }
             ClockOffsetMode::Random => {
                 let fixture_idx = fixtures.iter().position(|x| x == &fixture).unwrap();
@@ -65,50 +150,41 @@ impl ClockOffset {
 
                 match (fixture.location.as_ref(), direction) {
                     (Some(location), EffectDirection::LeftToRight) => {
-                        let location_idx = fixture_locations
-                            .map(|location| location.x)
-                            .unique()
-                            .sorted()
-                            .rev()
-                            .position(|x| x == location.x)
-                            .unwrap();
+                        let location_idx = location_rank(
+                            millimetres(location.x()),
+                            fixture_locations.map(|location| millimetres(location.x())),
+                        );
 
                         self.offset * location_idx as f64
                     }
                     (Some(location), EffectDirection::BottomToTop) => {
-                        let location_idx = fixture_locations
-                            .map(|location| location.y)
-                            .unique()
-                            .sorted()
-                            .rev()
-                            .position(|y| y == location.y)
-                            .unwrap();
+                        let location_idx = location_rank(
+                            millimetres(location.y()),
+                            fixture_locations.map(|location| millimetres(location.y())),
+                        );
 
                         self.offset * location_idx as f64
                     }
                     (Some(location), EffectDirection::ToCenter)
                     | (Some(location), EffectDirection::FromCenter) => {
                         let xs = fixture_locations
-                            .map(|location| location.x)
-                            .unique()
-                            .sorted()
+                            .map(|location| millimetres(location.x()))
                             .collect_vec();
-                        let len = xs.len();
+                        let min_x = xs.iter().copied().min().unwrap();
+                        let max_x = xs.iter().copied().max().unwrap();
 
-                        let mut pairs = xs
-                            .clone()
-                            .into_iter()
-                            .zip(xs.into_iter().rev())
-                            .take((len + 1) / 2);
+                        // Doubled distance from the centre of the rig, to avoid rounding when halving
+                        let distance_from_center = |x: i64| (x * 2 - (min_x + max_x)).abs();
+                        let distances = xs.iter().map(|x| distance_from_center(*x));
+                        let distance = distance_from_center(millimetres(location.x()));
 
+                        // Outermost fixtures go first moving in to the center, innermost
+                        // fixtures go first moving out from the center
                         let location_idx = match direction {
-                            EffectDirection::ToCenter => pairs
-                                .rev()
-                                .position(|(x1, x2)| x1 == location.x || x2 == location.x)
-                                .unwrap(),
-                            EffectDirection::FromCenter => pairs
-                                .position(|(x1, x2)| x1 == location.x || x2 == location.x)
-                                .unwrap(),
+                            EffectDirection::FromCenter => location_rank(distance, distances),
+                            EffectDirection::ToCenter => {
+                                location_rank(-distance, distances.map(|distance| -distance))
+                            }
                             _ => unreachable!(),
                         };
 
@@ -124,8 +200,9 @@ impl ClockOffset {
         clock: &'a ClockSnapshot,
         fixture: &FixtureParams,
         fixtures: &[&FixtureParams],
+        group_id: Option<FixtureGroupId>,
     ) -> Cow<'a, ClockSnapshot> {
-        clock.shift(self.offset_for_fixture(fixture, fixtures))
+        clock.shift(self.offset_for_fixture(fixture, fixtures, group_id))
     }
 }
 
@@ -134,9 +211,71 @@ pub fn offsetted_for_fixture<'a>(
     clock: &'a ClockSnapshot,
     fixture: &FixtureParams,
     fixtures: &[&FixtureParams],
+    group_id: Option<FixtureGroupId>,
 ) -> Cow<'a, ClockSnapshot> {
     match clock_offset {
-        Some(clock_offset) => clock_offset.offsetted_for_fixture(clock, fixture, fixtures),
+        Some(clock_offset) => {
+            clock_offset.offsetted_for_fixture(clock, fixture, fixtures, group_id)
+        }
         None => Cow::Borrowed(clock),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{DimmerCurve, Fixture, FixtureId, FixtureOverrides, FixtureProfile};
+
+    fn fixture(
+        key: &str,
+        group_ids: Vec<FixtureGroupId>,
+        group_orders: Vec<(FixtureGroupId, usize)>,
+    ) -> FixtureParams {
+        let profile = FixtureProfile {
+            slug: "par".to_owned(),
+            label: "Par".to_owned(),
+            channel_count: 1,
+            supported_effects: vec![],
+            dimmer_curve: DimmerCurve::default(),
+            beams: vec![],
+            dimmer_channel: None,
+            pan_channel: None,
+            tilt_channel: None,
+        };
+
+        Fixture::new(
+            FixtureId::from_key(key),
+            profile,
+            1,
+            1,
+            group_ids,
+            None,
+            vec![],
+            FixtureOverrides::default(),
+        )
+        .with_group_orders(group_orders.into_iter().collect())
+        .params
+    }
+
+    #[test]
+    fn fixture_indexes_follow_the_group_an_effect_runs_in() {
+        let (pars, chase) = (FixtureGroupId::new(1), FixtureGroupId::new(2));
+        let fixtures = vec![
+            fixture("a", vec![pars, chase], vec![(chase, 1)]),
+            fixture("b", vec![pars, chase], vec![]),
+            fixture("c", vec![pars, chase], vec![(chase, 0)]),
+            fixture("d", vec![pars], vec![]),
+        ];
+        let fixtures: Vec<_> = fixtures.iter().collect();
+        let indexes = |group_id: Option<FixtureGroupId>, count: usize| -> Vec<usize> {
+            (0..count)
+                .map(|idx| fixture_index(fixtures[idx], &fixtures, group_id))
+                .collect()
+        };
+
+        // Chase lists c then a, its other fixtures follow them
+        assert_eq!(indexes(Some(chase), 3), vec![1, 2, 0]);
+        assert_eq!(indexes(Some(pars), 4), vec![0, 1, 2, 3]);
+        assert_eq!(indexes(None, 4), vec![0, 1, 2, 3]);
+    }
+}
@@ -1,10 +1,11 @@
-use crate::clock::{Beats, Rate};
+use crate::clock::{Bars, Beats, Rate, TimeSignature};
 use std::borrow::Cow;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ClockSnapshot {
     pub secs_elapsed: f64,
     pub bpm: f64,
+    pub time_signature: TimeSignature,
 }
 impl ClockSnapshot {
     pub fn with_rate(&self, rate: Rate) -> Cow<ClockSnapshot> {
@@ -14,6 +15,7 @@ impl ClockSnapshot {
             Cow::Owned(ClockSnapshot {
                 secs_elapsed: self.secs_elapsed * f64::from(rate),
                 bpm: self.bpm,
+                time_signature: self.time_signature,
             })
         }
     }
@@ -27,6 +29,7 @@ impl ClockSnapshot {
             Cow::Owned(ClockSnapshot {
                 secs_elapsed: self.secs_elapsed + secs_to_shift,
                 bpm: self.bpm,
+                time_signature: self.time_signature,
             })
         }
     }
@@ -42,4 +45,38 @@ impl ClockSnapshot {
 
         1.0 / secs_per_meter * (secs_elapsed % secs_per_meter)
     }
+    pub fn beats_elapsed(&self) -> Beats {
+        Beats::new(self.secs_elapsed * self.bpm / 60.0)
+    }
+    /// The next beat count that's a whole number of `meter_length`s, bars start on the first
+    pub fn next_meter_start(&self, meter_length: Beats) -> Beats {
+        let meter_length = f64::from(meter_length);
+        let meters_elapsed = (f64::from(self.beats_elapsed()) / meter_length).floor();
+
+        Beats::new((meters_elapsed + 1.0) * meter_length)
+    }
+    pub fn bars_as_beats(&self, bars: Bars) -> Beats {
+        Beats::new(f64::from(bars) * self.time_signature.beats_per_bar)
+    }
+    /// Bars started so far, counting from 0
+    pub fn current_bar(&self) -> u64 {
+        (f64::from(self.beats_elapsed()) / self.time_signature.beats_per_bar)
+            .max(0.0)
+            .floor() as u64
+    }
+    /// Beat of the current bar, counting from 0 for the downbeat
+    pub fn beat_in_bar(&self) -> u32 {
+        (f64::from(self.beats_elapsed()).max(0.0) % self.time_signature.beats_per_bar).floor()
+            as u32
+    }
+    pub fn bar_elapsed_percent(&self) -> f64 {
+        self.meter_elapsed_percent(self.bars_as_beats(Bars::new(1.0)))
+    }
+    /// How far through the current phrase we are, from 0 up to the bars in a phrase
+    pub fn phrase_position(&self) -> Bars {
+        let bars_per_phrase = self.time_signature.bars_per_phrase.max(1) as f64;
+        let phrase_beats = self.bars_as_beats(Bars::new(bars_per_phrase));
+
+        Bars::new(self.meter_elapsed_percent(phrase_beats) * bars_per_phrase)
+    }
 }
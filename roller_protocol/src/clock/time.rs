@@ -0,0 +1,118 @@
+use chrono::{DateTime, TimeZone, Utc};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+/// Where the clock and engine get the current time from
+pub trait TimeSource: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+    fn instant(&self) -> Instant;
+    /// Sources that move on by themselves return false, otherwise `waker` is woken when the
+    /// time next changes
+    fn wake_when_advanced(&self, _waker: &Waker) -> bool {
+        false
+    }
+}
+
+/// The system's clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemTime;
+impl TimeSource for SystemTime {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Time that only moves when it's advanced, for tests
+#[derive(Debug)]
+pub struct ManualTime {
+    started_at: DateTime<Utc>,
+    started_at_instant: Instant,
+    elapsed: Mutex<Duration>,
+    wakers: Mutex<Vec<Waker>>,
+}
+impl ManualTime {
+    pub fn new() -> ManualTime {
+        ManualTime {
+            started_at: Utc.ymd(2020, 1, 1).and_hms(0, 0, 0),
+            started_at_instant: Instant::now(),
+            elapsed: Mutex::new(Duration::from_secs(0)),
+            wakers: Mutex::new(vec![]),
+        }
+    }
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+    pub fn advance_secs(&self, secs: f64) {
+        self.advance(Duration::from_secs_f64(secs));
+    }
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+impl Default for ManualTime {
+    fn default() -> ManualTime {
+        ManualTime::new()
+    }
+}
+impl TimeSource for ManualTime {
+    fn now(&self) -> DateTime<Utc> {
+        self.started_at + chrono::Duration::from_std(self.elapsed()).unwrap()
+    }
+    fn instant(&self) -> Instant {
+        self.started_at_instant + self.elapsed()
+    }
+    fn wake_when_advanced(&self, waker: &Waker) -> bool {
+        self.wakers.lock().unwrap().push(waker.clone());
+        true
+    }
+}
+
+/// A shared time source. Clocks compare equal whatever time source they use, as it isn't part of
+/// their state
+#[derive(Clone)]
+pub struct Time(Arc<dyn TimeSource>);
+impl Time {
+    pub fn new(source: Arc<dyn TimeSource>) -> Time {
+        Time(source)
+    }
+    pub fn system() -> Time {
+        Time(Arc::new(SystemTime))
+    }
+    pub fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+    pub fn instant(&self) -> Instant {
+        self.0.instant()
+    }
+    pub fn wake_when_advanced(&self, waker: &Waker) -> bool {
+        self.0.wake_when_advanced(waker)
+    }
+}
+impl<T: TimeSource + 'static> From<Arc<T>> for Time {
+    fn from(source: Arc<T>) -> Time {
+        Time(source)
+    }
+}
+impl Default for Time {
+    fn default() -> Time {
+        Time::system()
+    }
+}
+impl PartialEq for Time {
+    fn eq(&self, _other: &Time) -> bool {
+        true
+    }
+}
+impl fmt::Debug for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Time")
+    }
+}
@@ -53,6 +53,26 @@ impl From<Beats> for f64 {
     }
 }
 
+// Bars are counted in the clock's time signature, see `ClockSnapshot::bars_as_beats`
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, From, Into, Serialize, Deserialize,
+)]
+pub struct Bars(OrderedFloat<f64>);
+impl Bars {
+    pub fn new(x: impl Into<OrderedFloat<f64>>) -> Bars {
+        Bars(x.into())
+    }
+    pub fn is_zero(&self) -> bool {
+        self.0.into_inner() == 0.0
+    }
+}
+
+impl From<Bars> for f64 {
+    fn from(bars: Bars) -> f64 {
+        bars.0.into()
+    }
+}
+
 #[derive(
     Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, From, Into, Serialize, Deserialize,
 )]
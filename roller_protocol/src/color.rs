@@ -1,9 +1,10 @@
+use ordered_float::OrderedFloat;
 use palette::Hsl;
 use serde::{Deserialize, Serialize};
 
 pub type Hsl64 = Hsl<palette::encoding::srgb::Srgb, f64>;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Color {
     White,
     Yellow,
@@ -13,10 +14,13 @@ pub enum Color {
     DarkBlue,
     Teal,
     Green,
+    // An entry in the active palette, by name
+    Palette(String),
 }
 impl Color {
-    pub fn to_hsl(self) -> Hsl64 {
-        match self {
+    // Palette entries can only be resolved by `ColorPalette::hsl`
+    fn fixed_hsl(&self) -> Option<Hsl64> {
+        Some(match self {
             Color::White => Hsl::new(0.0, 0.0, 1.0),
             Color::Yellow => Hsl::new(42.0, 1.0, 0.5),
             Color::DeepOrange => Hsl::new(32.0, 1.0, 0.5),
@@ -25,19 +29,107 @@ impl Color {
             Color::DarkBlue => Hsl::new(240.0, 1.0, 0.5),
             Color::Teal => Hsl::new(180.0, 1.0, 0.5),
             Color::Green => Hsl::new(120.0, 1.0, 0.5),
-        }
-    }
-    pub fn to_rgb(self) -> palette::LinSrgb<f64> {
-        palette::LinSrgb::from(self.to_hsl())
+            Color::Palette(_) => return None,
+        })
     }
 }
-impl From<Color> for palette::LinSrgb<f64> {
-    fn from(color: Color) -> palette::LinSrgb<f64> {
-        color.to_rgb()
+
+fn clamp_channel(x: f64) -> f64 {
+    x.max(0.0).min(255.0) / 255.0
+}
+
+/// A color as written in a project, in whichever space is most convenient
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorValue {
+    // Hue in degrees, saturation and lightness from 0 to 1
+    Hsl {
+        hue: OrderedFloat<f64>,
+        saturation: OrderedFloat<f64>,
+        lightness: OrderedFloat<f64>,
+    },
+    // sRGB, 0 to 255 per channel
+    Rgb {
+        red: u8,
+        green: u8,
+        blue: u8,
+    },
+    // The color of white light at a temperature, eg 3200 for tungsten or 5600 for daylight
+    Kelvin(OrderedFloat<f64>),
+}
+impl ColorValue {
+    pub fn to_hsl(self) -> Hsl64 {
+        match self {
+            ColorValue::Hsl {
+                hue,
+                saturation,
+                lightness,
+            } => Hsl::new(
+                hue.into_inner(),
+                saturation.into_inner(),
+                lightness.into_inner(),
+            ),
+            ColorValue::Rgb { red, green, blue } => Hsl::from(palette::Srgb::new(
+                red as f64 / 255.0,
+                green as f64 / 255.0,
+                blue as f64 / 255.0,
+            )),
+            ColorValue::Kelvin(kelvin) => {
+                // Tanner Helland's approximation of the blackbody curve, good from 1000K to 40000K
+                let temperature = kelvin.into_inner().max(1000.0).min(40000.0) / 100.0;
+
+                let red = if temperature <= 66.0 {
+                    255.0
+                } else {
+                    329.698727446 * (temperature - 60.0).powf(-0.1332047592)
+                };
+                let green = if temperature <= 66.0 {
+                    99.4708025861 * temperature.ln() - 161.1195681661
+                } else {
+                    288.1221695283 * (temperature - 60.0).powf(-0.0755148492)
+                };
+                let blue = if temperature >= 66.0 {
+                    255.0
+                } else if temperature <= 19.0 {
+                    0.0
+                } else {
+                    138.5177312231 * (temperature - 10.0).ln() - 305.0447927307
+                };
+
+                Hsl::from(palette::Srgb::new(
+                    clamp_channel(red),
+                    clamp_channel(green),
+                    clamp_channel(blue),
+                ))
+            }
+        }
     }
 }
-impl From<Color> for Hsl64 {
-    fn from(color: Color) -> Hsl64 {
-        color.to_hsl()
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaletteColor {
+    pub name: String,
+    pub color: ColorValue,
+}
+
+/// A named set of colors. `Color::Palette` entries refer to colors by name in the active
+/// palette, so switching between palettes that share entry names swaps every color at once
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ColorPalette {
+    pub name: String,
+    pub colors: Vec<PaletteColor>,
+}
+impl ColorPalette {
+    pub fn entry(&self, name: &str) -> Option<&PaletteColor> {
+        self.colors.iter().find(|entry| entry.name == name)
+    }
+    /// Entries missing from this palette fall back to white. Control mappings and fixture park
+    /// colors are checked for these when the project loads
+    pub fn hsl(&self, color: &Color) -> Hsl64 {
+        match color {
+            Color::Palette(name) => self.entry(name).map(|entry| entry.color.to_hsl()),
+            color => color.fixed_hsl(),
+        }
+        .unwrap_or_else(|| Hsl::new(0.0, 0.0, 1.0))
     }
 }
@@ -2,13 +2,13 @@ use derive_more::{From, Into};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum NoteState {
     On,
     Off,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum InputEvent {
     ButtonPressed(ButtonGridLocation, ButtonCoordinate),
     ButtonReleased(ButtonGridLocation, ButtonCoordinate),
@@ -56,6 +56,8 @@ pub enum ButtonState {
     Inactive,
     Deactivated,
     Unused,
+    // Pressed, waiting for the next quantization boundary
+    Armed,
 }
 impl ButtonState {
     pub fn css_class(&self) -> &'static str {
@@ -64,6 +66,7 @@ impl ButtonState {
             ButtonState::Inactive => "button--inactive",
             ButtonState::Deactivated => "button--deactivated",
             ButtonState::Unused => "button--unused",
+            ButtonState::Armed => "button--armed",
         }
     }
 }
@@ -4,10 +4,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     clock::{offset::ClockOffset, Beats, ClockSnapshot},
-    color::{Color, Hsl64},
+    color::Hsl64,
 };
 
-use crate::effect::{Step, Steps, Waveform};
+use crate::effect::{validate_value, EffectError, Step, Steps, Waveform};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ColorEffect {
@@ -21,6 +21,18 @@ impl ColorEffect {
             clock_offset,
         }
     }
+    pub fn validate(&self) -> Result<(), EffectError> {
+        self.steps.validate()?;
+        for step in self.steps.iter() {
+            if let ColorModulation::HueShift(degrees) = step.modulation {
+                validate_value("hue shift", degrees.into_inner())?;
+            }
+        }
+        if let Some(clock_offset) = self.clock_offset.as_ref() {
+            clock_offset.validate()?;
+        }
+        Ok(())
+    }
     pub fn color(
         &self,
         color: Hsl64,
@@ -89,7 +101,7 @@ impl ColorModulator {
                 color.shift_hue(degrees_to_secondary * self.waveform.apply(elapsed_percent))
             }
             ColorModulation::White => {
-                color.mix(&Color::White.to_hsl(), self.waveform.apply(elapsed_percent))
+                color.mix(&Hsl64::new(0.0, 0.0, 1.0), self.waveform.apply(elapsed_percent))
             }
             ColorModulation::NoOp => color,
         }
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::clock::{offset::ClockOffset, Beats, ClockSnapshot};
 
-use crate::effect::{Step, Steps, Waveform};
+use crate::effect::{validate_value, EffectError, Step, Steps, Waveform};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct DimmerEffect {
@@ -17,6 +17,17 @@ impl DimmerEffect {
             clock_offset,
         }
     }
+    pub fn validate(&self) -> Result<(), EffectError> {
+        self.steps.validate()?;
+        for step in self.steps.iter() {
+            validate_value("dimmer scale", step.scale.min.into_inner())?;
+            validate_value("dimmer scale", step.scale.max.into_inner())?;
+        }
+        if let Some(clock_offset) = self.clock_offset.as_ref() {
+            clock_offset.validate()?;
+        }
+        Ok(())
+    }
     pub fn dimmer(&self, clock: &ClockSnapshot) -> f64 {
         let (step, elapsed_percent) = self.steps.current_step(clock);
         step.dimmer_for_elapsed_percent(elapsed_percent)
@@ -1,5 +1,6 @@
 use palette::Mix;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 mod color;
 mod dimmer;
@@ -9,7 +10,7 @@ mod waveform;
 
 pub use color::{ColorEffect, ColorModulation, ColorModulator};
 pub use dimmer::{DimmerEffect, DimmerModulator};
-pub use pixel::{PixelEffect, PixelModulator, PixelRangeSet};
+pub use pixel::{PixelBlendMode, PixelEffect, PixelModulator, PixelRangeSet};
 pub use position::{PositionEffect, PositionModulator};
 pub use waveform::Waveform;
 
@@ -18,11 +19,42 @@ use crate::{
     color::Hsl64,
 };
 
+#[derive(Debug, Error, PartialEq)]
+pub enum EffectError {
+    #[error("effect has no steps")]
+    NoSteps,
+    #[error("step length must be more than zero beats, not {0}")]
+    InvalidStepLength(f64),
+    #[error("clock offset must be a number of beats, not {0}")]
+    InvalidClockOffset(f64),
+    #[error("{0} must be a number, not {1}")]
+    InvalidValue(&'static str, f64),
+}
+
+// Zero length steps would never finish, and leave the effect dividing by zero
+fn validate_meter_length(meter_length: Beats) -> Result<(), EffectError> {
+    let beats = f64::from(meter_length);
+    if beats.is_finite() && beats > 0.0 {
+        Ok(())
+    } else {
+        Err(EffectError::InvalidStepLength(beats))
+    }
+}
+
+fn validate_value(name: &'static str, value: f64) -> Result<(), EffectError> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(EffectError::InvalidValue(name, value))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum EffectDirection {
     BottomToTop,
     ToCenter,
     FromCenter,
+    // Sweeps across fixtures with a 2D beam layout, single rows of beams are lit in full
     LeftToRight,
 }
 
@@ -58,6 +90,7 @@ pub trait Step {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Steps<T> {
     steps: Vec<T>,
 }
@@ -69,6 +102,20 @@ where
         Steps { steps }
     }
 
+    fn iter(&self) -> impl Iterator<Item = &T> {
+        self.steps.iter()
+    }
+
+    fn validate(&self) -> Result<(), EffectError> {
+        if self.steps.is_empty() {
+            return Err(EffectError::NoSteps);
+        }
+        for step in self.steps.iter() {
+            validate_meter_length(step.meter_length())?;
+        }
+        Ok(())
+    }
+
     fn total_length(&self) -> Beats {
         self.steps
             .iter()
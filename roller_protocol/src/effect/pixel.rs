@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     clock::{offset::ClockOffset, Beats, ClockSnapshot},
-    effect::{EffectDirection, Step, Steps, Waveform},
+    effect::{EffectDirection, EffectError, Step, Steps, Waveform},
 };
 
 fn percent_contained(a: (f64, f64), b: (f64, f64)) -> f64 {
@@ -40,25 +40,148 @@ impl From<&(f64, f64)> for PixelRange {
     }
 }
 
+// Which way a pixel effect travels across the face of a fixture
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PixelAxis {
+    Horizontal,
+    Vertical,
+    // Distance from the middle of the fixture
+    Radial,
+}
+
+// The spread of a fixture's beams along one axis
+#[derive(Debug)]
+struct PixelAxisLayout {
+    min: f64,
+    max: f64,
+    // Number of distinct rows or columns of beams
+    count: usize,
+}
+impl PixelAxisLayout {
+    fn new(values: impl Iterator<Item = f64>) -> PixelAxisLayout {
+        // Round off any noise left over from rotating the fixture
+        let mut values: Vec<f64> = values.map(|value| (value * 1e6).round() / 1e6).collect();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        values.dedup();
+
+        PixelAxisLayout {
+            min: values.first().copied().unwrap_or(0.0),
+            max: values.last().copied().unwrap_or(0.0),
+            count: values.len(),
+        }
+    }
+    fn is_flat(&self) -> bool {
+        self.count <= 1
+    }
+    // The slice of 0.0 to 1.0 covered by a beam at `value`
+    fn pixel_range(&self, value: f64) -> (f64, f64) {
+        if self.is_flat() {
+            return (0.0, 1.0);
+        }
+
+        let count = self.count as f64;
+        let position = (value - self.min) / (self.max - self.min) * (count - 1.0);
+
+        (position / count, (position + 1.0) / count)
+    }
+}
+
+// How far the nearest and furthest points of `range` are from the middle of the axis
+fn distance_from_center((low, high): (f64, f64)) -> (f64, f64) {
+    let furthest = f64::max((low - 0.5).abs(), (high - 0.5).abs());
+    let nearest = if low <= 0.5 && high >= 0.5 {
+        0.0
+    } else {
+        f64::min((low - 0.5).abs(), (high - 0.5).abs())
+    };
+
+    (nearest, furthest)
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct PixelRangeSet {
+    axis: PixelAxis,
     ranges: Vec<PixelRange>,
 }
 impl PixelRangeSet {
-    fn new(ranges: impl IntoIterator<Item = impl Into<PixelRange>>) -> PixelRangeSet {
+    fn new(
+        axis: PixelAxis,
+        ranges: impl IntoIterator<Item = impl Into<PixelRange>>,
+    ) -> PixelRangeSet {
         PixelRangeSet {
+            axis,
             ranges: ranges.into_iter().map(|stop| stop.into()).collect(),
         }
     }
-    pub fn pixel_dimmers(&self, pixel_count: usize) -> Vec<f64> {
-        let pixel_width = 1.0 / pixel_count as f64;
+    // Each beam's range along the effect's axis, scaled to 0.0 to 1.0
+    fn beam_ranges(&self, beam_positions: &[(f64, f64)]) -> Vec<(f64, f64)> {
+        let columns = PixelAxisLayout::new(beam_positions.iter().map(|(x, _)| *x));
+        let rows = PixelAxisLayout::new(beam_positions.iter().map(|(_, y)| *y));
 
-        (0..pixel_count)
-            .into_iter()
-            .map(|pixel_idx| {
-                let pixel_min = pixel_idx as f64 * pixel_width;
-                let pixel_max = (pixel_idx + 1) as f64 * pixel_width;
+        // A single row of beams runs effects along its length, whichever way it's hung. Left to
+        // right goes across the row instead, so lights every beam as it did before beams had
+        // locations, and only sweeps on fixtures with a 2D layout
+        let is_single_row = columns.is_flat() || rows.is_flat();
+        let axis = match self.axis {
+            PixelAxis::Horizontal if is_single_row => {
+                return beam_positions.iter().map(|_| (0.0, 1.0)).collect()
+            }
+            PixelAxis::Vertical if rows.is_flat() => PixelAxis::Horizontal,
+            axis => axis,
+        };
+
+        match axis {
+            PixelAxis::Horizontal => beam_positions
+                .iter()
+                .map(|(x, _)| columns.pixel_range(*x))
+                .collect(),
+            PixelAxis::Vertical => beam_positions
+                .iter()
+                .map(|(_, y)| rows.pixel_range(*y))
+                .collect(),
+            PixelAxis::Radial => {
+                let distances: Vec<(f64, f64)> = beam_positions
+                    .iter()
+                    .map(|(x, y)| {
+                        let axis_distance = |layout: &PixelAxisLayout, value| {
+                            if layout.is_flat() {
+                                (0.0, 0.0)
+                            } else {
+                                distance_from_center(layout.pixel_range(value))
+                            }
+                        };
+                        let (x_nearest, x_furthest) = axis_distance(&columns, *x);
+                        let (y_nearest, y_furthest) = axis_distance(&rows, *y);
 
+                        (
+                            f64::hypot(x_nearest, y_nearest),
+                            f64::hypot(x_furthest, y_furthest),
+                        )
+                    })
+                    .collect();
+
+                // Scale so the outermost beams reach 1.0
+                let max_distance = distances
+                    .iter()
+                    .map(|(_, furthest)| *furthest)
+                    .fold(0.0, f64::max);
+                if max_distance > 0.0 {
+                    distances
+                        .into_iter()
+                        .map(|(nearest, furthest)| {
+                            (nearest / max_distance, furthest / max_distance)
+                        })
+                        .collect()
+                } else {
+                    distances.into_iter().map(|_| (0.0, 1.0)).collect()
+                }
+            }
+        }
+    }
+    pub fn pixel_dimmers(&self, beam_positions: &[(f64, f64)]) -> Vec<f64> {
+        self.beam_ranges(beam_positions)
+            .into_iter()
+            .map(|(pixel_min, pixel_max)| {
                 f64::min(
                     self.ranges
                         .iter()
@@ -73,6 +196,48 @@ impl PixelRangeSet {
     }
 }
 
+/// How the beam dimmers of several active pixel effects combine, each layer applied over those
+/// activated before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelBlendMode {
+    // The brightest layer wins
+    #[default]
+    Max,
+    // Layers add up, to at most fully on
+    Add,
+    // Beams are only lit where every layer lights them
+    Multiply,
+    // Each layer is mixed half over the layers before it, so the newest leads
+    Crossfade,
+}
+impl PixelBlendMode {
+    fn blend(self, below: f64, above: f64) -> f64 {
+        let value = match self {
+            PixelBlendMode::Max => f64::max(below, above),
+            PixelBlendMode::Add => below + above,
+            PixelBlendMode::Multiply => below * above,
+            PixelBlendMode::Crossfade => (below + above) / 2.0,
+        };
+        value.clamp(0.0, 1.0)
+    }
+    /// Combines each layer's beam dimmers, in activation order. None without any layers
+    pub fn blend_layers(self, layers: impl IntoIterator<Item = Vec<f64>>) -> Option<Vec<f64>> {
+        layers
+            .into_iter()
+            .fold(None, |blended, layer| match blended {
+                Some(below) => Some(
+                    below
+                        .into_iter()
+                        .zip(layer)
+                        .map(|(below, above)| self.blend(below, above))
+                        .collect(),
+                ),
+                None => Some(layer),
+            })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PixelEffect {
     steps: Steps<PixelModulator>,
@@ -85,6 +250,13 @@ impl PixelEffect {
             clock_offset,
         }
     }
+    pub fn validate(&self) -> Result<(), EffectError> {
+        self.steps.validate()?;
+        if let Some(clock_offset) = self.clock_offset.as_ref() {
+            clock_offset.validate()?;
+        }
+        Ok(())
+    }
     pub fn pixel_range_set(&self, clock: &ClockSnapshot) -> PixelRangeSet {
         let (step, elapsed_percent) = self.steps.current_step(clock);
         step.pixel_range_set_for_elapsed_percent(elapsed_percent)
@@ -121,20 +293,14 @@ impl PixelModulator {
         let high = f64::min(x + 0.1, 1.0);
 
         match self.direction {
-            EffectDirection::BottomToTop => PixelRangeSet::new(&[(low, high)]),
-            EffectDirection::FromCenter => {
-                let low = low / 2.0;
-                let high = high / 2.0;
-
-                PixelRangeSet::new(&[(0.5 - low, 0.5 - high), (0.5 + low, 0.5 + high)])
+            EffectDirection::BottomToTop => PixelRangeSet::new(PixelAxis::Vertical, &[(low, high)]),
+            EffectDirection::LeftToRight => {
+                PixelRangeSet::new(PixelAxis::Horizontal, &[(low, high)])
             }
+            EffectDirection::FromCenter => PixelRangeSet::new(PixelAxis::Radial, &[(low, high)]),
             EffectDirection::ToCenter => {
-                let low = low / 2.0;
-                let high = high / 2.0;
-
-                PixelRangeSet::new(&[(low, high), (1.0 - low, 1.0 - high)])
+                PixelRangeSet::new(PixelAxis::Radial, &[(1.0 - high, 1.0 - low)])
             }
-            EffectDirection::LeftToRight => PixelRangeSet::new(&[(0.0, 1.0)]),
         }
     }
 }
@@ -143,3 +309,97 @@ impl Step for PixelModulator {
         self.meter_length
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layers() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 0.5, 0.0],
+            vec![0.0, 0.5, 0.0],
+            vec![0.0, 1.0, 1.0],
+        ]
+    }
+
+    #[test]
+    fn layers_blend_in_activation_order() {
+        let blend = |mode: PixelBlendMode| mode.blend_layers(layers()).unwrap();
+
+        assert_eq!(blend(PixelBlendMode::Max), vec![1.0, 1.0, 1.0]);
+        assert_eq!(blend(PixelBlendMode::Add), vec![1.0, 1.0, 1.0]);
+        assert_eq!(blend(PixelBlendMode::Multiply), vec![0.0, 0.25, 0.0]);
+        // The last layer activated makes up half of each beam
+        assert_eq!(blend(PixelBlendMode::Crossfade), vec![0.25, 0.75, 0.5]);
+    }
+
+    fn pixel_dimmers(direction: EffectDirection, beam_positions: &[(f64, f64)]) -> Vec<f64> {
+        PixelModulator::new(Waveform::SawUp, Beats::new(1.0), direction)
+            .pixel_range_set_for_elapsed_percent(0.0)
+            .pixel_dimmers(beam_positions)
+    }
+
+    fn ledbar() -> Vec<(f64, f64)> {
+        (0..4).map(|x| (x as f64, 0.0)).collect()
+    }
+
+    fn panel() -> Vec<(f64, f64)> {
+        (0..2)
+            .flat_map(|y| (0..2).map(move |x| (x as f64, y as f64)))
+            .collect()
+    }
+
+    #[test]
+    fn left_to_right_lights_every_beam_in_a_row() {
+        let hung_vertically: Vec<_> = ledbar().into_iter().map(|(x, y)| (y, x)).collect();
+
+        assert_eq!(
+            pixel_dimmers(EffectDirection::LeftToRight, &ledbar()),
+            vec![1.0; 4]
+        );
+        assert_eq!(
+            pixel_dimmers(EffectDirection::LeftToRight, &hung_vertically),
+            vec![1.0; 4]
+        );
+        assert_eq!(
+            pixel_dimmers(EffectDirection::LeftToRight, &[(0.0, 0.0)]),
+            vec![1.0]
+        );
+    }
+
+    #[test]
+    fn single_rows_sweep_along_their_length() {
+        let hung_vertically: Vec<_> = ledbar().into_iter().map(|(x, y)| (y, x)).collect();
+
+        assert_eq!(
+            pixel_dimmers(EffectDirection::BottomToTop, &ledbar()),
+            vec![1.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            pixel_dimmers(EffectDirection::BottomToTop, &hung_vertically),
+            vec![1.0, 0.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn grids_sweep_along_the_effect_s_axis() {
+        // Beams are listed left to right, bottom row first
+        assert_eq!(
+            pixel_dimmers(EffectDirection::LeftToRight, &panel()),
+            vec![1.0, 0.0, 1.0, 0.0]
+        );
+        assert_eq!(
+            pixel_dimmers(EffectDirection::BottomToTop, &panel()),
+            vec![1.0, 1.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn single_layers_are_unchanged() {
+        assert_eq!(
+            PixelBlendMode::Multiply.blend_layers(vec![vec![0.2, 0.8]]),
+            Some(vec![0.2, 0.8])
+        );
+        assert_eq!(PixelBlendMode::Max.blend_layers(vec![]), None);
+    }
+}
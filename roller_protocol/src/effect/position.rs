@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     clock::{offset::ClockOffset, Beats, ClockSnapshot},
-    effect::Waveform,
+    effect::{validate_meter_length, validate_value, EffectError, Waveform},
     position::Position,
 };
 
@@ -25,6 +25,19 @@ impl PositionEffect {
             clock_offset,
         }
     }
+    pub fn validate(&self) -> Result<(), EffectError> {
+        if self.pan.is_none() && self.tilt.is_none() {
+            return Err(EffectError::NoSteps);
+        }
+        for modulator in self.pan.iter().chain(self.tilt.iter()) {
+            validate_meter_length(modulator.meter_length)?;
+            validate_value("position range", modulator.range.into_inner())?;
+        }
+        if let Some(clock_offset) = self.clock_offset.as_ref() {
+            clock_offset.validate()?;
+        }
+        Ok(())
+    }
     pub fn position(&self, clock: &ClockSnapshot) -> Position {
         let pan = self.pan.as_ref().map(|pan| pan.axis(clock)).unwrap_or(0.0);
         let tilt = self
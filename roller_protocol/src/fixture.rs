@@ -1,16 +1,26 @@
 use derive_more::{Constructor, From, Into};
+use ordered_float::OrderedFloat;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 
-use crate::position::{degrees_to_percent, Position};
+use crate::{
+    color::{Color, ColorPalette},
+    position::{degrees_to_percent, Position},
+    utils::clamp,
+};
 
 #[derive(
     Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, From, Into, Serialize, Deserialize,
 )]
 pub struct FixtureId(uuid::Uuid);
 impl FixtureId {
-    fn new() -> FixtureId {
-        FixtureId(uuid::Uuid::new_v4())
+    // IDs are derived from a key identifying the fixture within its project, so
+    // the same fixture keeps its ID when the project is reloaded
+    pub fn from_key(key: &str) -> FixtureId {
+        FixtureId(uuid::Uuid::new_v5(
+            &uuid::Uuid::NAMESPACE_OID,
+            key.as_bytes(),
+        ))
     }
 }
 
@@ -30,10 +40,181 @@ impl FixtureId {
 )]
 pub struct FixtureGroupId(usize);
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Constructor, Serialize, Deserialize)]
+/// A named set of fixtures that can be controlled together. A fixture may be in
+/// any number of groups
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FixtureGroup {
+    pub id: FixtureGroupId,
+    pub name: String,
+    // Shown alongside the group wherever it is displayed
+    pub color: Option<Color>,
+    // IDs of fixtures in the order effects chase through the group. Fixtures listed
+    // here are in the group whether or not they list it themselves
+    #[serde(default)]
+    pub fixtures: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureMounting {
+    #[default]
+    Standing,
+    // Rigged upside down, eg. from a truss
+    Hanging,
+}
+
+/// Which way a fixture is rigged. Angles are in degrees, with a yaw of 0.0
+/// facing the audience, a positive pitch tilting the fixture upstage and a
+/// positive roll turning it anticlockwise as seen from the audience. Roll turns
+/// the layout of the fixture's beams, which pixel effects run across
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize)]
+pub struct FixtureOrientation {
+    #[serde(default)]
+    yaw: OrderedFloat<f64>,
+    #[serde(default)]
+    pitch: OrderedFloat<f64>,
+    #[serde(default)]
+    roll: OrderedFloat<f64>,
+    #[serde(default)]
+    pub mounting: FixtureMounting,
+}
+impl FixtureOrientation {
+    pub fn new(yaw: f64, pitch: f64, roll: f64, mounting: FixtureMounting) -> FixtureOrientation {
+        FixtureOrientation {
+            yaw: yaw.into(),
+            pitch: pitch.into(),
+            roll: roll.into(),
+            mounting,
+        }
+    }
+    pub fn yaw(&self) -> f64 {
+        self.yaw.into_inner()
+    }
+    pub fn pitch(&self) -> f64 {
+        self.pitch.into_inner()
+    }
+    pub fn roll(&self) -> f64 {
+        self.roll.into_inner()
+    }
+    pub fn is_hanging(&self) -> bool {
+        self.mounting == FixtureMounting::Hanging
+    }
+    // Where a beam ends up on the face of the fixture once it has been rolled
+    pub fn beam_position(&self, location: &FixtureBeamLocation) -> (f64, f64) {
+        let (sin, cos) = self.roll().to_radians().sin_cos();
+
+        (
+            location.x() * cos - location.y() * sin,
+            location.x() * sin + location.y() * cos,
+        )
+    }
+    // Converts a position relative to the room into one relative to the fixture's home position.
+    // Tilt is measured from the way the mounting points, and the fixture's yaw, pitch and roll
+    // are taken back out, so pitched or rolled fixtures still hit the same spot
+    pub fn fixture_position(&self, position: Position) -> Position {
+        let unrotated = Position::new(position.pan() - self.yaw(), position.tilt());
+        let position = if self.pitch() == 0.0 && self.roll() == 0.0 {
+            unrotated
+        } else {
+            // Undo the rig's rotations in reverse, yaw around the vertical, then pitch towards
+            // upstage, then roll as seen from the audience
+            let (x, y, z) = position_direction(position);
+            let (x, z) = rotate(x, z, -self.yaw());
+            let (y, z) = rotate(y, z, self.pitch());
+            let (x, y) = rotate(x, y, self.roll());
+
+            nearest_position((x, y, z), unrotated)
+        };
+
+        match self.mounting {
+            FixtureMounting::Standing => position,
+            FixtureMounting::Hanging => position.inverted_pan().inverted_tilt(),
+        }
+    }
+    // Unit vector of the direction the fixture points in its home position, as (x, y, z)
+    pub fn direction(&self) -> (f64, f64, f64) {
+        let up = match self.mounting {
+            FixtureMounting::Standing => 1.0,
+            FixtureMounting::Hanging => -1.0,
+        };
+        let (pitch, yaw) = (self.pitch().to_radians(), self.yaw().to_radians());
+
+        let y = up * pitch.cos();
+        let z = up * pitch.sin();
+
+        (z * yaw.sin(), y, z * yaw.cos())
+    }
+}
+
+// Turns (a, b) back by `degrees`, taking them off the angle measured from a towards b
+fn rotate(a: f64, b: f64, degrees: f64) -> (f64, f64) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    (a * cos + b * sin, b * cos - a * sin)
+}
+
+// Unit vector a position points along, with a tilt of 0.0 pointing along y and a pan of 0.0
+// tilting towards z
+fn position_direction(position: Position) -> (f64, f64, f64) {
+    let (pan, tilt) = (position.pan().to_radians(), position.tilt().to_radians());
+    (tilt.sin() * pan.sin(), tilt.cos(), tilt.sin() * pan.cos())
+}
+
+// The position pointing along `direction`. Every direction can be reached by more than one
+// pan and tilt, so whichever is closest to `near` is used, keeping movement continuous
+fn nearest_position((x, y, z): (f64, f64, f64), near: Position) -> Position {
+    let tilt = y.clamp(-1.0, 1.0).acos().to_degrees();
+    // Straight along the axis, any pan will do
+    if x.hypot(z) < 1e-9 {
+        return Position::new(near.pan(), tilt);
+    }
+    let pan = x.atan2(z).to_degrees();
+
+    let closest_turn = |pan: f64| pan + ((near.pan() - pan) / 360.0).round() * 360.0;
+    let flipped = Position::new(closest_turn(pan + 180.0), -tilt);
+    let position = Position::new(closest_turn(pan), tilt);
+
+    let distance = |position: &Position| {
+        (position.pan() - near.pan()).abs() + (position.tilt() - near.tilt()).abs()
+    };
+    if distance(&flipped) < distance(&position) {
+        flipped
+    } else {
+        position
+    }
+}
+
+/// Where a fixture is rigged, in metres. `x` runs left to right across the
+/// stage, `y` is the height above the floor and `z` runs from the front of
+/// the stage to the back
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Serialize, Deserialize)]
 pub struct FixtureLocation {
-    pub x: isize,
-    pub y: isize,
+    x: OrderedFloat<f64>,
+    y: OrderedFloat<f64>,
+    z: OrderedFloat<f64>,
+    pub orientation: FixtureOrientation,
+}
+impl FixtureLocation {
+    pub fn new(x: f64, y: f64, z: f64) -> FixtureLocation {
+        FixtureLocation {
+            x: x.into(),
+            y: y.into(),
+            z: z.into(),
+            orientation: FixtureOrientation::default(),
+        }
+    }
+    pub fn with_orientation(mut self, orientation: FixtureOrientation) -> FixtureLocation {
+        self.orientation = orientation;
+        self
+    }
+    pub fn x(&self) -> f64 {
+        self.x.into_inner()
+    }
+    pub fn y(&self) -> f64 {
+        self.y.into_inner()
+    }
+    pub fn z(&self) -> f64 {
+        self.z.into_inner()
+    }
 }
 
 #[derive(
@@ -105,16 +286,143 @@ impl FixtureProfileChannel {
     pub fn channel_index(&self) -> usize {
         self.channel - 1
     }
-    // value in range 0.0 - 1.0
+    // value in range 0.0 - 1.0, values outside it are clamped
     pub fn encode_value(&self, value: f64) -> u8 {
         let range = self.max_value - self.min_value;
 
-        self.min_value + (range as f64 * value) as u8
+        self.min_value + (range as f64 * clamp(value, 0.0, 1.0)) as u8
+    }
+}
+
+// Maps a logical dimmer value onto the value sent to the fixture, to even out
+// fixtures with different dimmer responses
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DimmerCurve {
+    #[default]
+    Linear,
+    // gammas above 1.0 spend more of the fader travel at the low end
+    Gamma(OrderedFloat<f64>),
+}
+impl DimmerCurve {
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            DimmerCurve::Linear => x,
+            DimmerCurve::Gamma(gamma) => f64::powf(x, gamma.into_inner()),
+        }
+    }
+}
+// Scales each of a fixture's color channels, in range 0.0 - 1.0
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ColorTrim {
+    #[serde(default = "ColorTrim::default_value")]
+    pub red: f64,
+    #[serde(default = "ColorTrim::default_value")]
+    pub green: f64,
+    #[serde(default = "ColorTrim::default_value")]
+    pub blue: f64,
+}
+impl ColorTrim {
+    const fn default_value() -> f64 {
+        1.0
+    }
+    pub fn is_valid(&self) -> bool {
+        [self.red, self.green, self.blue]
+            .iter()
+            .all(|value| (0.0..=1.0).contains(value))
+    }
+    pub fn apply(&self, (red, green, blue): (f64, f64, f64)) -> (f64, f64, f64) {
+        (
+            clamp(red * self.red, 0.0, 1.0),
+            clamp(green * self.green, 0.0, 1.0),
+            clamp(blue * self.blue, 0.0, 1.0),
+        )
+    }
+}
+
+/// A fixed look a fixture is held at, regardless of any active effects.
+/// The master dimmer is still applied so a blackout still works
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FixturePark {
+    #[serde(default = "FixturePark::default_dimmer")]
+    pub dimmer: f64,
+    #[serde(default = "FixturePark::default_color")]
+    pub color: Color,
+    pub position: Option<Position>,
+}
+impl FixturePark {
+    const fn default_dimmer() -> f64 {
+        1.0
+    }
+    const fn default_color() -> Color {
+        Color::White
+    }
+    pub fn fixture_state(
+        &self,
+        profile: &FixtureProfile,
+        master_dimmer: f64,
+        palette: &ColorPalette,
+    ) -> FixtureState {
+        let mut state = FixtureState::new(profile);
+
+        state.set_color(palette.hsl(&self.color));
+        if let Some(position) = self.position {
+            state.set_position(position);
+        }
+        state.set_dimmer(master_dimmer * self.dimmer);
+
+        state
+    }
+}
+
+/// Settings for a single patched fixture, layered on top of its profile
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct FixtureOverrides {
+    pub label: Option<String>,
+    // Upper limit on the fixture's output dimmer, in range 0.0 - 1.0
+    pub max_dimmer: Option<f64>,
+    pub dimmer_curve: Option<DimmerCurve>,
+    // degrees, added to whatever position the fixture is rendered at
+    #[serde(default)]
+    pub pan_offset: f64,
+    #[serde(default)]
+    pub tilt_offset: f64,
+    pub color_trim: Option<ColorTrim>,
+    pub park: Option<FixturePark>,
+}
+
+/// Where a beam sits on the face of its fixture, relative to the other beams.
+/// Units are arbitrary, so a 5x5 panel can simply use 0.0 to 4.0 on each axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct FixtureBeamLocation {
+    x: OrderedFloat<f64>,
+    #[serde(default)]
+    y: OrderedFloat<f64>,
+    #[serde(default)]
+    z: OrderedFloat<f64>,
+}
+impl FixtureBeamLocation {
+    pub fn new(x: f64, y: f64, z: f64) -> FixtureBeamLocation {
+        FixtureBeamLocation {
+            x: x.into(),
+            y: y.into(),
+            z: z.into(),
+        }
+    }
+    pub fn x(&self) -> f64 {
+        self.x.into_inner()
+    }
+    pub fn y(&self) -> f64 {
+        self.y.into_inner()
+    }
+    pub fn z(&self) -> f64 {
+        self.z.into_inner()
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct FixtureBeamProfile {
+    pub location: FixtureBeamLocation,
     pub dimmer_channel: Option<FixtureProfileChannel>,
     pub red_channel: Option<FixtureProfileChannel>,
     pub green_channel: Option<FixtureProfileChannel>,
@@ -156,6 +464,7 @@ pub struct FixtureProfile {
     pub label: String,
     pub channel_count: usize,
     pub supported_effects: Vec<FixtureEffectType>,
+    pub dimmer_curve: DimmerCurve,
 
     pub beams: Vec<FixtureBeamProfile>,
     pub dimmer_channel: Option<FixtureProfileChannel>,
@@ -208,11 +517,49 @@ pub struct FixtureParams {
     pub profile: FixtureProfile,
     pub universe: usize,
     pub start_channel: usize,
-    pub group_id: Option<FixtureGroupId>,
+    // Ordered by precedence, where the last group's state wins if groups disagree
+    pub group_ids: Vec<FixtureGroupId>,
+    // Position in the fixture order of each group that lists the fixture
+    pub group_orders: FxHashMap<FixtureGroupId, usize>,
     pub location: Option<FixtureLocation>,
     pub enabled_effects: Vec<FixtureEffectType>,
+    pub overrides: FixtureOverrides,
 }
 impl FixtureParams {
+    pub fn label(&self) -> &str {
+        self.overrides
+            .label
+            .as_deref()
+            .unwrap_or(&self.profile.label)
+    }
+    pub fn park(&self) -> Option<&FixturePark> {
+        self.overrides.park.as_ref()
+    }
+    pub fn dimmer_curve(&self) -> DimmerCurve {
+        self.overrides
+            .dimmer_curve
+            .unwrap_or(self.profile.dimmer_curve)
+    }
+    // Converts a rendered dimmer value into the value that is actually output
+    pub fn output_dimmer(&self, dimmer: f64) -> f64 {
+        let max_dimmer = clamp(self.overrides.max_dimmer.unwrap_or(1.0), 0.0, 1.0);
+
+        self.dimmer_curve().apply(clamp(dimmer, 0.0, 1.0)) * max_dimmer
+    }
+    // Beam positions as seen from the audience, in the same order as the profile's beams
+    pub fn beam_positions(&self) -> Vec<(f64, f64)> {
+        let orientation = self
+            .location
+            .as_ref()
+            .map(|location| location.orientation)
+            .unwrap_or_default();
+
+        self.profile
+            .beams
+            .iter()
+            .map(|beam| orientation.beam_position(&beam.location))
+            .collect()
+    }
     fn enabled_effects(&self) -> impl Iterator<Item = FixtureEffectType> + '_ {
         self.profile
             .supported_effects
@@ -309,26 +656,34 @@ pub struct Fixture {
 }
 impl Fixture {
     pub fn new(
+        id: FixtureId,
         profile: FixtureProfile,
         universe: usize,
         start_channel: usize,
-        group_id: Option<FixtureGroupId>,
+        group_ids: Vec<FixtureGroupId>,
         location: Option<FixtureLocation>,
         enabled_effects: Vec<FixtureEffectType>,
+        overrides: FixtureOverrides,
     ) -> Fixture {
         Fixture {
             state: FixtureState::new(&profile),
             params: FixtureParams {
-                id: FixtureId::new(),
+                id,
                 profile,
                 universe,
                 start_channel,
-                group_id,
+                group_ids,
+                group_orders: FxHashMap::default(),
                 location,
                 enabled_effects,
+                overrides,
             },
         }
     }
+    pub fn with_group_orders(mut self, group_orders: FxHashMap<FixtureGroupId, usize>) -> Fixture {
+        self.params.group_orders = group_orders;
+        self
+    }
     pub fn id(&self) -> &FixtureId {
         &self.params.id
     }
@@ -336,7 +691,8 @@ impl Fixture {
         let mut dmx: Vec<u8> = vec![0; self.params.profile.channel_count];
 
         if let Some(dimmer_channel) = &self.params.profile.dimmer_channel {
-            dmx[dimmer_channel.channel_index()] = dimmer_channel.encode_value(self.state.dimmer)
+            dmx[dimmer_channel.channel_index()] =
+                dimmer_channel.encode_value(self.params.output_dimmer(self.state.dimmer))
         }
 
         let beam_profiles = self
@@ -351,7 +707,8 @@ impl Fixture {
             let beam_dimmer = if self.params.profile.dimmer_channel.is_some() {
                 beam_state.dimmer
             } else {
-                beam_state.dimmer * self.state.dimmer
+                self.params
+                    .output_dimmer(beam_state.dimmer * self.state.dimmer)
             };
 
             if let Some(channel) = &beam_profile.dimmer_channel {
@@ -370,6 +727,14 @@ impl Fixture {
                     blue = 0.49;
                 }
 
+                if let Some(color_trim) = self.params.overrides.color_trim.as_ref() {
+                    let (trimmed_red, trimmed_green, trimmed_blue) =
+                        color_trim.apply((red, green, blue));
+                    red = trimmed_red;
+                    green = trimmed_green;
+                    blue = trimmed_blue;
+                }
+
                 // If light doesn't have dimmer control, scale the color values instead
                 if !beam_profile.is_dimmable() {
                     red *= beam_dimmer;
@@ -404,8 +769,15 @@ impl Fixture {
             const PAN_RANGE: f64 = 540.0;
             const TILT_RANGE: f64 = 180.0;
 
-            let pan_value = degrees_to_percent(position.pan(), PAN_RANGE);
-            let tilt_value = degrees_to_percent(position.tilt(), TILT_RANGE);
+            let position = match self.params.location.as_ref() {
+                Some(location) => location.orientation.fixture_position(position),
+                None => position,
+            };
+
+            let overrides = &self.params.overrides;
+            let pan_value = degrees_to_percent(position.pan() + overrides.pan_offset, PAN_RANGE);
+            let tilt_value =
+                degrees_to_percent(position.tilt() + overrides.tilt_offset, TILT_RANGE);
 
             dmx[pan_channel.channel_index()] = pan_channel.encode_value(pan_value);
             dmx[tilt_channel.channel_index()] = tilt_channel.encode_value(tilt_value);
@@ -436,3 +808,67 @@ pub fn fold_fixture_dmx_data<'a>(
 
     universe_dmx_data
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_position_eq(a: Position, b: Position) {
+        assert!(
+            (a.pan() - b.pan()).abs() < 1e-6 && (a.tilt() - b.tilt()).abs() < 1e-6,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn fixture_positions_take_out_pitch_and_roll() {
+        let orientation = |pitch: f64, roll: f64| {
+            FixtureOrientation::new(20.0, pitch, roll, FixtureMounting::Standing)
+        };
+
+        // Without pitch or roll, only the yaw is taken off the pan
+        assert_position_eq(
+            orientation(0.0, 0.0).fixture_position(Position::new(50.0, -40.0)),
+            Position::new(30.0, -40.0),
+        );
+
+        // A fixture pitched upstage is already tilted that way
+        let pitched = orientation(30.0, 0.0);
+        assert_position_eq(
+            pitched.fixture_position(Position::new(20.0, 30.0)),
+            Position::new(0.0, 0.0),
+        );
+        assert_position_eq(
+            pitched.fixture_position(Position::new(20.0, 0.0)),
+            Position::new(0.0, -30.0),
+        );
+        assert_position_eq(
+            pitched.fixture_position(Position::new(20.0, 70.0)),
+            Position::new(0.0, 40.0),
+        );
+
+        // Rolled a quarter turn, pointing straight up is a full tilt to the side
+        let rolled = FixtureOrientation::new(0.0, 0.0, 90.0, FixtureMounting::Standing);
+        assert_position_eq(
+            rolled.fixture_position(Position::new(0.0, 0.0)),
+            Position::new(90.0, 90.0),
+        );
+        // And pointing back along the fixture's own axis is its home position
+        assert_position_eq(
+            rolled.fixture_position(Position::new(-90.0, 90.0)),
+            Position::new(-90.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn hanging_fixtures_are_mirrored_after_pitch() {
+        let hanging = FixtureOrientation::new(0.0, 30.0, 0.0, FixtureMounting::Hanging);
+
+        assert_position_eq(
+            hanging.fixture_position(Position::new(0.0, 50.0)),
+            Position::new(0.0, -20.0),
+        );
+    }
+}
@@ -22,6 +22,8 @@ pub enum Message {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ClientMessage {
     Input(InputEvent),
+    SaveShow(String),
+    LoadShow(String),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -30,5 +32,9 @@ pub enum ServerMessage {
     ButtonLabelsUpdated(Vec<(ButtonGridLocation, ButtonCoordinate, String)>),
     ClockUpdated(clock::Clock),
     FixtureParamsUpdated(Vec<(FixtureId, FixtureParams)>),
+    FixtureParamsRemoved(Vec<FixtureId>),
     FixtureGroupStatesUpdated(Vec<(Option<FixtureGroupId>, FixtureGroupState)>),
+    PaletteUpdated(color::ColorPalette),
+    // Name of the source driving the clock, `None` when it's tapped
+    ClockSourceChanged(Option<String>),
 }
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     clock::Rate,
     color::Color,
-    effect::{ColorEffect, DimmerEffect, PixelEffect, PositionEffect},
+    effect::{ColorEffect, DimmerEffect, PixelBlendMode, PixelEffect, PositionEffect},
     position::BasePosition,
     utils::FxIndexMap,
 };
@@ -13,6 +13,7 @@ pub struct FixtureGroupState {
     pub dimmer: f64,
     pub dimmer_effect_intensity: Option<f64>,
     pub color_effect_intensity: Option<f64>,
+    pub pixel_blend_mode: Option<PixelBlendMode>,
     pub clock_rate: Rate,
     pub global_color: Option<Color>,
     pub secondary_color: Option<Color>,
@@ -23,13 +24,16 @@ pub struct FixtureGroupState {
     pub active_position_effects: FxIndexMap<PositionEffect, Rate>,
 }
 impl FixtureGroupState {
+    // Fills in anything `self` hasn't set from `other`. Dimmers and clock rates
+    // are scaled by `other`, and active effects from both states are combined
     pub fn merge(mut self, other: &FixtureGroupState) -> FixtureGroupState {
+        self.dimmer = self.dimmer * other.dimmer;
         self.clock_rate = self.clock_rate * other.clock_rate;
         if self.global_color == None {
-            self.global_color = other.global_color;
+            self.global_color = other.global_color.clone();
         }
         if self.secondary_color == None {
-            self.secondary_color = other.secondary_color;
+            self.secondary_color = other.secondary_color.clone();
         }
         if self.base_position == None {
             self.base_position = other.base_position;
@@ -40,6 +44,9 @@ impl FixtureGroupState {
         if self.color_effect_intensity == None {
             self.color_effect_intensity = other.color_effect_intensity;
         }
+        if self.pixel_blend_mode == None {
+            self.pixel_blend_mode = other.pixel_blend_mode;
+        }
         self.active_dimmer_effects
             .extend(other.active_dimmer_effects.clone().into_iter());
         self.active_color_effects
@@ -52,7 +59,7 @@ impl FixtureGroupState {
         self
     }
     pub fn global_color(&self) -> Color {
-        self.global_color.unwrap_or(Color::Violet)
+        self.global_color.clone().unwrap_or(Color::Violet)
     }
     pub fn base_position(&self) -> BasePosition {
         self.base_position.unwrap_or_default()
@@ -63,4 +70,7 @@ impl FixtureGroupState {
     pub fn color_effect_intensity(&self) -> f64 {
         self.color_effect_intensity.unwrap_or(1.0)
     }
+    pub fn pixel_blend_mode(&self) -> PixelBlendMode {
+        self.pixel_blend_mode.unwrap_or_default()
+    }
 }
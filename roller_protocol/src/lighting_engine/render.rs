@@ -1,16 +1,62 @@
+use std::borrow::Cow;
+
 use crate::{
     clock::{offset::offsetted_for_fixture, ClockSnapshot},
-    color::Color,
+    color::ColorPalette,
     effect,
     fixture::{FixtureGroupId, FixtureParams, FixtureState},
     lighting_engine::FixtureGroupState,
 };
 
+/// Combines the states of every group a fixture is in, on top of the base state.
+///
+/// Groups are applied in the fixture's precedence order, so for colors, base
+/// positions, effect intensities and the pixel blend mode the last group to set a
+/// value wins, falling back to the base state. Dimmers and clock rates are multiplied
+/// together, and the effects active in every group are all applied
+fn fixture_group_state<'a>(
+    params: &FixtureParams,
+    fixture_group_states: &[(&FixtureGroupId, &FixtureGroupState)],
+    base_state: &'a FixtureGroupState,
+) -> Cow<'a, FixtureGroupState> {
+    let mut group_states = params.group_ids.iter().rev().filter_map(|group_id| {
+        fixture_group_states
+            .iter()
+            .find(|(id, _)| group_id == *id)
+            .map(|(_, state)| *state)
+    });
+
+    match group_states.next() {
+        Some(group_state) => Cow::Owned(
+            group_states
+                .fold(group_state.clone(), |merged, state| merged.merge(state))
+                .merge(base_state),
+        ),
+        None => Cow::Borrowed(base_state),
+    }
+}
+
+// The group an effect is running in for a fixture, the last of the fixture's groups to have it
+// active, or `None` when it's only active in the base state
+fn effect_group_id(
+    params: &FixtureParams,
+    fixture_group_states: &[(&FixtureGroupId, &FixtureGroupState)],
+    is_active: impl Fn(&FixtureGroupState) -> bool,
+) -> Option<FixtureGroupId> {
+    params.group_ids.iter().rev().copied().find(|group_id| {
+        fixture_group_states
+            .iter()
+            .any(|(id, state)| group_id == *id && is_active(state))
+    })
+}
+
 pub struct FixtureStateRenderContext<'a> {
     pub base_state: &'a FixtureGroupState,
     pub fixture_group_states: &'a [(&'a FixtureGroupId, &'a FixtureGroupState)],
     pub clock_snapshot: ClockSnapshot,
     pub master_dimmer: f64,
+    // Palette entries in group colors are looked up in this palette
+    pub palette: &'a ColorPalette,
 }
 pub fn render_fixture_states<'a>(
     ctx: FixtureStateRenderContext<'_>,
@@ -21,22 +67,24 @@ pub fn render_fixture_states<'a>(
         clock_snapshot,
         base_state,
         fixture_group_states,
+        palette,
     } = ctx;
 
     fixture_params
         .iter()
         .map(|params| {
+            // Parked fixtures hold their look, ignoring any groups or effects
+            if let Some(park) = params.park() {
+                return (
+                    *params,
+                    park.fixture_state(&params.profile, master_dimmer, palette),
+                );
+            }
+
             let mut state = FixtureState::new(&params.profile);
 
-            let group_state = params
-                .group_id
-                .and_then(|group_id| {
-                    fixture_group_states
-                        .iter()
-                        .find(|(id, _)| &group_id == *id)
-                        .map(|(_, state)| *state)
-                })
-                .unwrap_or(base_state);
+            let group_state = fixture_group_state(params, fixture_group_states, base_state);
+            let group_state = group_state.as_ref();
 
             let clock_snapshot = clock_snapshot.with_rate(group_state.clock_rate);
 
@@ -52,6 +100,9 @@ pub fn render_fixture_states<'a>(
                                     &clock_snapshot.with_rate(*rate),
                                     &params,
                                     &fixture_params,
+                                    effect_group_id(params, fixture_group_states, |state| {
+                                        state.active_dimmer_effects.contains_key(effect)
+                                    }),
                                 )),
                                 group_state.dimmer_effect_intensity(),
                             )
@@ -60,8 +111,11 @@ pub fn render_fixture_states<'a>(
                 1.0
             };
 
-            let base_color = group_state.global_color().to_hsl();
-            let secondary_color = group_state.secondary_color.map(Color::to_hsl);
+            let base_color = palette.hsl(&group_state.global_color());
+            let secondary_color = group_state
+                .secondary_color
+                .as_ref()
+                .map(|color| palette.hsl(color));
 
             let color = if params.color_effects_enabled() {
                 effect::color_intensity(
@@ -77,6 +131,9 @@ pub fn render_fixture_states<'a>(
                                     &clock_snapshot.with_rate(*rate),
                                     &params,
                                     &fixture_params,
+                                    effect_group_id(params, fixture_group_states, |state| {
+                                        state.active_color_effects.contains_key(effect)
+                                    }),
                                 ),
                             )
                         },
@@ -88,31 +145,34 @@ pub fn render_fixture_states<'a>(
             };
             state.set_color(color);
 
-            if params.pixel_effects_enabled() {
-                // TODO only using first active pixel effect
-                let pixel_range =
+            if params.pixel_effects_enabled() && params.profile.beam_count() > 1 {
+                let beam_positions = params.beam_positions();
+                // Blended per beam, as layers may run along different axes. The result is a
+                // level for each beam, which the fixture's dimmer, dimmer effects included, scales
+                let beam_dimmers = group_state.pixel_blend_mode().blend_layers(
                     group_state
                         .active_pixel_effects
                         .iter()
-                        .nth(0)
                         .map(|(effect, rate)| {
-                            effect.pixel_range_set(&offsetted_for_fixture(
-                                effect.clock_offset.as_ref(),
-                                &clock_snapshot.with_rate(*rate),
-                                &params,
-                                &fixture_params,
-                            ))
-                        });
-
-                if params.profile.beam_count() > 1 {
-                    if let Some(pixel_range) = pixel_range {
-                        state.set_beam_dimmers(
-                            &pixel_range.pixel_dimmers(params.profile.beam_count()),
-                        )
-                    } else {
-                        // If there's no active pixel effect, reset pixels
-                        state.set_all_beam_dimmers(1.0);
-                    }
+                            effect
+                                .pixel_range_set(&offsetted_for_fixture(
+                                    effect.clock_offset.as_ref(),
+                                    &clock_snapshot.with_rate(*rate),
+                                    &params,
+                                    &fixture_params,
+                                    effect_group_id(params, fixture_group_states, |state| {
+                                        state.active_pixel_effects.contains_key(effect)
+                                    }),
+                                ))
+                                .pixel_dimmers(&beam_positions)
+                        }),
+                );
+
+                if let Some(beam_dimmers) = beam_dimmers {
+                    state.set_beam_dimmers(&beam_dimmers)
+                } else {
+                    // If there's no active pixel effect, reset pixels
+                    state.set_all_beam_dimmers(1.0);
                 }
             }
 
@@ -126,6 +186,9 @@ pub fn render_fixture_states<'a>(
                             &clock_snapshot.with_rate(*rate),
                             &params,
                             &fixture_params,
+                            effect_group_id(params, fixture_group_states, |state| {
+                                state.active_position_effects.contains_key(effect)
+                            }),
                         ))
                     })
                     .fold(
@@ -26,6 +26,10 @@ impl Position {
         self.pan = OrderedFloat::from(-*self.pan);
         self
     }
+    pub fn inverted_tilt(mut self) -> Position {
+        self.tilt = OrderedFloat::from(-*self.tilt);
+        self
+    }
 }
 impl Default for Position {
     fn default() -> Position {
@@ -70,24 +74,26 @@ impl BasePosition {
         BasePosition { position, mode }
     }
     pub fn for_fixture(&self, fixture: &FixtureParams, fixtures: &[&FixtureParams]) -> Position {
-        // Hackily find the index of this moving fixture and use that for mirroring.
-        // Ultimately we need a `location` attribute on a fixture
-        let moving_fixtures = fixtures
-            .iter()
-            .filter(|fixture| fixture.profile.is_positionable());
-        let fixture_i = moving_fixtures
-            .enumerate()
-            .find(|(_, f)| **f == fixture)
-            .map(|(i, _)| i)
-            .unwrap_or(0);
-
         match self.mode {
             BasePositionMode::Default => self.position,
             BasePositionMode::MirrorPan => {
-                if fixture_i % 2 == 0 {
-                    self.position
-                } else {
-                    self.position.inverted_pan()
+                // Mirror around the centre line of the moving fixtures in the rig.
+                // Fixtures on the left hand side have their pan inverted
+                let (min_x, max_x) = fixtures
+                    .iter()
+                    .filter(|fixture| fixture.profile.is_positionable())
+                    .filter_map(|fixture| fixture.location.as_ref())
+                    .fold(
+                        (f64::INFINITY, f64::NEG_INFINITY),
+                        |(min_x, max_x), location| {
+                            (f64::min(min_x, location.x()), f64::max(max_x, location.x()))
+                        },
+                    );
+                let center_x = (min_x + max_x) / 2.0;
+
+                match fixture.location.as_ref() {
+                    Some(location) if location.x() < center_x => self.position.inverted_pan(),
+                    _ => self.position,
                 }
             }
         }
@@ -0,0 +1,73 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// The files served by the web UI, relative to the web_ui directory
+const ASSET_PATHS: &[&str] = &[
+    "index.html",
+    "app.css",
+    "normalize.css",
+    "babylon.js",
+    "pkg",
+    "assets",
+];
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let mut entries: Vec<_> = fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+
+        for entry in entries {
+            collect_files(&entry, files);
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+}
+
+fn main() {
+    if std::env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_none() {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let web_ui_dir = manifest_dir.join("..").join("web_ui");
+
+    let mut files = vec![];
+    for asset_path in ASSET_PATHS {
+        let path = web_ui_dir.join(asset_path);
+        if !path.exists() {
+            panic!(
+                "{} is missing, build web_ui before embedding its assets",
+                path.display()
+            );
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+        collect_files(&path, &mut files);
+    }
+
+    let out_path = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("embedded_assets.rs");
+    let mut out = fs::File::create(out_path).unwrap();
+
+    writeln!(out, "pub static ASSETS: &[(&str, &[u8])] = &[").unwrap();
+    for file in files {
+        let asset_path = file.strip_prefix(&web_ui_dir).unwrap();
+        let asset_path = asset_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        writeln!(
+            out,
+            "    ({:?}, include_bytes!({:?})),",
+            asset_path,
+            file.canonicalize().unwrap()
+        )
+        .unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
@@ -2,14 +2,19 @@ use async_std::sync::{Mutex, Receiver, Sender};
 use broadcaster::BroadcastChannel;
 use futures::prelude::*;
 use rustc_hash::FxHashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use thiserror::Error;
 use warp::{
+    filters::{path::Tail, BoxedFilter},
     ws::{self, WebSocket, Ws},
-    Filter,
+    Filter, Rejection, Reply,
 };
 
 use roller_protocol::{
-    control::{ButtonCoordinate, ButtonGridLocation, ButtonState, InputEvent},
+    color::ColorPalette,
+    control::{ButtonCoordinate, ButtonGridLocation, ButtonState},
     fixture::{FixtureGroupId, FixtureId, FixtureParams},
     lighting_engine::FixtureGroupState,
     ClientMessage, ServerMessage,
@@ -23,8 +28,10 @@ async fn browser_session(
         FixtureGroupState,
         FxHashMap<FixtureGroupId, FixtureGroupState>,
     ),
+    palette: ColorPalette,
+    clock_source: Option<String>,
     server_message_recv: impl Stream<Item = ServerMessage> + Unpin,
-    event_sender: Sender<InputEvent>,
+    client_message_sender: Sender<ClientMessage>,
 ) {
     let (mut tx, rx) = websocket.split();
 
@@ -43,6 +50,8 @@ async fn browser_session(
                 .collect(),
         ),
         ServerMessage::FixtureParamsUpdated(fixture_params.into_iter().collect()),
+        ServerMessage::PaletteUpdated(palette),
+        ServerMessage::ClockSourceChanged(clock_source),
         ServerMessage::FixtureGroupStatesUpdated({
             let (base_state, group_states) = initial_fixture_group_states;
 
@@ -104,23 +113,127 @@ async fn browser_session(
 
                 println!("{:?}", msg);
 
-                match msg {
-                    ClientMessage::Input(input_event) => {
-                        event_sender.send(input_event).await;
-                    }
-                };
+                client_message_sender.send(msg).await;
             }
         }
     }
 }
 
+#[derive(Debug, Error)]
+pub enum WebServerError {
+    #[error("unable to start the web server runtime: {0}")]
+    Runtime(std::io::Error),
+    #[error("unable to listen on {0}: {1}")]
+    Bind(SocketAddr, warp::Error),
+    #[error("the web server stopped before it started listening")]
+    Stopped,
+    #[error("roller was built without embedded web assets")]
+    NoEmbeddedAssets,
+}
+
+/// Where the web UI's html, js and css are served from
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebAssets {
+    Directory(PathBuf),
+    // Built into the binary with the `embed-assets` feature
+    Embedded,
+}
+impl WebAssets {
+    pub fn embedded_available() -> bool {
+        cfg!(feature = "embed-assets")
+    }
+}
+impl Default for WebAssets {
+    fn default() -> WebAssets {
+        if WebAssets::embedded_available() {
+            WebAssets::Embedded
+        } else {
+            WebAssets::Directory(PathBuf::from("web_ui"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebServerConfig {
+    pub address: SocketAddr,
+    pub assets: WebAssets,
+}
+impl Default for WebServerConfig {
+    fn default() -> WebServerConfig {
+        WebServerConfig {
+            address: SocketAddr::from(([0, 0, 0, 0], 8888)),
+            assets: WebAssets::default(),
+        }
+    }
+}
+
+#[cfg(feature = "embed-assets")]
+mod embedded_assets {
+    include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+    pub fn find(path: &str) -> Option<&'static [u8]> {
+        ASSETS
+            .iter()
+            .find(|(asset_path, _)| *asset_path == path)
+            .map(|(_, contents)| *contents)
+    }
+}
+
+#[cfg(not(feature = "embed-assets"))]
+mod embedded_assets {
+    pub fn find(_path: &str) -> Option<&'static [u8]> {
+        None
+    }
+}
+
+fn asset_routes(assets: WebAssets) -> BoxedFilter<(Box<dyn Reply>,)> {
+    match assets {
+        WebAssets::Directory(path) => {
+            let index = warp::path::end().and(warp::fs::file(path.join("index.html")));
+            let assets = warp::fs::dir(path);
+
+            warp::get()
+                .and(index.or(assets).unify())
+                .map(|file| Box::new(file) as Box<dyn Reply>)
+                .boxed()
+        }
+        WebAssets::Embedded => warp::get()
+            .and(warp::path::tail())
+            .and_then(|tail: Tail| async move {
+                let path = match tail.as_str() {
+                    "" => "index.html",
+                    path => path,
+                };
+                let contents = embedded_assets::find(path).ok_or_else(warp::reject::not_found)?;
+                let content_type = mime_guess::from_path(path).first_or_octet_stream();
+
+                Ok::<_, Rejection>(Box::new(warp::reply::with_header(
+                    contents,
+                    "content-type",
+                    content_type.to_string(),
+                )) as Box<dyn Reply>)
+            })
+            .boxed(),
+    }
+}
+
+/// Starts the web UI on its own thread, returning the address it's listening on
 pub fn serve_frontend(
+    config: WebServerConfig,
     initial_button_states: FxHashMap<(ButtonGridLocation, ButtonCoordinate), (String, ButtonState)>,
     fixture_params: FxHashMap<FixtureId, FixtureParams>,
     mut server_message_recv: Receiver<ServerMessage>,
-    event_sender: Sender<InputEvent>,
-) {
+    client_message_sender: Sender<ClientMessage>,
+) -> Result<SocketAddr, WebServerError> {
+    let WebServerConfig { address, assets } = config;
+    if assets == WebAssets::Embedded && !WebAssets::embedded_available() {
+        return Err(WebServerError::NoEmbeddedAssets);
+    }
+
     let initial_button_states = Arc::new(Mutex::new(initial_button_states));
+    let fixture_params = Arc::new(Mutex::new(fixture_params));
+    let palette = Arc::new(Mutex::new(ColorPalette::default()));
+    let clock_source = Arc::new(Mutex::new(None));
     let initial_fixture_group_states = Arc::new(Mutex::new((
         FixtureGroupState::default(),
         FxHashMap::default(),
@@ -130,6 +243,9 @@ pub fn serve_frontend(
     // Update initial button states with incoming messages
     let initial_button_states2 = initial_button_states.clone();
     let initial_fixture_group_states2 = initial_fixture_group_states.clone();
+    let fixture_params2 = fixture_params.clone();
+    let palette2 = palette.clone();
+    let clock_source2 = clock_source.clone();
     let (mut server_message_sender, _) = server_message_channel.clone().split();
     async_std::task::spawn(async move {
         while let Some(server_message) = server_message_recv.next().await {
@@ -153,10 +269,28 @@ pub fn serve_frontend(
                         }
                     }
                 }
+                ServerMessage::FixtureParamsUpdated(updates) => {
+                    let mut fixture_params = fixture_params2.lock().await;
+                    for (id, params) in updates.clone() {
+                        fixture_params.insert(id, params);
+                    }
+                }
+                ServerMessage::PaletteUpdated(updated_palette) => {
+                    *palette2.lock().await = updated_palette.clone();
+                }
+                ServerMessage::ClockSourceChanged(updated_clock_source) => {
+                    *clock_source2.lock().await = updated_clock_source.clone();
+                }
+                ServerMessage::FixtureParamsRemoved(ids) => {
+                    let mut fixture_params = fixture_params2.lock().await;
+                    for id in ids {
+                        fixture_params.remove(id);
+                    }
+                }
                 _ => {}
             }
             match server_message_sender.send(server_message).await {
-                Ok(()) => {},
+                Ok(()) => {}
                 Err(_) => {
                     dbg!("unable to broadcast server message");
                     return;
@@ -165,15 +299,14 @@ pub fn serve_frontend(
         }
     });
 
-    let index = warp::get().and(warp::fs::file("web_ui/index.html"));
-    let assets = warp::get().and(warp::fs::dir("web_ui"));
-
     let websocket = warp::get()
         .and(warp::path("ws"))
         .and(warp::ws())
         .map(move |ws: Ws| {
-            let fixture_params = fixture_params.clone();
-            let event_sender = event_sender.clone();
+            let fixture_params = async_std::task::block_on(fixture_params.lock()).clone();
+            let palette = async_std::task::block_on(palette.lock()).clone();
+            let clock_source = async_std::task::block_on(clock_source.lock()).clone();
+            let client_message_sender = client_message_sender.clone();
             let initial_button_states =
                 async_std::task::block_on(initial_button_states.lock()).clone();
             let initial_fixture_group_states =
@@ -186,19 +319,36 @@ pub fn serve_frontend(
                     fixture_params,
                     initial_button_states,
                     initial_fixture_group_states,
+                    palette,
+                    clock_source,
                     server_message_recv,
-                    event_sender,
+                    client_message_sender,
                 )
             })
         });
 
-    let app = warp::path::end().and(index).or(websocket).or(assets);
+    let app = websocket.or(asset_routes(assets));
 
-    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let mut rt = tokio::runtime::Runtime::new().map_err(WebServerError::Runtime)?;
+    let (bind_result_send, bind_result_recv) = std::sync::mpsc::channel();
 
+    // Warp runs on its own tokio runtime, binding has to happen inside it, so the result is sent
+    // back before the server starts handling connections
     std::thread::spawn(move || {
-        rt.block_on(async {
-            warp::serve(app).bind(([0, 0, 0, 0], 8888)).await;
+        rt.block_on(async move {
+            match warp::serve(app).try_bind_ephemeral(address) {
+                Ok((bound_address, server)) => {
+                    let _ = bind_result_send.send(Ok(bound_address));
+                    server.await;
+                }
+                Err(err) => {
+                    let _ = bind_result_send.send(Err(WebServerError::Bind(address, err)));
+                }
+            }
         });
     });
+
+    bind_result_recv
+        .recv()
+        .unwrap_or(Err(WebServerError::Stopped))
 }
@@ -17,6 +17,7 @@ use crate::{
 };
 use roller_protocol::{
     clock::Clock,
+    color::ColorPalette,
     control::{ButtonCoordinate, ButtonGridLocation, ButtonState, FaderId, InputEvent},
     fixture::{FixtureGroupId, FixtureId, FixtureParams, FixtureState},
     lighting_engine::FixtureGroupState,
@@ -53,6 +54,12 @@ impl PageType {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShowAction {
+    Save,
+    Load,
+}
+
 pub struct App {
     link: ComponentLink<Self>,
     websocket: WebSocketTask,
@@ -61,8 +68,10 @@ pub struct App {
     fixture_params: HashMap<FixtureId, FixtureParams>,
     base_fixture_group_state: Rc<FixtureGroupState>,
     fixture_group_states: HashMap<FixtureGroupId, FixtureGroupState>,
+    palette: Rc<ColorPalette>,
     active_page: PageType,
     clock: Rc<Clock>,
+    clock_source: Option<String>,
 }
 
 impl App {
@@ -79,6 +88,7 @@ pub enum AppMsg {
     FaderValueUpdated(FaderId, FaderValue),
     ServerMessage(ServerMessage),
     ActivePageUpdated(PageType),
+    ShowActionTriggered(ShowAction),
     NoOp,
 }
 
@@ -143,8 +153,10 @@ impl Component for App {
             fixture_params: HashMap::new(),
             base_fixture_group_state: Rc::new(FixtureGroupState::default()),
             fixture_group_states: HashMap::new(),
+            palette: Rc::new(ColorPalette::default()),
             active_page: PageType::Buttons,
             clock: Rc::new(Clock::new(130.0)),
+            clock_source: None,
         }
     }
 
@@ -172,6 +184,9 @@ impl Component for App {
             AppMsg::ServerMessage(ServerMessage::ClockUpdated(clock)) => {
                 self.clock = Rc::new(clock);
             }
+            AppMsg::ServerMessage(ServerMessage::ClockSourceChanged(clock_source)) => {
+                self.clock_source = clock_source;
+            }
             AppMsg::ServerMessage(ServerMessage::ButtonStatesUpdated(updates)) => {
                 for (location, coords, state) in updates {
                     let grid = self.button_states.get_mut(&location).unwrap();
@@ -189,6 +204,11 @@ impl Component for App {
                     self.fixture_params.insert(fixture_id, fixture_params);
                 }
             }
+            AppMsg::ServerMessage(ServerMessage::FixtureParamsRemoved(fixture_ids)) => {
+                for fixture_id in fixture_ids {
+                    self.fixture_params.remove(&fixture_id);
+                }
+            }
             AppMsg::ServerMessage(ServerMessage::FixtureGroupStatesUpdated(updates)) => {
                 for (fixture_group_id, fixture_group_state) in updates {
                     if let Some(fixture_group_id) = fixture_group_id {
@@ -199,9 +219,26 @@ impl Component for App {
                     }
                 }
             }
+            AppMsg::ServerMessage(ServerMessage::PaletteUpdated(palette)) => {
+                self.palette = Rc::new(palette);
+            }
             AppMsg::ActivePageUpdated(page_type) => {
                 self.active_page = page_type;
             }
+            AppMsg::ShowActionTriggered(show_action) => {
+                let name = window()
+                    .prompt_with_message("Show name")
+                    .ok()
+                    .flatten()
+                    .filter(|name| !name.is_empty());
+
+                if let Some(name) = name {
+                    self.send_client_message(match show_action {
+                        ShowAction::Save => ClientMessage::SaveShow(name),
+                        ShowAction::Load => ClientMessage::LoadShow(name),
+                    });
+                }
+            }
             AppMsg::NoOp => {}
         };
         true
@@ -227,6 +264,13 @@ impl Component for App {
             }
         });
 
+        let link = self.link.to_owned();
+        let show_button_callback_fn = callback_fn(move |(show_action, action)| {
+            if action == ButtonAction::Press {
+                link.send_message(AppMsg::ShowActionTriggered(show_action))
+            }
+        });
+
         let link = self.link.to_owned();
 
         html! {
@@ -256,6 +300,21 @@ impl Component for App {
                         state={if self.active_page.is_preview_3d() {ButtonState::Active} else {ButtonState::Inactive}}
                         on_action={fader_button_callback_fn.clone()}
                     />
+                    <Button<ShowAction>
+                        id={ShowAction::Save}
+                        label={"Save Show"}
+                        state={ButtonState::Inactive}
+                        on_action={show_button_callback_fn.clone()}
+                    />
+                    <Button<ShowAction>
+                        id={ShowAction::Load}
+                        label={"Load Show"}
+                        state={ButtonState::Inactive}
+                        on_action={show_button_callback_fn}
+                    />
+                    <div class="clock-source">
+                        { self.clock_source.as_deref().unwrap_or("Tap tempo") }
+                    </div>
                 </div>
                 <Page active={true}>
                 {
@@ -287,6 +346,7 @@ impl Component for App {
                                 clock={self.clock.clone()}
                                 base_fixture_group_state={self.base_fixture_group_state.clone()}
                                 fixture_group_states={self.fixture_group_states.clone()}
+                                palette={self.palette.clone()}
                             />
                         }
                     }
@@ -227,6 +227,9 @@ extern "C" {
     #[wasm_bindgen(method, setter, js_namespace = BABYLON)]
     pub fn set_position(this: &Mesh, val: &Vector3);
 
+    #[wasm_bindgen(method, setter, js_namespace = BABYLON)]
+    pub fn set_rotation(this: &Mesh, val: &Vector3);
+
     #[wasm_bindgen(extends = TransformNode)]
     #[derive(Debug, Clone)]
     pub type AbstractMesh;
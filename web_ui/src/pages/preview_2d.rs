@@ -1,5 +1,4 @@
 use im_rc::HashMap;
-use itertools::Itertools;
 use yew::prelude::*;
 
 use roller_protocol::fixture::{FixtureId, FixtureParams, FixtureState};
@@ -71,19 +70,19 @@ impl PureComponent for PurePreview2dPage {
             .map(FixtureRef::from)
             .collect();
 
-        let sorted_rows: Vec<isize> = sorted_unique(
+        // Fixtures are laid out as seen from the front of the stage
+        let sorted_rows: Vec<f64> = sorted_unique(
             fixtures
                 .iter()
                 .filter_map(|fixture| fixture.params.location.as_ref())
-                .map(|location| location.y),
+                .map(|location| location.y()),
         );
 
-        let sorted_columns: Vec<isize> = sorted_unique(
+        let sorted_columns: Vec<f64> = sorted_unique(
             fixtures
                 .iter()
                 .filter_map(|fixture| fixture.params.location.as_ref())
-                .map(|location| location.x)
-                .unique(),
+                .map(|location| location.x()),
         );
 
         let fixture_grid: Vec<Vec<Vec<FixtureRef<'_>>>> = {
@@ -97,8 +96,8 @@ impl PureComponent for PurePreview2dPage {
 
             for fixture in fixtures.into_iter() {
                 if let Some(location) = fixture.params.location.as_ref() {
-                    let row_idx = find_index(&sorted_rows, &location.y).unwrap();
-                    let col_idx = find_index(&sorted_columns, &location.x).unwrap();
+                    let row_idx = find_index(&sorted_rows, &location.y()).unwrap();
+                    let col_idx = find_index(&sorted_columns, &location.x()).unwrap();
 
                     grid[row_idx][col_idx].push(fixture);
                 }
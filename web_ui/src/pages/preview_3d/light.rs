@@ -1,3 +1,7 @@
+use std::f64::consts::PI;
+
+use roller_protocol::fixture::FixtureOrientation;
+
 use crate::{
     console_log,
     js::babylon,
@@ -39,6 +43,7 @@ impl Light {
 pub struct CreateLightArgs<'a> {
     pub scene: &'a babylon::Scene,
     pub origin_position: Vector,
+    pub orientation: &'a FixtureOrientation,
 }
 pub fn create_light<'a>(args: CreateLightArgs<'a>) -> Light {
     let beam_angle = f64::to_radians(30.0);
@@ -60,17 +65,33 @@ pub fn create_light<'a>(args: CreateLightArgs<'a>) -> Light {
         },
         Some(&args.scene),
     );
-    cone_mesh.set_position(&babylon::Vector3::from(&args.origin_position));
+
+    // The cylinder's narrow end sits at the fixture with the beam pointing away
+    // from it, so the mesh is centred half a cone length along the beam
+    let (dx, dy, dz) = args.orientation.direction();
+    let origin = &args.origin_position;
+    cone_mesh.set_position(&babylon::Vector3::new(
+        origin.x + dx * cone_length / 2.0,
+        origin.y + dy * cone_length / 2.0,
+        origin.z + dz * cone_length / 2.0,
+    ));
+    // Unrotated the cone points down, so standing fixtures are flipped over
+    let flip = if args.orientation.is_hanging() {
+        0.0
+    } else {
+        PI
+    };
+    cone_mesh.set_rotation(&babylon::Vector3::new(
+        args.orientation.pitch().to_radians() + flip,
+        args.orientation.yaw().to_radians(),
+        0.0,
+    ));
     cone_mesh.set_material(&cone_material);
 
     let spot_light = babylon::SpotLight::new(
         "spot_light".to_string(),
-        babylon::Vector3::new(
-            args.origin_position.x,
-            args.origin_position.y + 25.5,
-            args.origin_position.z,
-        ),
-        babylon::Vector3::new(0.0, -1.0, 0.0),
+        babylon::Vector3::from(origin),
+        babylon::Vector3::new(dx, dy, dz),
         beam_angle,
         1.0,
         &args.scene,
@@ -6,6 +6,7 @@ use yew::prelude::*;
 
 use roller_protocol::{
     clock::Clock,
+    color::ColorPalette,
     fixture::{FixtureGroupId, FixtureId, FixtureLocation, FixtureParams, FixtureState},
     lighting_engine::{
         render::{render_fixture_states, FixtureStateRenderContext},
@@ -40,12 +41,27 @@ impl From<&Vector> for babylon::Vector3 {
     }
 }
 
+// Scene units per metre of fixture location
+const SCENE_UNITS_PER_METRE: f64 = 5.0;
+// Height of the room's floor in the scene, which fixture heights are measured from
+const FLOOR_Y: f64 = -2.0;
+
+// Where a fixture sits in the scene, with the rig centred across the room
+fn scene_position(location: &FixtureLocation, rig_centre_x: f64) -> Vector {
+    Vector::new(
+        (location.x() - rig_centre_x) * SCENE_UNITS_PER_METRE,
+        location.y() * SCENE_UNITS_PER_METRE + FLOOR_Y,
+        location.z() * SCENE_UNITS_PER_METRE,
+    )
+}
+
 #[derive(Debug, Properties, Clone, PartialEq)]
 pub struct Preview3dProps {
     pub fixture_params: HashMap<FixtureId, FixtureParams>,
     pub clock: Rc<Clock>,
     pub base_fixture_group_state: Rc<FixtureGroupState>,
     pub fixture_group_states: HashMap<FixtureGroupId, FixtureGroupState>,
+    pub palette: Rc<ColorPalette>,
 }
 
 #[derive(Debug)]
@@ -110,6 +126,7 @@ impl Component for Preview3dPage {
                             clock_snapshot: self.props.clock.snapshot(),
                             // TODO
                             master_dimmer: 1.0,
+                            palette: &self.props.palette,
                         },
                         &fixture_params,
                     );
@@ -230,13 +247,22 @@ impl Component for Preview3dPage {
                 height: 30.0,
             });
 
+            let (min_x, max_x) = positioned_fixtures.iter().fold(
+                (f64::INFINITY, f64::NEG_INFINITY),
+                |(min_x, max_x), (_, location)| (min_x.min(location.x()), max_x.max(location.x())),
+            );
+            let rig_centre_x = if min_x <= max_x {
+                (min_x + max_x) / 2.0
+            } else {
+                0.0
+            };
+
             let mut lights = HashMap::new();
             for (id, location) in positioned_fixtures {
-                let x = location.x as f64;
-                let y = location.y as f64;
                 let light = light::create_light(light::CreateLightArgs {
                     scene: &scene,
-                    origin_position: Vector::new(x * 2.0 - 50.0, 15.0, y * 2.0 - 50.0),
+                    origin_position: scene_position(&location, rig_centre_x),
+                    orientation: &location.orientation,
                 });
                 lights.insert(id, light);
             }